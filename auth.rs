@@ -1,26 +1,91 @@
 //! This is a simplest possible authentication module.
 //! The password is stored directly in the program as a
 //! plain text constant.
+use std::io::IsTerminal;
+use std::process::{Command, Stdio};
+
+use crate::ui;
 
 /// Stores the password for working with the program.
 const SECRET: &str = "password";
 
+/// Returns `true` if the password matches the stored SECRET
+/// value.
+pub fn verify(password: &str) -> bool {
+    password == SECRET
+}
+
 /// Prompts the user for a password and returns `true` if
 /// it matches the stored SECRET value.
 /// To exit the function, enter `x`, and it will return
 /// `false`.
 pub fn authorize() -> bool {
-    let mut password = String::new();
+    authorize_with(read_password)
+}
+
+/// Prompts for a password the same way as `authorize`,
+/// reading each attempt with `read`.
+fn authorize_with(mut read: impl FnMut() -> String) -> bool {
+    let mut password;
     loop {
-        println!("Enter password, or x to escape:");
-        password.clear();
-        std::io::stdin().read_line(&mut password).unwrap();
-        password = password.trim().to_string();
+        ui::prompt("Enter password, or x to escape:");
+        password = read();
         if password == "x" {
             return false;
-        } else if password == SECRET {
+        } else if verify(&password) {
             break;
         }
     }
     true
 }
+
+/// Reads a password from the standard input. When it is a
+/// terminal, echoing of the typed characters is turned off
+/// with `stty` while reading, so the password is not shown
+/// on screen. Otherwise, e.g. when the input is piped, the
+/// line is read as is.
+fn read_password() -> String {
+    if !std::io::stdin().is_terminal() {
+        return ui::read_trimmed_line();
+    }
+    let hidden = set_echo(false);
+    let password = ui::read_trimmed_line();
+    if hidden {
+        set_echo(true);
+        println!();
+    }
+    password
+}
+
+/// Turns echoing of the terminal input on or off. Returns
+/// `true` on success.
+fn set_echo(on: bool) -> bool {
+    Command::new("stty")
+        .arg(if on { "echo" } else { "-echo" })
+        .stdin(Stdio::inherit())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_compares_with_the_secret() {
+        assert!(verify(SECRET));
+        assert!(!verify(""));
+        assert!(!verify("Password"));
+        assert!(!verify(" password"));
+    }
+
+    #[test]
+    fn authorize_retries_until_the_password_or_x() {
+        let mut attempts = vec!["password", "wrong"];
+        assert!(authorize_with(|| attempts.pop().unwrap().to_string()));
+        assert!(attempts.is_empty());
+        let mut attempts = vec!["x", "wrong"];
+        assert!(!authorize_with(|| attempts.pop().unwrap().to_string()));
+    }
+}
@@ -0,0 +1,77 @@
+//! This module contains helpers for working with
+//! timestamps stored as seconds since the Unix epoch.
+
+/// Number of seconds in a day.
+pub const SECS_PER_DAY: u64 = 86_400;
+
+/// Converts a number of days since the Unix epoch into a
+/// civil `(year, month, day)` date of the proleptic
+/// Gregorian calendar.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a civil date of the proleptic Gregorian
+/// calendar into a number of days since the Unix epoch. This
+/// is the inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the ISO 8601 `(year, week)` of a number of days
+/// since the Unix epoch. Weeks start on Monday, and the first
+/// week of a year is the one containing its first Thursday,
+/// so the first days of January may belong to the last week
+/// of the previous year and vice versa.
+pub fn iso_week(days: i64) -> (i64, u32) {
+    // 1970-01-01 was a Thursday, weekdays count from Monday
+    let weekday = (days + 3).rem_euclid(7);
+    let thursday = days - weekday + 3;
+    let (year, _, _) = civil_from_days(thursday);
+    let week = (thursday - days_from_civil(year, 1, 1)) / 7 + 1;
+    (year, week as u32)
+}
+
+/// Formats a timestamp as `YYYY-MM-DD HH:MM:SS` in the time
+/// zone `offset` minutes ahead of UTC, e.g. 120 for UTC+2 or
+/// -300 for UTC-5.
+pub fn format_timestamp(ts: u64, offset: i32) -> String {
+    let local = ts as i64 + offset as i64 * 60;
+    let (year, month, day) = civil_from_days(local.div_euclid(SECS_PER_DAY as i64));
+    let secs = local.rem_euclid(SECS_PER_DAY as i64);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_timestamps_in_the_time_zone() {
+        assert_eq!(format_timestamp(1_767_571_200, 0), "2026-01-05 00:00:00");
+        assert_eq!(format_timestamp(1_767_571_200, 120), "2026-01-05 02:00:00");
+        assert_eq!(format_timestamp(1_767_571_200 + 3_661, -300), "2026-01-04 20:01:01");
+    }
+}
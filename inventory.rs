@@ -1,15 +1,43 @@
 //! This is a core module which implements the system
 //! functionality.
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::{self, BufRead, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::datetime;
+use crate::json::{self, Value};
+use crate::money::Cents;
+use crate::settings::{CostMethod, ReportFormat, Settings};
+use crate::ui;
+
+/// Name of the file the inventory is autosaved to.
+pub const AUTOSAVE_FILE: &str = "autosave.json";
 
 /// Stores the entire Inventory Management System state in
 /// RAM. It is reset every time the program is restarted.
-/// Contains three vectors of products, sale and purchase
-/// transactions.
+/// Contains vectors of products, deleted products kept in
+/// the trash for restoring, sale, purchase, write-off and
+/// transfer transactions, product bundles, stock
+/// reservations, the counters the transaction IDs are
+/// assigned from, the number of changes since the last
+/// autosave, the revenue from the sales recorded since the
+/// program started, and the system settings.
 pub struct Inventory {
     products: Vec<Product>,
+    trash: Vec<Product>,
+    bundles: Vec<Bundle>,
     sale_txs: Vec<SaleTx>,
     purchase_txs: Vec<PurchaseTx>,
+    write_off_txs: Vec<WriteOffTx>,
+    transfer_txs: Vec<TransferTx>,
+    reservations: Vec<Reservation>,
+    next_sale_id: u64,
+    next_purchase_id: u64,
+    unsaved_changes: u64,
+    session_revenue: Cents,
+    settings: Settings,
 }
 
 /// Structure for a product that includes information such
@@ -17,44 +45,172 @@ pub struct Inventory {
 /// - `name`: Product name (string)
 /// - `description`: Product description (string)
 /// - `quantity`: Quantity in stock (unsigned integer)
-/// - `sale_price`: Sale price per unit (floating point 
-/// number)
-/// - `purchase_prices`: Vector of quantity and purchase
-/// price per unit (tuple of unsigned integer and floating
-/// point number)
+/// - `sale_price`: Sale price per unit (amount in cents)
+/// - `purchase_prices`: Vector of the purchase lots (see
+///   `Lot`)
+/// - `backordered`: Quantity sold but not yet in stock
+///   (unsigned integer)
+/// - `moq`: Minimum order quantity; restocked quantities
+///   must be a multiple of it (unsigned integer)
+/// - `lead_time_days`: Number of days it takes for a
+///   reorder to arrive (unsigned integer)
+/// - `category`: Product category, empty if not set
+///   (string)
+/// - `reorder_level`: Quantity in stock at or below which
+///   the product should be reordered (unsigned integer)
+/// - `tags`: Lowercase tags for filtering products, without
+///   duplicates (vector of strings)
+/// - `price_updated_at`: Time the sale price was last set in
+///   seconds since the Unix epoch (unsigned integer)
+/// - `price_history`: Vector of the times the sale price was
+///   set and the prices, from the oldest (tuple of unsigned
+///   integer and amount in cents)
+/// - `notes`: Freeform notes, one timestamped note per line
+///   (string)
+/// - `profit_target`: Realized profit the product is expected
+///   to reach, if set (optional amount in cents)
+/// - `image_url`: Link to an image of the product for online
+///   catalogs, if set (optional string)
+/// - `realized_profit`: Profit from all sales of the
+///   product, updated on each sale (amount in cents)
 #[derive(Debug, Clone)]
 struct Product {
     name: String,
     description: String,
     quantity: u64,
-    sale_price: f64,
-    purchase_prices: Vec<(u64, f64)>,
+    sale_price: Cents,
+    purchase_prices: Vec<Lot>,
+    backordered: u64,
+    moq: u64,
+    lead_time_days: u64,
+    category: String,
+    reorder_level: u64,
+    tags: Vec<String>,
+    price_updated_at: u64,
+    price_history: Vec<(u64, Cents)>,
+    notes: String,
+    profit_target: Option<Cents>,
+    image_url: Option<String>,
+    realized_profit: Cents,
 }
 
+/// Purchase lot of a product: the quantity purchased, the
+/// purchase price per unit, the supplier, empty if not known,
+/// and the ID of the first sale the lot can be sold by, i.e.
+/// the next sale ID when it was purchased (tuple of unsigned
+/// integer, amount in cents, string and unsigned integer).
+/// Sales don't remove units from the lots; the units sold
+/// from each lot are worked out by `allocate_lots`.
+type Lot = (u64, Cents, String, u64);
+
 /// Structure for recording sales information:
+/// - `id`: Unique ID of the sale, assigned when it is
+///   recorded (unsigned integer)
 /// - `product_name`: Name of the sold product (string)
 /// - `quantity`: Quantity of goods sold (unsigned integer)
-/// - `sale_price`: Sale price per unit (floating point
-/// number)
+/// - `free`: Units of the quantity given free under a
+///   promotion, which are not billed (unsigned integer)
+/// - `sale_price`: Sale price per unit (amount in cents)
+/// - `timestamp`: Time of the sale in seconds since the
+///   Unix epoch (unsigned integer)
+/// - `note`: Reason or remark for the sale, e.g. a gift, if
+///   entered (optional string)
 #[derive(Debug, Clone)]
 struct SaleTx {
+    id: u64,
     product_name: String,
     quantity: u64,
-    sale_price: f64,
+    free: u64,
+    sale_price: Cents,
+    timestamp: u64,
+    note: Option<String>,
 }
 
 /// Structure for recording purchase information:
+/// - `id`: Unique ID of the purchase, assigned when it is
+///   recorded (unsigned integer)
 /// - `product_name`: Name of the purchased product
-/// (string)
+///   (string)
 /// - `quantity`: Quantity of purchased products (unsigned
-/// integer)
-/// - `purchase_price`: Purchase price per unit (floating
-/// point number)
+///   integer)
+/// - `purchase_price`: Purchase price per unit (amount in
+///   cents)
+/// - `supplier`: Name of the supplier, empty if not known
+///   (string)
+/// - `timestamp`: Time of the purchase in seconds since the
+///   Unix epoch (unsigned integer)
 #[derive(Debug, Clone)]
 struct PurchaseTx {
+    id: u64,
+    product_name: String,
+    quantity: u64,
+    purchase_price: Cents,
+    supplier: String,
+    timestamp: u64,
+}
+
+/// Structure for recording stock written off without a
+/// sale, e.g. spoiled perishables:
+/// - `product_name`: Name of the product written off
+///   (string)
+/// - `quantity`: Quantity written off (unsigned integer)
+/// - `cost`: Purchase cost of the units written off
+///   (amount in cents)
+/// - `reason`: Reason for the write-off (string)
+/// - `timestamp`: Time of the write-off in seconds since
+///   the Unix epoch (unsigned integer)
+#[derive(Debug, Clone)]
+struct WriteOffTx {
+    product_name: String,
+    quantity: u64,
+    cost: Cents,
+    reason: String,
+    timestamp: u64,
+}
+
+/// Structure for recording stock moved from one product to
+/// another, e.g. when repackaging:
+/// - `from`: Name of the product the units were taken from
+///   (string)
+/// - `to`: Name of the product the units were added to
+///   (string)
+/// - `quantity`: Quantity transferred (unsigned integer)
+/// - `timestamp`: Time of the transfer in seconds since the
+///   Unix epoch (unsigned integer)
+#[derive(Debug, Clone)]
+struct TransferTx {
+    from: String,
+    to: String,
+    quantity: u64,
+    timestamp: u64,
+}
+
+/// Structure for units of a product held for a customer
+/// until they expire, during which they cannot be sold to
+/// others:
+/// - `product_name`: Name of the reserved product (string)
+/// - `quantity`: Quantity reserved (unsigned integer)
+/// - `expires_at`: Time the reservation expires in seconds
+///   since the Unix epoch (unsigned integer)
+#[derive(Debug, Clone)]
+struct Reservation {
     product_name: String,
     quantity: u64,
-    purchase_price: f64,
+    expires_at: u64,
+}
+
+/// Structure for a kit sold as a single item and made of
+/// component products:
+/// - `name`: Bundle name (string)
+/// - `components`: Vector of component product names and
+///   quantities per bundle (tuple of string and unsigned
+///   integer)
+/// - `sale_price`: Sale price per bundle (amount in cents)
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    name: String,
+    components: Vec<(String, u64)>,
+    sale_price: Cents,
 }
 
 impl Inventory {
@@ -62,644 +218,6489 @@ impl Inventory {
     pub fn new() -> Self {
         Inventory {
             products: Vec::new(),
+            trash: Vec::new(),
+            bundles: Vec::new(),
             sale_txs: Vec::new(),
             purchase_txs: Vec::new(),
+            write_off_txs: Vec::new(),
+            transfer_txs: Vec::new(),
+            reservations: Vec::new(),
+            next_sale_id: 1,
+            next_purchase_id: 1,
+            unsaved_changes: 0,
+            session_revenue: Cents::ZERO,
+            settings: Settings::default(),
         }
     }
-}
 
-impl Product {
-    fn new(name: String, description: String, quantity: u64, sale_price: f64, purchase_price: f64) -> Product {
-        Product {
-            name,
-            description,
-            quantity,
-            sale_price,
-            purchase_prices: vec![(quantity, purchase_price)],
-        }
+    /// Counts a change to the products or transactions
+    /// towards the next autosave.
+    fn mark_changed(&mut self) {
+        self.unsaved_changes += 1;
     }
-}
 
-impl SaleTx {
-    fn new(product_name: String, quantity: u64, sale_price: f64) -> SaleTx {
-        SaleTx {
-            product_name,
-            quantity,
-            sale_price,
+    /// Saves the inventory with `save` once the number of
+    /// changes since the last autosave reaches the autosave
+    /// interval of the settings, or on `exit` if there are
+    /// any. Nothing is saved when autosaving is disabled.
+    /// Returns whether the inventory was saved.
+    pub fn autosave<F>(&mut self, exit: bool, save: F) -> Result<bool, String>
+    where
+        F: FnOnce(&Inventory) -> Result<(), String>,
+    {
+        let interval = self.settings.autosave_interval;
+        if interval == 0 || self.unsaved_changes == 0 || (!exit && self.unsaved_changes < interval) {
+            return Ok(false);
         }
+        save(self)?;
+        self.unsaved_changes = 0;
+        Ok(true)
     }
-}
 
-impl PurchaseTx {
-    fn new(product_name: String, quantity: u64, purchase_price: f64) -> PurchaseTx {
-        PurchaseTx {
-            product_name,
-            quantity,
-            purchase_price,
-        }
+    /// Returns the system settings for editing.
+    pub fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
     }
-}
-
-trait InventoryManager {
-    fn add_new_product(&mut self, name: String, description: String, quantity: u64, sale_price: f64, purchase_price: f64);
-    fn add_same_product(&mut self, name: String, quantity: u64, purchase_price: f64) -> Result<(), String>;
-    fn edit_product(&mut self, new_product: Product) -> Result<(), String>;
-    fn delete_product(&mut self, product_name: &String);
-    fn get_product(&self, product_name: &String) -> Option<&Product>;
-    fn record_sale(&mut self, tx: SaleTx);
-    fn record_purchase(&mut self, tx: PurchaseTx);
-}
 
-impl InventoryManager for Inventory {
-    fn add_new_product(&mut self, name: String, description: String, quantity: u64, sale_price: f64, purchase_price: f64) {
-        let new_product = Product::new(
-            name,
-            description,
-            quantity,
-            sale_price,
-            purchase_price
-        );
-        self.products.push(new_product);
+    /// Imports sales from a CSV file with rows in the form
+    /// `product,quantity[,timestamp]`. Each row is applied as
+    /// a regular sale, decrementing the stock and recording
+    /// the transaction. Rows that are malformed or would
+    /// oversell are reported and skipped. Returns the number
+    /// of imported sales.
+    pub fn import_sales_csv(&mut self, path: &Path) -> Result<usize, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read file: {} ({})", path.display(), e))?;
+        let mut imported: usize = 0;
+        for (line, row) in parse_sales_csv(&content) {
+            let result = row.and_then(|(name, quantity, timestamp)| self.sell_product(&name, quantity, None, timestamp));
+            match result {
+                Ok(_) => imported += 1,
+                Err(e) => ui::print_warning(&format!("Line {} skipped: {}", line, e)),
+            }
+        }
+        Ok(imported)
     }
 
-    fn add_same_product(&mut self, name: String, quantity: u64, purchase_price: f64) -> Result<(), String> {
-        match self.products.iter_mut().find(|p| p.name == name) {
-            Some(product) => {
-                product.quantity += quantity;
-                for (q, p) in product.purchase_prices.iter_mut() {
-                    if *p == purchase_price {
-                        *q += quantity;
-                        return Ok(());
-                    }
+    /// Checks a CSV file of sales in the format of
+    /// `import_sales_csv` without importing it. Returns the
+    /// problem of each row that would be skipped on import,
+    /// i.e. malformed rows and rows that would oversell given
+    /// the rows before them, leaving the stock unchanged.
+    pub fn validate_sales_csv(&self, path: &Path) -> Result<Vec<String>, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read file: {} ({})", path.display(), e))?;
+        let mut remaining: HashMap<String, u64> = HashMap::new();
+        let mut problems = Vec::new();
+        for (line, row) in parse_sales_csv(&content) {
+            let result = row.and_then(|(name, quantity, _)| {
+                let product = self.get_product(&name).ok_or(format!("Unavailable product: {}", name))?;
+                safe_total(quantity, product.sale_price)?;
+                let available = remaining.entry(name.clone())
+                    .or_insert_with(|| self.unreserved_quantity(&name, now()).unwrap_or(0));
+                if quantity > *available && !self.settings.allow_backorder {
+                    return Err(format!("Insufficient stock: {} (requested {}, available {})", name, quantity, available));
                 }
-                product.purchase_prices.push((quantity, purchase_price));
+                *available = available.saturating_sub(quantity);
                 Ok(())
+            });
+            if let Err(e) = result {
+                problems.push(format!("Line {}: {}", line, e));
             }
-            None => Err(format!("Unavailable product: {}", name))
         }
+        Ok(problems)
     }
 
-    fn edit_product(&mut self, new_product: Product) -> Result<(), String> {
-        match self.products.iter_mut().find(|p| p.name == new_product.name) {
-            Some(product) => {
-                *product = new_product;
-                Ok(())
-            }
-            None => Err(format!("Unavailable product: {}", new_product.name))
+    /// Moves `qty` units of stock from one product to
+    /// another, e.g. when repackaging. The units are taken
+    /// from the purchase lots still in stock in the order of
+    /// the cost method and are added to the target product as
+    /// new lots with the same purchase prices, so the value of
+    /// the stock is kept. Reserved units cannot be transferred.
+    /// The transfer is recorded for the stock ledger.
+    pub fn transfer_stock(&mut self, from: &str, to: &str, qty: u64) -> Result<(), String> {
+        if from == to {
+            return Err(format!("Invalid transfer: {} to itself", from));
+        }
+        if qty == 0 {
+            return Err(format!("Invalid quantity: {}", qty));
         }
+        let from_index = self.products.iter().position(|p| p.name == from)
+            .ok_or(format!("Unavailable product: {}", from))?;
+        let to_index = self.products.iter().position(|p| p.name == to)
+            .ok_or(format!("Unavailable product: {}", to))?;
+
+        self.check_unreserved(from, qty)?;
+        let sold = self.lots_sold(&self.products[from_index]);
+        let method = self.settings.cost_method;
+        let source = &mut self.products[from_index];
+        let moved = source.take_from_lots(qty, &sold, method);
+
+        let first_sale_id = self.next_sale_id;
+        let target = &mut self.products[to_index];
+        let mut incoming = qty;
+        for (q, p, s) in moved {
+            let filled = q.min(target.backordered);
+            target.backordered -= filled;
+            incoming -= filled;
+            target.purchase_prices.push((q, p, s, first_sale_id));
+        }
+        target.quantity += incoming;
+        self.transfer_txs.push(TransferTx {
+            from: from.to_string(),
+            to: to.to_string(),
+            quantity: qty,
+            timestamp: now(),
+        });
+        self.refresh_profit(to);
+        self.mark_changed();
+        Ok(())
     }
 
-    fn delete_product(&mut self, product_name: &String) {
-        self.products.retain(|p| p.name != *product_name);
+    /// Sets the quantity in stock of the product, leaving the
+    /// rest of it, including the purchase lots, untouched. Used
+    /// by sales instead of replacing the whole product, so
+    /// that changes made to it in the meantime are kept. The
+    /// change is counted by the sale it is part of.
+    pub fn set_quantity(&mut self, name: &str, qty: u64) -> Result<(), String> {
+        let product = self.products.iter_mut().find(|p| p.name == name)
+            .ok_or(format!("Unavailable product: {}", name))?;
+        product.quantity = qty;
+        Ok(())
     }
 
-    fn get_product(&self, product_name: &String) -> Option<&Product> {
-        self.products.iter().find(|p| p.name == *product_name)
+    /// Moves the most recently deleted product with the name
+    /// back from the trash, unless another product with the
+    /// same name was added in the meantime. Its realized
+    /// profit is rebuilt from the sales history.
+    pub fn restore_product(&mut self, name: &str) -> Result<(), String> {
+        if self.get_product(name).is_some() {
+            return Err(format!("Product already exists: {}", name));
+        }
+        let i = self.trash.iter().rposition(|p| p.name == name)
+            .ok_or(format!("Unavailable deleted product: {}", name))?;
+        let product = self.trash.remove(i);
+        self.products.push(product);
+        self.recompute_profit();
+        self.mark_changed();
+        Ok(())
     }
 
-    fn record_sale(&mut self, tx: SaleTx) {
-        self.sale_txs.push(tx);
+    /// Attaches the note to the sale with the given ID.
+    pub fn set_sale_note(&mut self, id: u64, note: String) -> Result<(), String> {
+        let tx = self.sale_txs.iter_mut().find(|tx| tx.id == id)
+            .ok_or(format!("Unavailable sale: {}", id))?;
+        tx.note = Some(note);
+        self.mark_changed();
+        Ok(())
     }
 
-    fn record_purchase(&mut self, tx: PurchaseTx) {
-        self.purchase_txs.push(tx);
+    /// Deletes every product with less than `threshold` units
+    /// in stock, moving them to the trash, and returns their
+    /// names. The sales and purchases of the deleted products
+    /// are kept.
+    pub fn delete_below_quantity(&mut self, threshold: u64) -> Vec<String> {
+        let names: Vec<String> = self.products.iter()
+            .filter(|p| p.quantity < threshold)
+            .map(|p| p.name.clone())
+            .collect();
+        for name in names.iter() {
+            self.delete_product(name);
+        }
+        names
     }
-}
 
-/// Displays the Inventory Management submenu and prompts
-/// the user for an option number to continue. To return to
-/// the main menu, enter `x`.
-pub fn inventory_handler(inventory: &mut Inventory) {
-    let mut feature = String::new();
-    loop {
-        println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-        println!("Enter feature number to go to, or x to escape:");
-        println!("Get product       1");
-        println!("Edit product      2");
-        println!("Delete product    3");
-        feature.clear();
-        std::io::stdin().read_line(&mut feature).unwrap();
-        feature = feature.trim().to_string();
-        if feature == "x" {
-            return;
+    /// Holds `qty` units of the product in stock until
+    /// `expires_at`. Only units not reserved already can be
+    /// reserved.
+    pub fn reserve(&mut self, name: &str, qty: u64, expires_at: u64) -> Result<(), String> {
+        if qty == 0 {
+            return Err(format!("Invalid quantity: {}", qty));
         }
-        match &feature[..] {
-            "1" => get_handler(inventory),
-            "2" => edit_handler(inventory),
-            "3" => delete_handler(inventory),
-            _ => (),
+        let available = self.unreserved_quantity(name, now())
+            .ok_or(format!("Unavailable product: {}", name))?;
+        if qty > available {
+            return Err(format!("Insufficient stock: {} (requested {}, available {})", name, qty, available));
         }
+        self.reservations.push(Reservation {
+            product_name: name.to_string(),
+            quantity: qty,
+            expires_at,
+        });
+        self.mark_changed();
+        Ok(())
     }
-}
 
-/// Adds the product to the inventory according to the
-/// parameters provided by the user.
-fn add_handler(inventory: &mut Inventory) {
-    let mut feature = String::new();
+    /// Removes the reservations that expired by `now` and
+    /// returns how many were released.
+    pub fn release_expired(&mut self, now: u64) -> u64 {
+        let before = self.reservations.len();
+        self.reservations.retain(|r| r.expires_at > now);
+        let released = (before - self.reservations.len()) as u64;
+        if released > 0 {
+            self.mark_changed();
+        }
+        released
+    }
 
-    // name
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product name to purchase, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Returns the quantity in stock of the product less the
+    /// units of its reservations still active at `now`, or
+    /// `None` if the product does not exist.
+    fn unreserved_quantity(&self, name: &str, now: u64) -> Option<u64> {
+        let product = self.get_product(name)?;
+        Some(product.quantity.saturating_sub(self.reserved_quantity(name, now)))
     }
 
-    if let Some(p) = inventory.get_product(&feature) {
-        println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-        println!("Product already exists: {}", feature);
-        println!("Enter any value to add more of this product, or x to escape:");
-        feature.clear();
-        std::io::stdin().read_line(&mut feature).unwrap();
-        feature = feature.trim().to_string();
-        if feature == "x" {
-            return;
+    /// Returns an error unless `qty` units of the product can
+    /// be taken out of stock without touching the units held
+    /// by its active reservations. Checked by the operations
+    /// that take units out of stock: sales without backorders,
+    /// bundle sales, write-offs and transfers.
+    fn check_unreserved(&self, name: &str, qty: u64) -> Result<(), String> {
+        let available = self.unreserved_quantity(name, now()).unwrap_or(0);
+        if qty > available {
+            return Err(format!("Insufficient stock: {} (requested {}, available {})", name, qty, available));
         }
+        Ok(())
+    }
 
-        // same quantity
-        println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-        println!("Enter product quantity, or x to escape:");
-        feature.clear();
-        std::io::stdin().read_line(&mut feature).unwrap();
-        feature = feature.trim().to_string();
-        if feature == "x" {
-            return;
-        }
-        let quantity: u64;
-        match feature.parse() {
-            Ok(x) => {
-                if x == 0 {
-                    println!(">>> Invalid quantity: {}", x);
-                    return;
-                }
-                quantity = x;
-            },
-            Err(e) => {
-                println!(">>> Invalid quantity: {} ({})", feature, e);
-                return;
-            }
+    /// Returns the units of the product held by its
+    /// reservations still active at `now`.
+    fn reserved_quantity(&self, name: &str, now: u64) -> u64 {
+        self.reservations.iter()
+            .filter(|r| r.product_name == name && r.expires_at > now)
+            .map(|r| r.quantity)
+            .sum()
+    }
+
+    /// Removes `qty` units of spoiled or damaged stock of the
+    /// product without a sale. The units are taken from the
+    /// unsold units of the purchase lots in the order of the
+    /// cost method, and their cost is recorded as a write-off
+    /// and returned. Reserved units cannot be written off.
+    pub fn write_off(&mut self, name: &str, qty: u64, reason: String) -> Result<Cents, String> {
+        if qty == 0 {
+            return Err(format!("Invalid quantity: {}", qty));
         }
+        let index = self.products.iter().position(|p| p.name == name)
+            .ok_or(format!("Unavailable product: {}", name))?;
+        self.check_unreserved(name, qty)?;
+        let sold = self.lots_sold(&self.products[index]);
+        let method = self.settings.cost_method;
+        let product = &mut self.products[index];
+        let cost: Cents = product.take_from_lots(qty, &sold, method).iter().map(|(q, p, _)| p.times(*q)).sum();
+        self.write_off_txs.push(WriteOffTx {
+            product_name: product.name.clone(),
+            quantity: qty,
+            cost,
+            reason,
+            timestamp: now(),
+        });
+        self.mark_changed();
+        Ok(cost)
+    }
 
-        // same sale price
-        println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-        println!("Enter product purchase price, or x to escape:");
-        feature.clear();
-        std::io::stdin().read_line(&mut feature).unwrap();
-        feature = feature.trim().to_string();
-        if feature == "x" {
-            return;
+    /// Adds a bundle of existing products. Bundle names are
+    /// unique, and each component needs a quantity of at
+    /// least one.
+    pub fn add_bundle(&mut self, bundle: Bundle) -> Result<(), String> {
+        if self.bundles.iter().any(|b| b.name == bundle.name) {
+            return Err(format!("Bundle already exists: {}", bundle.name));
         }
-        let purchase_price: f64;
-        match feature.parse() {
-            Ok(x) => {
-                if x < 0.0 {
-                    println!(">>> Invalid sale price: {}", x);
-                    return;
-                }
-                purchase_price = x;
+        if bundle.components.is_empty() {
+            return Err(format!("Invalid bundle: {} (no components)", bundle.name));
+        }
+        for (name, quantity) in bundle.components.iter() {
+            if self.get_product(name).is_none() {
+                return Err(format!("Unavailable product: {}", name));
             }
-            Err(e) => {
-                println!(">>> Invalid sale price: {} ({})", feature, e);
-                return;
+            if *quantity == 0 {
+                return Err(format!("Invalid quantity: {}", quantity));
             }
         }
+        self.bundles.push(bundle);
+        self.mark_changed();
+        Ok(())
+    }
 
-        let tx = PurchaseTx::new(p.name.clone(), quantity, purchase_price);
-        match inventory.add_same_product(p.name.clone(), quantity, purchase_price) {
-            Ok(_) => {
-                println!(">>> Product added: {:?}; Total cost: {}", tx, tx.quantity as f64 * tx.purchase_price);
-                inventory.record_purchase(tx);
+    /// Sells `qty` units of the bundle by selling the
+    /// components it is made of. The bundle price is split
+    /// between the component sales in proportion to their
+    /// listed sale prices, rounded down to whole cents per
+    /// unit, and the last component takes the remainder so
+    /// that the sales add up to the bundle total. Nothing is
+    /// sold if any component lacks stock, regardless of the
+    /// backorder setting.
+    pub fn sell_bundle(&mut self, bundle: &Bundle, qty: u64) -> Result<(), String> {
+        if qty == 0 {
+            return Err(format!("Invalid quantity: {}", qty));
+        }
+        let total = safe_total(qty, bundle.sale_price)?;
+        // units needed of each product, with repeated components merged
+        let mut needed: Vec<(String, u64)> = Vec::new();
+        for (name, per_bundle) in bundle.components.iter() {
+            let units = per_bundle.checked_mul(qty)
+                .ok_or(format!("Quantity overflow: {} units of {}", qty, bundle.name))?;
+            match needed.iter_mut().find(|(n, _)| n == name) {
+                Some((_, q)) => *q = q.checked_add(units)
+                    .ok_or(format!("Quantity overflow: {} units of {}", qty, bundle.name))?,
+                None => needed.push((name.clone(), units)),
             }
-            Err(e) => println!(">>> {}", e)
         }
-        return;
+        let mut listed_total = Cents::ZERO;
+        let mut units_total = 0;
+        for (name, units) in needed.iter() {
+            let product = self.get_product(name).ok_or(format!("Unavailable product: {}", name))?;
+            self.check_unreserved(name, *units)?;
+            listed_total += product.sale_price.times(*units);
+            units_total += *units;
+        }
+        let timestamp = now();
+        let last = needed.len() - 1;
+        let mut remaining = total;
+        for (i, (name, units)) in needed.into_iter().enumerate() {
+            if i == last {
+                // the rest of the total, with the odd cents added
+                // to some of the units so that nothing is lost
+                let price = Cents(remaining.0 / units as i64);
+                let extra = (remaining.0 % units as i64) as u64;
+                if extra > 0 {
+                    self.sell_product(&name, extra, Some(price + Cents(1)), timestamp)?;
+                }
+                self.sell_product(&name, units - extra, Some(price), timestamp)?;
+                break;
+            }
+            let listed = self.get_product(&name).map(|p| p.sale_price.times(units)).unwrap_or(Cents::ZERO);
+            // share of the bundle total, in whole cents per unit
+            let (part, whole) = if listed_total > Cents::ZERO {
+                (listed.0 as i128, listed_total.0 as i128)
+            } else {
+                (units as i128, units_total as i128)
+            };
+            let price = Cents((total.0 as i128 * part / (whole * units as i128)) as i64);
+            self.sell_product(&name, units, Some(price), timestamp)?;
+            remaining -= price.times(units);
+        }
+        Ok(())
     }
 
-    let name = feature.clone();
+    /// Sells `quantity` units of the product, of which `free`
+    /// units are not billed, as a single sale. See
+    /// `sell_product`; `free` must not exceed the quantity.
+    fn sell_units(&mut self, product_name: &str, quantity: u64, free: u64, sale_price: Option<Cents>, timestamp: u64) -> Result<SaleTx, String> {
+        if quantity == 0 {
+            return Err(format!("Invalid quantity: {}", quantity));
+        }
+        let index = self.products.iter().position(|p| p.name == product_name)
+            .ok_or(format!("Unavailable product: {}", product_name))?;
+        if !self.settings.allow_backorder {
+            self.check_unreserved(product_name, quantity)?;
+        }
+        let product = &mut self.products[index];
+        let sale_price = sale_price.unwrap_or(product.sale_price);
+        safe_total(quantity - free, sale_price)?;
+        product.backordered += quantity.saturating_sub(product.quantity);
+        let remaining = product.quantity.saturating_sub(quantity);
+        let mut tx = SaleTx::new(product.name.clone(), quantity, sale_price, timestamp);
+        tx.free = free;
+        self.set_quantity(product_name, remaining)?;
+        let tx = self.record_sale(tx);
+        self.refresh_profit(product_name);
+        Ok(tx)
+    }
 
-    // description
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product description, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Sells `qty` units of the product at `sale_price` under a
+    /// buy `paid` get `free` promotion. All units are recorded
+    /// as a single sale with the free ones marked as such, so
+    /// the stock is reduced by all units while the revenue
+    /// only counts the billed ones. Nothing is sold if the
+    /// stock cannot cover all units.
+    fn sell_promo(&mut self, name: &str, qty: u64, sale_price: Cents, paid: u64, free: u64, timestamp: u64) -> Result<SaleTx, String> {
+        let (_, free_units) = apply_promo(qty, paid, free);
+        self.sell_units(name, qty, free_units, Some(sale_price), timestamp)
     }
-    let description = feature.clone();
 
-    // quantity
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product quantity, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Splits `qty` units out of the purchase lot at
+    /// `lot_index` of the product into a new lot with the
+    /// same purchase price and supplier, placed right after
+    /// it and holding the rest of the units, so the order
+    /// of the units for the cost method is kept.
+    pub fn split_lot(&mut self, name: &str, lot_index: usize, qty: u64) -> Result<(), String> {
+        let product = self.products.iter_mut().find(|p| p.name == name)
+            .ok_or(format!("Unavailable product: {}", name))?;
+        let lot = product.purchase_prices.get_mut(lot_index)
+            .ok_or(format!("Unavailable lot: {} of {}", lot_index + 1, name))?;
+        if qty == 0 || qty >= lot.0 {
+            return Err(format!("Invalid quantity: {} (must be between 1 and {})", qty, lot.0.saturating_sub(1)));
+        }
+        let rest = (lot.0 - qty, lot.1, lot.2.clone(), lot.3);
+        lot.0 = qty;
+        product.purchase_prices.insert(lot_index + 1, rest);
+        self.mark_changed();
+        Ok(())
     }
-    let quantity: u64;
-    match feature.parse() {
-        Ok(x) => {
-            if x == 0 {
-               println!(">>> Invalid quantity: {}", x);
-               return; 
+
+    /// Sets the sale price of every product below `floor` to
+    /// `floor` and above `ceil` to `ceil`, leaving the bounds
+    /// that are `None` open. Returns the number of products
+    /// whose price changed.
+    pub fn clamp_prices(&mut self, floor: Option<Cents>, ceil: Option<Cents>) -> usize {
+        let mut changed = 0;
+        for product in self.products.iter_mut() {
+            let mut price = product.sale_price;
+            if let Some(floor) = floor {
+                price = price.max(floor);
+            }
+            if let Some(ceil) = ceil {
+                price = price.min(ceil);
+            }
+            if price != product.sale_price {
+                product.sale_price = price;
+                product.record_price_change(now());
+                changed += 1;
             }
-            quantity = x;
         }
-        Err(e) => {
-            println!(">>> Invalid quantity: {} ({})", feature, e);
-            return;
+        if changed > 0 {
+            self.mark_changed();
         }
+        changed
     }
 
-    // sale price
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product sale price, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
-    }
-    let sale_price: f64;
-    match feature.parse() {
-        Ok(x) => {
-            if x < 0.0 {
-                println!(">>> Invalid sale price: {}", x);
-                return;
+    /// Checks the quantity in stock of every product against
+    /// its purchase lots. Since sales don't remove units from
+    /// the lots, the stock should equal the units in the lots
+    /// less the units sold from stock, i.e. all units sold
+    /// except the backordered ones. Mismatched quantities are
+    /// corrected, while products whose sales exceed their lots
+    /// are only flagged for manual review. Returns a message
+    /// for each correction or flag.
+    pub fn repair_quantities(&mut self) -> Vec<String> {
+        let mut messages = Vec::new();
+        for product in self.products.iter_mut() {
+            let lots_quantity: u64 = product.purchase_prices.iter().map(|(q, _, _, _)| q).sum();
+            let sold: u64 = self.sale_txs.iter()
+                .filter(|tx| tx.product_name == product.name)
+                .map(|tx| tx.quantity)
+                .sum();
+            let sold_from_stock = sold.saturating_sub(product.backordered);
+            match lots_quantity.checked_sub(sold_from_stock) {
+                Some(expected) if expected != product.quantity => {
+                    messages.push(format!("Quantity repaired: {} ({} -> {})", product.name, product.quantity, expected));
+                    product.quantity = expected;
+                }
+                Some(_) => (),
+                None => messages.push(format!(
+                    "Manual review needed: {} (sold {} from stock, {} in purchase lots)",
+                    product.name,
+                    sold_from_stock,
+                    lots_quantity)),
             }
-            sale_price = x;
         }
-        Err(e) => {
-            println!(">>> Invalid sale price: {} ({})", feature, e);
-            return;
+        if messages.iter().any(|m| m.starts_with("Quantity repaired")) {
+            self.mark_changed();
         }
+        messages
     }
 
-    // purchase price
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product purchase price, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Formats an amount of money for the reports according to
+    /// the settings.
+    fn money(&self, amount: Cents) -> String {
+        amount.format(self.settings.group_thousands)
     }
-    let purchase_price: f64;
-    match feature.parse() {
-        Ok(x) => {
-            if x < 0.0 {
-                println!(">>> Invalid purchase price: {}", x);
-                return;
-            }
-            purchase_price = x;
+
+    /// Formats a timestamp for the reports in the time zone
+    /// of the settings.
+    fn timestamp(&self, ts: u64) -> String {
+        datetime::format_timestamp(ts, self.settings.tz_offset_minutes)
+    }
+
+    /// Rebuilds the realized profit of every product from
+    /// the whole sales history, allocating the sales to the
+    /// purchase lots with `allocate_lots`.
+    pub fn recompute_profit(&mut self) {
+        let method = self.settings.cost_method;
+        let mut sales: HashMap<&str, Vec<&SaleTx>> = HashMap::new();
+        for tx in self.sale_txs.iter() {
+            sales.entry(tx.product_name.as_str()).or_default().push(tx);
         }
-        Err(e) => {
-            println!(">>> Invalid purchase price: {} ({})", feature, e);
-            return;
+        for product in self.products.iter_mut() {
+            let txs = sales.get(product.name.as_str()).map_or(&[][..], |t| &t[..]);
+            product.realized_profit = sale_profits_from_lots(&product.purchase_prices, txs, method).iter().copied().sum();
         }
     }
 
-    let tx = PurchaseTx::new(name.clone(), quantity, purchase_price);
-    inventory.add_new_product(name.clone(), description, quantity, sale_price, purchase_price);
-    println!(">>> Product added: {:?}; Total cost: {}", tx, tx.quantity as f64 * tx.purchase_price);
-    inventory.record_purchase(tx);
-}
+    /// Returns the units sold of each purchase lot of the
+    /// product, allocated with `allocate_lots`.
+    fn lots_sold(&self, product: &Product) -> Vec<u64> {
+        let txs: Vec<&SaleTx> = self.sale_txs.iter().filter(|tx| tx.product_name == product.name).collect();
+        allocate_lots(&product.purchase_prices, &txs, self.settings.cost_method).1
+    }
 
-/// Edits product information, particularly the description
-/// or sale price.
-fn edit_handler(inventory: &mut Inventory) {
-    let mut feature = String::new();
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product name to edit, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Returns the purchase price per unit averaged over the
+    /// units still in stock of each purchase lot, weighted by
+    /// their quantity and rounded to the cent, or `None` if
+    /// no purchased units are left. The units sold of each lot
+    /// follow the cost method.
+    fn stock_avg_cost(&self, product: &Product) -> Option<Cents> {
+        let sold = self.lots_sold(product);
+        let unsold = |i: usize| product.purchase_prices[i].0.saturating_sub(sold[i]);
+        let quantity: u64 = (0..sold.len()).map(unsold).sum();
+        if quantity == 0 {
+            return None;
+        }
+        let total: Cents = (0..sold.len()).map(|i| product.purchase_prices[i].1.times(unsold(i))).sum();
+        Some(total.per_unit(quantity))
     }
-    let product = match inventory.get_product(&feature) {
-        Some(p) => p,
-        None => {
-            println!(">>> Unavailable product: {}", feature);
-            return;
+
+    /// Rebuilds the realized profit of the product the same
+    /// way as `recompute_profit`, after a change to its sales
+    /// or purchase lots.
+    fn refresh_profit(&mut self, name: &str) {
+        let method = self.settings.cost_method;
+        let txs: Vec<&SaleTx> = self.sale_txs.iter().filter(|tx| tx.product_name == name).collect();
+        if let Some(product) = self.products.iter_mut().find(|p| p.name == name) {
+            product.realized_profit = sale_profits_from_lots(&product.purchase_prices, &txs, method).iter().copied().sum();
         }
-    };
+    }
 
-    let mut new_product = product.clone();
-    
-    // description
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Product being set: {:?}", new_product);
-    println!("Enter product description, or c to continue, or press x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Converts the products and transactions into a JSON
+    /// object. Settings are stored separately.
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("products".to_string(), Value::Array(self.products.iter().map(|p| p.to_json()).collect())),
+            ("trash".to_string(), Value::Array(self.trash.iter().map(|p| p.to_json()).collect())),
+            ("bundles".to_string(), Value::Array(self.bundles.iter().map(|b| b.to_json()).collect())),
+            ("sale_txs".to_string(), Value::Array(self.sale_txs.iter().map(|tx| tx.to_json()).collect())),
+            ("purchase_txs".to_string(), Value::Array(self.purchase_txs.iter().map(|tx| tx.to_json()).collect())),
+            ("write_off_txs".to_string(), Value::Array(self.write_off_txs.iter().map(|tx| tx.to_json()).collect())),
+            ("transfer_txs".to_string(), Value::Array(self.transfer_txs.iter().map(|tx| tx.to_json()).collect())),
+            ("reservations".to_string(), Value::Array(self.reservations.iter().map(|r| r.to_json()).collect())),
+            ("next_sale_id".to_string(), Value::Number(self.next_sale_id as f64)),
+            ("next_purchase_id".to_string(), Value::Number(self.next_purchase_id as f64)),
+        ])
+    }
+
+    /// Reads the products and transactions from a JSON
+    /// object, using the default settings. Transactions saved
+    /// without an ID by older versions are given new IDs.
+    fn from_json(value: &Value) -> Result<Inventory, String> {
+        let mut inventory = Inventory::new();
+        for product in array_field(value, "products")? {
+            inventory.products.push(Product::from_json(product)?);
+        }
+        if value.get("trash").is_some() {
+            for product in array_field(value, "trash")? {
+                inventory.trash.push(Product::from_json(product)?);
+            }
+        }
+        if value.get("bundles").is_some() {
+            for bundle in array_field(value, "bundles")? {
+                inventory.bundles.push(Bundle::from_json(bundle)?);
+            }
+        }
+        for tx in array_field(value, "sale_txs")? {
+            inventory.sale_txs.push(SaleTx::from_json(tx)?);
+        }
+        for tx in array_field(value, "purchase_txs")? {
+            inventory.purchase_txs.push(PurchaseTx::from_json(tx)?);
+        }
+        if value.get("write_off_txs").is_some() {
+            for tx in array_field(value, "write_off_txs")? {
+                inventory.write_off_txs.push(WriteOffTx::from_json(tx)?);
+            }
+        }
+        if value.get("transfer_txs").is_some() {
+            for tx in array_field(value, "transfer_txs")? {
+                inventory.transfer_txs.push(TransferTx::from_json(tx)?);
+            }
+        }
+        if value.get("reservations").is_some() {
+            for reservation in array_field(value, "reservations")? {
+                inventory.reservations.push(Reservation::from_json(reservation)?);
+            }
+        }
+        if value.get("next_sale_id").is_some() {
+            inventory.next_sale_id = u64_field(value, "next_sale_id")?;
+        }
+        if value.get("next_purchase_id").is_some() {
+            inventory.next_purchase_id = u64_field(value, "next_purchase_id")?;
+        }
+        let max_sale_id = inventory.sale_txs.iter().map(|tx| tx.id).max().unwrap_or(0);
+        inventory.next_sale_id = inventory.next_sale_id.max(max_sale_id + 1);
+        for tx in inventory.sale_txs.iter_mut().filter(|tx| tx.id == 0) {
+            tx.id = inventory.next_sale_id;
+            inventory.next_sale_id += 1;
+        }
+        let max_purchase_id = inventory.purchase_txs.iter().map(|tx| tx.id).max().unwrap_or(0);
+        inventory.next_purchase_id = inventory.next_purchase_id.max(max_purchase_id + 1);
+        for tx in inventory.purchase_txs.iter_mut().filter(|tx| tx.id == 0) {
+            tx.id = inventory.next_purchase_id;
+            inventory.next_purchase_id += 1;
+        }
+        inventory.recompute_profit();
+        Ok(inventory)
     }
-    if feature != "c" {
-        new_product.description = feature.clone();
+
+    /// Returns the complete record of the product, including
+    /// its purchase lots, as a JSON document, or `None` if the
+    /// product does not exist.
+    pub fn product_json(&self, name: &str) -> Option<String> {
+        self.get_product(name).map(|p| p.to_json().pretty())
     }
-        
-    // sale price
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Product being set: {:?}", new_product);
-    println!("Enter product sale price, or c to continue, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+
+    /// Returns the product catalog for an online storefront
+    /// as a JSON document: the name, description, category,
+    /// tags, sale price, image URL and whether it is in stock
+    /// of every product, including the products out of stock.
+    /// Costs, purchase lots and histories are left out.
+    pub fn catalog_json(&self) -> String {
+        Value::Array(self.products.iter().map(|p| p.catalog_json()).collect()).pretty()
     }
-    if feature != "c" {
-        match feature.parse() {
-            Ok(x) => {
-                if x < 0.0 {
-                    println!(">>> Invalid sale price: {}", x);
-                    return;
+
+    /// Saves the products and transactions to a JSON file. If
+    /// the file stays locked by another process after a few
+    /// attempts, the data is written to the same path with a
+    /// `.tmp` extension appended instead, so it is not lost,
+    /// and an error naming that file is returned.
+    pub fn save_to_file(&self, path: &Path) -> Result<(), String> {
+        let content = self.to_json().pretty() + "\n";
+        let error = match save_with_retry(|| std::fs::write(path, &content), SAVE_ATTEMPTS, SAVE_RETRY_DELAY) {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+        let message = format!("Unable to save file: {} ({})", path.display(), error);
+        if !is_lock_error(&error) {
+            return Err(message);
+        }
+        let mut fallback = path.as_os_str().to_owned();
+        fallback.push(".tmp");
+        let fallback = Path::new(&fallback);
+        match std::fs::write(fallback, &content) {
+            Ok(_) => Err(format!("{}; saved to {} instead", message, fallback.display())),
+            Err(e) => Err(format!("{}; unable to save {} ({})", message, fallback.display(), e)),
+        }
+    }
+
+    /// Loads the products and transactions from a JSON file
+    /// previously written by `save_to_file`.
+    pub fn load_from_file(path: &Path) -> Result<Inventory, String> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read file: {} ({})", path.display(), e))?;
+        json::parse(&content).and_then(|v| Inventory::from_json(&v))
+    }
+
+    /// Populates the inventory with the sample products,
+    /// purchases and sales of the demo mode, so that the
+    /// reports have data to show. The transactions are dated
+    /// from `DEMO_START`, so the data is the same on every run.
+    /// Seeding does not count towards the next autosave or the
+    /// revenue of the session.
+    pub fn seed_demo(&mut self) {
+        for (name, description, category, quantity, sale_price, purchase_price) in DEMO_PRODUCTS {
+            let mut tx = PurchaseTx::new(name.to_string(), quantity, Cents(purchase_price), DEMO_SUPPLIER.to_string());
+            tx.timestamp = DEMO_START;
+            self.add_new_product(name.to_string(), description.to_string(), quantity, Cents(sale_price), Cents(purchase_price), DEMO_SUPPLIER.to_string());
+            if let Some(product) = self.products.iter_mut().find(|p| p.name == name) {
+                product.category = category.to_string();
+            }
+            self.record_purchase(tx);
+        }
+        for (name, quantity, purchase_price, day) in DEMO_RESTOCKS {
+            let mut tx = PurchaseTx::new(name.to_string(), quantity, Cents(purchase_price), DEMO_SUPPLIER.to_string());
+            tx.timestamp = DEMO_START + day * datetime::SECS_PER_DAY;
+            match self.add_same_product(name.to_string(), quantity, Cents(purchase_price), DEMO_SUPPLIER.to_string()) {
+                Ok(_) => {
+                    self.record_purchase(tx);
                 }
-                new_product.sale_price = x;
-            },
-            Err(e) => {
-                println!(">>> Invalid sale price: {} ({})", feature, e);
-                return;
+                Err(e) => ui::print_warning(&e),
+            }
+        }
+        for (name, quantity, day) in DEMO_SALES {
+            if let Err(e) = self.sell_product(name, quantity, None, DEMO_START + day * datetime::SECS_PER_DAY) {
+                ui::print_warning(&e);
             }
         }
+        self.unsaved_changes = 0;
+        self.session_revenue = Cents::ZERO;
     }
+}
 
-    match inventory.edit_product(new_product.clone()) {
-        Ok(_) => println!(">>> Product edited: {:?}", new_product),
-        Err(e) => println!(">>> {}", e)
-    }
+/// Time of the first transaction of the demo mode,
+/// 2026-01-05 00:00 UTC, in seconds since the Unix epoch.
+const DEMO_START: u64 = 1_767_571_200;
+/// Supplier of the purchases of the demo mode.
+const DEMO_SUPPLIER: &str = "Demo Farm";
+/// Products added by the demo mode: name, description,
+/// category, quantity, sale price and purchase price in
+/// cents.
+const DEMO_PRODUCTS: [(&str, &str, &str, u64, i64, i64); 5] = [
+    ("Potato", "Made in Ukraine", "Vegetables", 100, 150, 120),
+    ("Carrot", "Fresh and crunchy", "Vegetables", 80, 90, 60),
+    ("Onion", "Yellow onion", "Vegetables", 60, 110, 70),
+    ("Apple", "Golden Delicious", "Fruits", 50, 250, 180),
+    ("Honey", "Sunflower honey, 500 g", "Pantry", 12, 899, 650),
+];
+/// Restocks of the demo mode: product name, quantity,
+/// purchase price in cents and day after `DEMO_START`.
+const DEMO_RESTOCKS: [(&str, u64, i64, u64); 2] = [
+    ("Potato", 50, 130, 7),
+    ("Apple", 30, 170, 10),
+];
+/// Sales of the demo mode at the listed sale prices:
+/// product name, quantity and day after `DEMO_START`.
+const DEMO_SALES: [(&str, u64, u64); 8] = [
+    ("Potato", 20, 1),
+    ("Carrot", 15, 2),
+    ("Apple", 10, 3),
+    ("Honey", 2, 4),
+    ("Potato", 35, 8),
+    ("Onion", 12, 9),
+    ("Apple", 25, 11),
+    ("Honey", 3, 12),
+];
+
+/// Checks that the content of a backup file can be loaded,
+/// and returns a summary of its products and transactions,
+/// or the error that prevents loading it.
+pub fn validate_backup(content: &str) -> Result<String, String> {
+    let inventory = json::parse(content).and_then(|v| Inventory::from_json(&v))?;
+    Ok(format!(
+        "OK: {} products, {} sales, {} purchases",
+        inventory.products.len(),
+        inventory.sale_txs.len(),
+        inventory.purchase_txs.len()))
 }
 
-/// Removes a product from the system.
-fn delete_handler(inventory: &mut Inventory) {
-    let mut feature = String::new();
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product name to delete, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+/// Product name, quantity and timestamp of a sale parsed
+/// from a row of a sales CSV file, or the error of the row.
+type SalesRow = Result<(String, u64, u64), String>;
+
+/// Parses the rows of a sales CSV file in the form
+/// `product,quantity[,timestamp]` into the product name,
+/// quantity and timestamp of each sale, or the error of the
+/// row, with the line number. Empty lines and the header row,
+/// if it is the first non-empty line, are skipped, and rows
+/// without a timestamp are dated now.
+fn parse_sales_csv(content: &str) -> Vec<(usize, SalesRow)> {
+    let mut rows = Vec::new();
+    let mut first = true;
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if std::mem::take(&mut first) && fields[0].eq_ignore_ascii_case("product") {
+            continue;
+        }
+        rows.push((i + 1, parse_sales_row(line, &fields)));
     }
-    inventory.delete_product(&feature);
-    println!(">>> Product deleted if existed: {}", feature);
+    rows
 }
 
-/// Displays information about the product.
-fn get_handler(inventory: &Inventory) {
-    let mut feature = String::new();
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product name to get information about, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
-    }
-    match inventory.get_product(&feature) {
-        Some(product) => {
-            println!(">>> Product information");
-            println!(">>> Name: {}", product.name);
-            println!(">>> Description: {}", product.description);
-            println!(">>> Quantity in stock: {}", product.quantity);
-            println!(">>> Sale price: {}", product.sale_price);
-            println!(">>> Purchase quantity and prices: {:?}\n", product.purchase_prices);
-        },
-        None => println!(">>> Unavailable product: {}", feature)
+/// Parses the fields of a row of a sales CSV file into the
+/// product name, quantity and timestamp of the sale.
+fn parse_sales_row(line: &str, fields: &[&str]) -> SalesRow {
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err(format!("Invalid row: {}", line));
     }
+    let quantity: u64 = fields[1].parse()
+        .map_err(|e| format!("Invalid quantity: {} ({})", fields[1], e))?;
+    let timestamp: u64 = match fields.get(2) {
+        Some(ts) => ts.parse().map_err(|e| format!("Invalid timestamp: {} ({})", ts, e))?,
+        None => now(),
+    };
+    Ok((fields[0].to_string(), quantity, timestamp))
 }
 
-/// Allows the user to sell products available in the system.
-pub fn sales_handler(inventory: &mut Inventory) {
-    let mut feature = String::new();
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Enter product name to sell, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+/// Number of attempts to write a file locked by another
+/// process before giving up.
+const SAVE_ATTEMPTS: u32 = 3;
+/// Time waited between the attempts to write a locked file.
+const SAVE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Returns `true` if the error means that the file is
+/// locked or not writable at the moment, e.g. open in
+/// another program, so that writing it again may succeed.
+fn is_lock_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ResourceBusy | io::ErrorKind::WouldBlock)
+}
+
+/// Calls `write` up to `attempts` times, waiting `delay`
+/// between the attempts, for as long as it fails because the
+/// file is locked. Returns the result of the last attempt.
+fn save_with_retry<F>(mut write: F, attempts: u32, delay: Duration) -> io::Result<()>
+where
+    F: FnMut() -> io::Result<()>,
+{
+    let mut attempt = 1;
+    loop {
+        match write() {
+            Err(e) if is_lock_error(&e) && attempt < attempts => {
+                attempt += 1;
+                std::thread::sleep(delay);
+            }
+            result => return result,
+        }
     }
-    let product = match inventory.get_product(&feature) {
-        Some(p) => p,
-        None => {
-            println!(">>> Unavailavle product: {}", feature);
-            return;
+}
+
+/// Returns the member of a JSON object with the given key,
+/// or an error if it is missing.
+fn field<'a>(value: &'a Value, key: &str) -> Result<&'a Value, String> {
+    value.get(key).ok_or(format!("Missing field: {}", key))
+}
+
+/// Returns the string member of a JSON object.
+fn str_field(value: &Value, key: &str) -> Result<String, String> {
+    field(value, key)?.as_str().map(|s| s.to_string()).ok_or(format!("Invalid field: {}", key))
+}
+
+/// Returns the unsigned integer member of a JSON object.
+fn u64_field(value: &Value, key: &str) -> Result<u64, String> {
+    field(value, key)?.as_u64().ok_or(format!("Invalid field: {}", key))
+}
+
+/// Returns the number member of a JSON object.
+fn f64_field(value: &Value, key: &str) -> Result<f64, String> {
+    field(value, key)?.as_f64().ok_or(format!("Invalid field: {}", key))
+}
+
+/// Returns the amount of money member of a JSON object,
+/// stored as a number of dollars.
+fn cents_field(value: &Value, key: &str) -> Result<Cents, String> {
+    f64_field(value, key).and_then(|x| Cents::from_dollars(x).ok_or(format!("Invalid field: {}", key)))
+}
+
+/// Returns the `id` member of a transaction JSON object, or
+/// 0 if it is missing, meaning that no ID is assigned yet.
+fn optional_id(value: &Value) -> Result<u64, String> {
+    match value.get("id") {
+        Some(_) => u64_field(value, "id"),
+        None => Ok(0),
+    }
+}
+
+/// Returns the array member of a JSON object.
+fn array_field<'a>(value: &'a Value, key: &str) -> Result<&'a Vec<Value>, String> {
+    field(value, key)?.as_array().ok_or(format!("Invalid field: {}", key))
+}
+
+impl Product {
+    fn new(name: String, description: String, quantity: u64, sale_price: Cents, purchase_price: Cents, supplier: String, first_sale_id: u64) -> Product {
+        let timestamp = now();
+        Product {
+            name,
+            description,
+            quantity,
+            sale_price,
+            purchase_prices: vec![(quantity, purchase_price, supplier, first_sale_id)],
+            backordered: 0,
+            moq: 1,
+            lead_time_days: 0,
+            category: String::new(),
+            reorder_level: 0,
+            tags: Vec::new(),
+            price_updated_at: timestamp,
+            price_history: vec![(timestamp, sale_price)],
+            notes: String::new(),
+            profit_target: None,
+            image_url: None,
+            realized_profit: Cents::ZERO,
         }
-    };
+    }
 
-    let mut new_product = product.clone();
-    
-    // quantity
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Product being sold: {:?}", new_product);
-    println!("Enter product quantity, or x to escape:");
-    feature.clear();
-    std::io::stdin().read_line(&mut feature).unwrap();
-    feature = feature.trim().to_string();
-    if feature == "x" {
-        return;
+    /// Records that the sale price was set to its current
+    /// value at `timestamp`.
+    fn record_price_change(&mut self, timestamp: u64) {
+        self.price_updated_at = timestamp;
+        self.price_history.push((timestamp, self.sale_price));
     }
-    let quantity: u64;
-    match feature.parse() {
-        Ok(x) => quantity = x,
-        Err(e) => { println!(">>> Invalid quantity: {} ({})", feature, e); return; }
+
+    /// Removes `qty` units from the stock, taking them from the
+    /// unsold units of the purchase lots, given the units sold
+    /// of each lot, in the order the cost method sells them:
+    /// oldest lots first for FIFO and newest first for LIFO.
+    /// Returns the removed quantity, purchase price and
+    /// supplier of each lot. The quantity must not exceed the
+    /// stock.
+    fn take_from_lots(&mut self, qty: u64, sold: &[u64], method: CostMethod) -> Vec<(u64, Cents, String)> {
+        let mut order: Vec<usize> = (0..self.purchase_prices.len()).collect();
+        order.sort_by_key(|i| self.purchase_prices[*i].3);
+        if method == CostMethod::Lifo {
+            order.reverse();
+        }
+        let mut remaining = qty;
+        let mut taken: Vec<(u64, Cents, String)> = Vec::new();
+        for i in order {
+            if remaining == 0 {
+                break;
+            }
+            let (q, p, s, _) = &mut self.purchase_prices[i];
+            let current_quantity = remaining.min(q.saturating_sub(sold[i]));
+            if current_quantity > 0 {
+                *q -= current_quantity;
+                remaining -= current_quantity;
+                taken.push((current_quantity, *p, s.clone()));
+            }
+        }
+        self.purchase_prices.retain(|(q, _, _, _)| *q > 0);
+        self.quantity -= qty;
+        taken
     }
-    if quantity > new_product.quantity {
-        println!(">>> Invalid quantity: {}", quantity);
-        return;
+
+    /// Returns the purchase price per unit averaged over all
+    /// purchase lots weighted by their quantity and rounded
+    /// to the cent, or `None` if there are no purchased
+    /// units.
+    fn weighted_avg_cost(&self) -> Option<Cents> {
+        let quantity: u64 = self.purchase_prices.iter().map(|(q, _, _, _)| q).sum();
+        if quantity == 0 {
+            return None;
+        }
+        let total: Cents = self.purchase_prices.iter().map(|(q, p, _, _)| p.times(*q)).sum();
+        Some(total.per_unit(quantity))
     }
 
-    new_product.quantity -= quantity;
-    let tx = SaleTx::new(new_product.name.clone(), quantity, new_product.sale_price);
-    match inventory.edit_product(new_product) {
-        Ok(_) => {
-            println!(">>> Product sold: {:?}", tx);
-            inventory.record_sale(tx);
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("name".to_string(), Value::String(self.name.clone())),
+            ("description".to_string(), Value::String(self.description.clone())),
+            ("quantity".to_string(), Value::Number(self.quantity as f64)),
+            ("sale_price".to_string(), Value::Number(self.sale_price.dollars())),
+            (
+                "purchase_prices".to_string(),
+                Value::Array(self.purchase_prices.iter()
+                    .map(|(q, p, s, id)| Value::Array(vec![
+                        Value::Number(*q as f64),
+                        Value::Number(p.dollars()),
+                        Value::String(s.clone()),
+                        Value::Number(*id as f64),
+                    ]))
+                    .collect()),
+            ),
+            ("backordered".to_string(), Value::Number(self.backordered as f64)),
+            ("moq".to_string(), Value::Number(self.moq as f64)),
+            ("lead_time_days".to_string(), Value::Number(self.lead_time_days as f64)),
+            ("category".to_string(), Value::String(self.category.clone())),
+            ("reorder_level".to_string(), Value::Number(self.reorder_level as f64)),
+            ("tags".to_string(), Value::Array(self.tags.iter().map(|t| Value::String(t.clone())).collect())),
+            ("price_updated_at".to_string(), Value::Number(self.price_updated_at as f64)),
+            (
+                "price_history".to_string(),
+                Value::Array(self.price_history.iter()
+                    .map(|(t, p)| Value::Array(vec![Value::Number(*t as f64), Value::Number(p.dollars())]))
+                    .collect()),
+            ),
+            ("notes".to_string(), Value::String(self.notes.clone())),
+            (
+                "profit_target".to_string(),
+                self.profit_target.map_or(Value::Null, |t| Value::Number(t.dollars())),
+            ),
+            (
+                "image_url".to_string(),
+                self.image_url.as_ref().map_or(Value::Null, |u| Value::String(u.clone())),
+            ),
+        ])
+    }
+
+    /// Converts the public fields of the product into a JSON
+    /// object for the catalog.
+    fn catalog_json(&self) -> Value {
+        Value::Object(vec![
+            ("name".to_string(), Value::String(self.name.clone())),
+            ("description".to_string(), Value::String(self.description.clone())),
+            ("category".to_string(), Value::String(self.category.clone())),
+            ("tags".to_string(), Value::Array(self.tags.iter().map(|t| Value::String(t.clone())).collect())),
+            ("sale_price".to_string(), Value::Number(self.sale_price.dollars())),
+            (
+                "image_url".to_string(),
+                self.image_url.as_ref().map_or(Value::Null, |u| Value::String(u.clone())),
+            ),
+            ("in_stock".to_string(), Value::Bool(self.quantity > 0)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<Product, String> {
+        let mut purchase_prices = Vec::new();
+        for lot in array_field(value, "purchase_prices")? {
+            // lots saved before suppliers were kept have no
+            // supplier, and lots saved before the first sale ID
+            // was kept can be sold by any sale
+            let (q, p, s, id) = match lot.as_array().map(|l| &l[..]) {
+                Some([q, p]) => (q, p, "", Some(0)),
+                Some([q, p, s]) => match s.as_str() {
+                    Some(s) => (q, p, s, Some(0)),
+                    None => return Err("Invalid field: purchase_prices".to_string()),
+                },
+                Some([q, p, s, id]) => match s.as_str() {
+                    Some(s) => (q, p, s, id.as_u64()),
+                    None => return Err("Invalid field: purchase_prices".to_string()),
+                },
+                _ => return Err("Invalid field: purchase_prices".to_string()),
+            };
+            match (q.as_u64(), p.as_f64().and_then(Cents::from_dollars), id) {
+                (Some(q), Some(p), Some(id)) => purchase_prices.push((q, p, s.to_string(), id)),
+                _ => return Err("Invalid field: purchase_prices".to_string()),
+            }
+        }
+        let mut price_history = Vec::new();
+        if value.get("price_history").is_some() {
+            for entry in array_field(value, "price_history")? {
+                match entry.as_array().map(|e| &e[..]) {
+                    Some([t, p]) => match (t.as_u64(), p.as_f64().and_then(Cents::from_dollars)) {
+                        (Some(t), Some(p)) => price_history.push((t, p)),
+                        _ => return Err("Invalid field: price_history".to_string()),
+                    },
+                    _ => return Err("Invalid field: price_history".to_string()),
+                }
+            }
+        }
+        let mut tags = Vec::new();
+        if value.get("tags").is_some() {
+            for tag in array_field(value, "tags")? {
+                add_tag(&mut tags, tag.as_str().ok_or("Invalid field: tags")?);
+            }
+        }
+        let mut product = Product {
+            name: str_field(value, "name")?,
+            description: str_field(value, "description")?,
+            quantity: u64_field(value, "quantity")?,
+            sale_price: cents_field(value, "sale_price")?,
+            purchase_prices,
+            backordered: u64_field(value, "backordered")?,
+            moq: match value.get("moq") {
+                Some(_) => u64_field(value, "moq")?.max(1),
+                None => 1,
+            },
+            lead_time_days: match value.get("lead_time_days") {
+                Some(_) => u64_field(value, "lead_time_days")?,
+                None => 0,
+            },
+            category: match value.get("category") {
+                Some(_) => str_field(value, "category")?,
+                None => String::new(),
+            },
+            reorder_level: match value.get("reorder_level") {
+                Some(_) => u64_field(value, "reorder_level")?,
+                None => 0,
+            },
+            tags,
+            price_updated_at: match value.get("price_updated_at") {
+                Some(_) => u64_field(value, "price_updated_at")?,
+                None => 0,
+            },
+            price_history,
+            notes: match value.get("notes") {
+                Some(_) => str_field(value, "notes")?,
+                None => String::new(),
+            },
+            profit_target: match value.get("profit_target") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(cents_field(value, "profit_target")?),
+            },
+            image_url: match value.get("image_url") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(str_field(value, "image_url")?),
+            },
+            realized_profit: Cents::ZERO,
+        };
+        // products saved before the history start with their
+        // current price
+        if product.price_history.is_empty() {
+            product.price_history.push((product.price_updated_at, product.sale_price));
         }
-        Err(e) => println!("{}", e)
+        Ok(product)
     }
 }
 
-/// Allows the user to purchase products and store them in the system.
-pub fn purchase_handler(inventory: &mut Inventory) {
-    add_handler(inventory);
-}
+impl SaleTx {
+    fn new(product_name: String, quantity: u64, sale_price: Cents, timestamp: u64) -> SaleTx {
+        SaleTx {
+            id: 0,
+            product_name,
+            quantity,
+            free: 0,
+            sale_price,
+            timestamp,
+            note: None,
+        }
+    }
 
-/// Allows the user to generate reports. Displays the
-/// Reporting submenu and prompts the user for an option
-/// number to continue. To return to the main menu, enter
-/// `x`.
-pub fn report_handler(inventory: &mut Inventory) {
-    let mut feature = String::new();
-    loop {
-        println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-        println!("Enter feature number to go to, or x to escape:");
-        println!("Generate product report                   1");
-        println!("Generate sales report for each product    2");
-        println!("Display sales history                     3");
-        println!("Generate purchase report for each product 4");
-        println!("Display purchase history                  5");
-        feature.clear();
-        std::io::stdin().read_line(&mut feature).unwrap();
-        feature = feature.trim().to_string();
-        if feature == "x" {
-            return;
+    /// Returns the revenue of the sale, i.e. the billed units
+    /// times the sale price.
+    fn revenue(&self) -> Cents {
+        self.sale_price.times(self.quantity - self.free)
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("id".to_string(), Value::Number(self.id as f64)),
+            ("product_name".to_string(), Value::String(self.product_name.clone())),
+            ("quantity".to_string(), Value::Number(self.quantity as f64)),
+            ("free".to_string(), Value::Number(self.free as f64)),
+            ("sale_price".to_string(), Value::Number(self.sale_price.dollars())),
+            ("timestamp".to_string(), Value::Number(self.timestamp as f64)),
+            ("note".to_string(), self.note.as_ref().map_or(Value::Null, |n| Value::String(n.clone()))),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<SaleTx, String> {
+        let quantity = u64_field(value, "quantity")?;
+        let free = match value.get("free") {
+            Some(_) => u64_field(value, "free")?,
+            None => 0,
+        };
+        if free > quantity {
+            return Err("Invalid field: free".to_string());
         }
-        match &feature[..] {
-            "1" => report_products(inventory),
-            "2" => report_sales(inventory),
-            "3" => display_sales(inventory),
-            "4" => report_purchases(inventory),
-            "5" => display_purchases(inventory),
-            _ => (),
+        Ok(SaleTx {
+            id: optional_id(value)?,
+            product_name: str_field(value, "product_name")?,
+            quantity,
+            free,
+            sale_price: cents_field(value, "sale_price")?,
+            timestamp: u64_field(value, "timestamp")?,
+            note: match value.get("note") {
+                None | Some(Value::Null) => None,
+                Some(_) => Some(str_field(value, "note")?),
+            },
+        })
+    }
+}
+
+impl PurchaseTx {
+    fn new(product_name: String, quantity: u64, purchase_price: Cents, supplier: String) -> PurchaseTx {
+        PurchaseTx {
+            id: 0,
+            product_name,
+            quantity,
+            purchase_price,
+            supplier,
+            timestamp: now(),
         }
     }
+
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("id".to_string(), Value::Number(self.id as f64)),
+            ("product_name".to_string(), Value::String(self.product_name.clone())),
+            ("quantity".to_string(), Value::Number(self.quantity as f64)),
+            ("purchase_price".to_string(), Value::Number(self.purchase_price.dollars())),
+            ("supplier".to_string(), Value::String(self.supplier.clone())),
+            ("timestamp".to_string(), Value::Number(self.timestamp as f64)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<PurchaseTx, String> {
+        Ok(PurchaseTx {
+            id: optional_id(value)?,
+            product_name: str_field(value, "product_name")?,
+            quantity: u64_field(value, "quantity")?,
+            purchase_price: cents_field(value, "purchase_price")?,
+            supplier: match value.get("supplier") {
+                Some(_) => str_field(value, "supplier")?,
+                None => String::new(),
+            },
+            timestamp: u64_field(value, "timestamp")?,
+        })
+    }
 }
 
-/// Displays a report of products.
-fn report_products(inventory: &mut Inventory) {
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Product report");
-    for product in inventory.products.iter() {
-        println!("Product: {}", product.name);
-        println!("Description: {}", product.description);
-        println!("Quantity in stock: {}", product.quantity);
-        println!("Sale price: {}", product.sale_price);
-        println!("Purchase quantity and prices: {:?}", product.purchase_prices);
-        println!("= = = = = = = = = = = = = = = = = = = = = = = = = = = = = =");
+impl WriteOffTx {
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("product_name".to_string(), Value::String(self.product_name.clone())),
+            ("quantity".to_string(), Value::Number(self.quantity as f64)),
+            ("cost".to_string(), Value::Number(self.cost.dollars())),
+            ("reason".to_string(), Value::String(self.reason.clone())),
+            ("timestamp".to_string(), Value::Number(self.timestamp as f64)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<WriteOffTx, String> {
+        Ok(WriteOffTx {
+            product_name: str_field(value, "product_name")?,
+            quantity: u64_field(value, "quantity")?,
+            cost: cents_field(value, "cost")?,
+            reason: str_field(value, "reason")?,
+            timestamp: u64_field(value, "timestamp")?,
+        })
     }
 }
 
-/// Displays a report of sales grouped by product, and total revenue.
-fn report_sales(inventory: &mut Inventory) {
-    let mut total_sales: HashMap<String, (u64, f64)> = HashMap::new();
-    for tx in inventory.sale_txs.iter() {
-        let sale = total_sales.entry(tx.product_name.clone()).or_insert((0, 0.0));
-        sale.0 += tx.quantity;
-        sale.1 += tx.quantity as f64 * tx.sale_price;
-    }
-    let mut revenue: f64 = 0.0;
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Sales report");
-    for (k, v) in total_sales.iter() {
-        print!("Product: {}; Quantity: {}; Total sale price: {}; Profit: ", k, v.0, v.1);
-        if let Some(product) = inventory.products.iter().find(|p| p.name == *k) {
-            let mut sold_quantity = v.0;
-            let mut purchase_price: f64 = 0.0;
-            for (q, p) in product.purchase_prices.iter() {
-                let current_quantity = sold_quantity.min(*q);
-                purchase_price += current_quantity as f64 * p;
-                sold_quantity -= current_quantity;
-                if sold_quantity == 0 {
-                    break;
-                }
-            }
-            println!("{}", v.1 - purchase_price);
-            revenue += v.1 - purchase_price;
-        } else {
-            println!("Error (Unable to calculate)");
+impl TransferTx {
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("from".to_string(), Value::String(self.from.clone())),
+            ("to".to_string(), Value::String(self.to.clone())),
+            ("quantity".to_string(), Value::Number(self.quantity as f64)),
+            ("timestamp".to_string(), Value::Number(self.timestamp as f64)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<TransferTx, String> {
+        Ok(TransferTx {
+            from: str_field(value, "from")?,
+            to: str_field(value, "to")?,
+            quantity: u64_field(value, "quantity")?,
+            timestamp: u64_field(value, "timestamp")?,
+        })
+    }
+}
+
+impl Reservation {
+    fn to_json(&self) -> Value {
+        Value::Object(vec![
+            ("product_name".to_string(), Value::String(self.product_name.clone())),
+            ("quantity".to_string(), Value::Number(self.quantity as f64)),
+            ("expires_at".to_string(), Value::Number(self.expires_at as f64)),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<Reservation, String> {
+        Ok(Reservation {
+            product_name: str_field(value, "product_name")?,
+            quantity: u64_field(value, "quantity")?,
+            expires_at: u64_field(value, "expires_at")?,
+        })
+    }
+}
+
+impl Bundle {
+    fn to_json(&self) -> Value {
+        let components = self.components.iter()
+            .map(|(name, quantity)| Value::Object(vec![
+                ("product_name".to_string(), Value::String(name.clone())),
+                ("quantity".to_string(), Value::Number(*quantity as f64)),
+            ]))
+            .collect();
+        Value::Object(vec![
+            ("name".to_string(), Value::String(self.name.clone())),
+            ("components".to_string(), Value::Array(components)),
+            ("sale_price".to_string(), Value::Number(self.sale_price.dollars())),
+        ])
+    }
+
+    fn from_json(value: &Value) -> Result<Bundle, String> {
+        let mut components = Vec::new();
+        for component in array_field(value, "components")? {
+            components.push((str_field(component, "product_name")?, u64_field(component, "quantity")?));
         }
+        Ok(Bundle {
+            name: str_field(value, "name")?,
+            components,
+            sale_price: cents_field(value, "sale_price")?,
+        })
     }
-    println!("Total Revenue: {}", revenue);
 }
 
-/// Displays a history of sales and profit from each transaction.
-fn display_sales(inventory: &mut Inventory) {
-    struct PurchasePrices {
-        offset: u64,
-        purchase_prices: Vec<(u64, f64)>,
-    }
-    let mut sold_purchase_prices: HashMap<String, PurchasePrices> = HashMap::new();
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Sales history");
-    for tx in inventory.sale_txs.iter() {
-        let mut err: Option<String> = None;
-        if !sold_purchase_prices.contains_key(&tx.product_name) {
-            if let Some(product) = inventory.products.iter().find(|p| p.name == tx.product_name) {
-                sold_purchase_prices.insert(
-                    product.name.clone(),
-                    PurchasePrices {
-                        offset: 0,
-                        purchase_prices: product.purchase_prices.clone()
-                    }
-                );
-            } else {
-                err = Some("Error (Unable to calculate)".to_string());
-            }
-        }
-        let mut purchase_price: f64 = 0.0;
-        if sold_purchase_prices.contains_key(&tx.product_name) {
-            let product = sold_purchase_prices.get_mut(&tx.product_name).unwrap();
-            let mut tx_quantity = tx.quantity;
-            let mut skip: u64 = product.offset;
-            for (q, p) in product.purchase_prices.iter() {
-                if skip >= *q {
-                    skip -= *q;
-                    continue;
+/// Returns the total price of `qty` units at `price` per
+/// unit, or an error if it is too large to be represented.
+fn safe_total(qty: u64, price: Cents) -> Result<Cents, String> {
+    price.checked_times(qty).ok_or(format!("Total overflow: {} units at {}", qty, price))
+}
+
+/// Returns the current time in seconds since the Unix
+/// epoch.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+trait InventoryManager {
+    fn add_new_product(&mut self, name: String, description: String, quantity: u64, sale_price: Cents, purchase_price: Cents, supplier: String);
+    fn add_same_product(&mut self, name: String, quantity: u64, purchase_price: Cents, supplier: String) -> Result<(), String>;
+    fn edit_product(&mut self, new_product: Product) -> Result<(), String>;
+    fn delete_product(&mut self, product_name: &str);
+    fn get_product(&self, product_name: &str) -> Option<&Product>;
+    fn sell_product(&mut self, product_name: &str, quantity: u64, sale_price: Option<Cents>, timestamp: u64) -> Result<SaleTx, String>;
+    fn record_sale(&mut self, tx: SaleTx) -> SaleTx;
+    fn record_purchase(&mut self, tx: PurchaseTx) -> PurchaseTx;
+}
+
+impl InventoryManager for Inventory {
+    fn add_new_product(&mut self, name: String, description: String, quantity: u64, sale_price: Cents, purchase_price: Cents, supplier: String) {
+        let new_product = Product::new(
+            name,
+            description,
+            quantity,
+            sale_price,
+            purchase_price,
+            supplier,
+            self.next_sale_id
+        );
+        self.products.push(new_product);
+        self.mark_changed();
+    }
+
+    /// Adds the purchased units to the product. They are
+    /// merged into an existing lot with the same purchase
+    /// price and supplier, so that the supplier of each lot
+    /// is kept, unless lot merging is disabled in the
+    /// settings, in which case a new lot is always appended
+    /// to keep the lots in chronological order. Lots are only
+    /// merged when no sale was recorded in between, since the
+    /// earlier sales cannot be taken from the new units.
+    fn add_same_product(&mut self, name: String, quantity: u64, purchase_price: Cents, supplier: String) -> Result<(), String> {
+        let merge_lots = self.settings.merge_lots;
+        let first_sale_id = self.next_sale_id;
+        match self.products.iter_mut().find(|p| p.name == name) {
+            Some(product) => {
+                if quantity == 0 {
+                    return Err(format!("Invalid quantity: {}", quantity));
+                }
+                if !quantity.is_multiple_of(product.moq) {
+                    return Err(format!("Invalid quantity: {} (must be a multiple of the minimum order quantity {})", quantity, product.moq));
                 }
-                let current_quantity = tx_quantity.min(*q - skip);
-                skip = 0;
-                purchase_price += current_quantity as f64 * p;
-                tx_quantity -= current_quantity;
-                if tx_quantity == 0 {
-                    break;
+                let filled = quantity.min(product.backordered);
+                product.backordered -= filled;
+                product.quantity += quantity - filled;
+                let same_lot = product.purchase_prices.iter_mut()
+                    .find(|(_, p, s, id)| merge_lots && *p == purchase_price && *s == supplier && *id == first_sale_id);
+                if let Some((q, _, _, _)) = same_lot {
+                    *q += quantity;
+                } else {
+                    product.purchase_prices.push((quantity, purchase_price, supplier, first_sale_id));
                 }
+                self.refresh_profit(&name);
+                self.mark_changed();
+                Ok(())
             }
-            product.offset += tx.quantity;
+            None => Err(format!("Unavailable product: {}", name))
         }
-        match err {
-            Some(e) => {
-                println!(
-                    "Product: {}; Quantity: {}; Sale price: {}; Profit: {}",
-                    tx.product_name,
-                    tx.quantity,
-                    tx.sale_price,
-                    e);
-            },
-            None => {
-                println!(
-                    "Product: {}; Quantity: {}; Sale price: {}; Profit: {}",
-                    tx.product_name,
-                    tx.quantity,
-                    tx.sale_price,
-                    tx.quantity as f64 * tx.sale_price - purchase_price);
+    }
+
+    /// Replaces the product with the edited one. The quantity
+    /// in stock cannot be set below the units held by active
+    /// reservations.
+    fn edit_product(&mut self, new_product: Product) -> Result<(), String> {
+        let reserved = self.reserved_quantity(&new_product.name, now());
+        if new_product.quantity < reserved {
+            return Err(format!("Invalid quantity: {} (below the {} reserved units)", new_product.quantity, reserved));
+        }
+        match self.products.iter_mut().find(|p| p.name == new_product.name) {
+            Some(product) => {
+                *product = new_product;
+                self.mark_changed();
+                Ok(())
             }
+            None => Err(format!("Unavailable product: {}", new_product.name))
+        }
+    }
+
+    /// Moves the product to the trash, from which it can be
+    /// restored with its stock and purchase lots.
+    fn delete_product(&mut self, product_name: &str) {
+        if let Some(i) = self.products.iter().position(|p| p.name == *product_name) {
+            let product = self.products.remove(i);
+            self.trash.push(product);
+            self.mark_changed();
         }
     }
+
+    fn get_product(&self, product_name: &str) -> Option<&Product> {
+        self.products.iter().find(|p| p.name == *product_name)
+    }
+
+    /// Sells the product at the given price, or at its listed
+    /// sale price if `sale_price` is `None`. Unless backorders
+    /// are allowed, reserved units cannot be sold. The
+    /// quantity must not be zero.
+    fn sell_product(&mut self, product_name: &str, quantity: u64, sale_price: Option<Cents>, timestamp: u64) -> Result<SaleTx, String> {
+        self.sell_units(product_name, quantity, 0, sale_price, timestamp)
+    }
+
+    /// Records the sale with the next sale ID and returns it.
+    /// Its total is added to the session revenue.
+    fn record_sale(&mut self, mut tx: SaleTx) -> SaleTx {
+        tx.id = self.next_sale_id;
+        self.next_sale_id += 1;
+        self.session_revenue += tx.revenue();
+        self.sale_txs.push(tx.clone());
+        self.mark_changed();
+        tx
+    }
+
+    /// Records the purchase with the next purchase ID and
+    /// returns it. The change is counted by the product update
+    /// that added the purchased units.
+    fn record_purchase(&mut self, mut tx: PurchaseTx) -> PurchaseTx {
+        tx.id = self.next_purchase_id;
+        self.next_purchase_id += 1;
+        self.purchase_txs.push(tx.clone());
+        tx
+    }
 }
 
-/// Displays a report of purchases grouped by product.
-fn report_purchases(inventory: &mut Inventory) {
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Purchases report");
-    for product in inventory.products.iter() {
-        let mut quantity: u64 = 0;
-        let mut purchase_price: f64 = 0.0;
-        for (q, p) in product.purchase_prices.iter() {
-            quantity += *q;
-            purchase_price += *q as f64 * p;
+/// Displays the Inventory Management submenu and prompts
+/// the user for an option number to continue. To return to
+/// the main menu, enter `x`.
+pub fn inventory_handler(inventory: &mut Inventory) {
+    let mut feature;
+    loop {
+        ui::banner();
+        ui::prompt("Enter feature number to go to, or x to escape:");
+        ui::prompt("Get product       1");
+        ui::prompt("Edit product      2");
+        ui::prompt("Delete product    3");
+        ui::prompt("Transfer stock    4");
+        ui::prompt("Batch restock     5");
+        ui::prompt("Write off stock   6");
+        ui::prompt("Split lot         7");
+        ui::prompt("Clamp prices      8");
+        ui::prompt("Repair quantities 9");
+        ui::prompt("Restore deleted   10");
+        ui::prompt("Delete low stock  11");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
         }
-        println!("Product: {}; Quantity: {}; Total purchase price: {}", product.name, quantity, purchase_price);
+        inventory_feature(inventory, &feature);
     }
 }
 
-/// Displays a history of purchases.
-fn display_purchases(inventory: &mut Inventory) {
-    println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-    println!("Purchase history");
-    for tx in inventory.purchase_txs.iter() {
-        println!(
-            "Product: {}; Quantity: {}; Purchase price: {}; Total cost: {}",
-            tx.product_name,
-            tx.quantity,
-            tx.purchase_price,
-            tx.quantity as f64 * tx.purchase_price);
+/// Runs the Inventory Management feature with the given
+/// number. Unknown numbers are ignored.
+pub fn inventory_feature(inventory: &mut Inventory, feature: &str) {
+    match feature {
+        "1" => get_handler(inventory),
+        "2" => edit_handler(inventory),
+        "3" => delete_handler(inventory, &mut io::stdin().lock()),
+        "4" => transfer_handler(inventory),
+        "5" => restock_handler(inventory),
+        "6" => write_off_handler(inventory),
+        "7" => split_lot_handler(inventory),
+        "8" => clamp_prices_handler(inventory),
+        "9" => {
+            let messages = inventory.repair_quantities();
+            for message in messages.iter() {
+                println!(">>> {}", message);
+            }
+            if messages.is_empty() {
+                println!(">>> Quantities match the purchase lots");
+            }
+        }
+        "10" => restore_handler(inventory),
+        "11" => delete_below_handler(inventory),
+        _ => (),
+    }
+}
+
+/// Adds the product to the inventory according to the
+/// parameters provided by the user.
+fn add_handler(inventory: &mut Inventory) {
+    let mut feature;
+
+    // name
+    ui::banner();
+    ui::prompt("Enter product name to purchase, or name | description | quantity | sale | purchase for fast entry, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    if feature.contains('|') {
+        fast_add(inventory, &feature);
+        return;
+    }
+
+    if let Some(p) = inventory.get_product(&feature) {
+        ui::banner();
+        ui::print_warning(&format!("Product already exists: {}", feature));
+        ui::prompt("Enter any value to add more of this product, or x to escape:");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+
+        // same quantity
+        ui::banner();
+        ui::prompt("Enter product quantity, or x to escape:");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        let quantity: u64 = match feature.parse() {
+            Ok(x) => {
+                if x == 0 {
+                    ui::print_error(&format!("Invalid quantity: {}", x));
+                    return;
+                }
+                x
+            },
+            Err(e) => {
+                ui::print_error(&format!("Invalid quantity: {} ({})", feature, e));
+                return;
+            }
+        };
+
+        // same sale price
+        ui::banner();
+        ui::prompt("Enter product purchase price, or x to escape:");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        let purchase_price = match Cents::parse(&feature) {
+            Ok(x) => x,
+            Err(e) => {
+                ui::print_error(&format!("Invalid purchase price: {} ({})", feature, e));
+                return;
+            }
+        };
+        if let Some(cost) = p.weighted_avg_cost() {
+            if is_price_outlier(cost, purchase_price, inventory.settings.price_outlier_factor) {
+                ui::print_warning(&format!("Purchase price {} is far from the average cost {}", purchase_price, cost));
+                if !ui::confirm("Keep this price?") {
+                    return;
+                }
+            }
+        }
+
+        // same supplier
+        ui::banner();
+        ui::prompt("Enter supplier name, or press enter to skip, or x to escape:");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        let supplier = feature.clone();
+
+        let total_cost = match safe_total(quantity, purchase_price) {
+            Ok(x) => x,
+            Err(e) => {
+                ui::print_error(&e);
+                return;
+            }
+        };
+        let name = p.name.clone();
+        if !confirm_purchase(&name, quantity, purchase_price, total_cost) {
+            println!(">>> Purchase discarded");
+            return;
+        }
+        match commit_purchase(inventory, name, None, quantity, purchase_price, supplier) {
+            Ok(tx) => println!(">>> Product added: {:?}; Total cost: {}", tx, total_cost),
+            Err(e) => ui::print_error(&e)
+        }
+        return;
+    }
+
+    let name = feature.clone();
+
+    // description
+    ui::banner();
+    ui::prompt("Enter product description, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let description = feature.clone();
+
+    // quantity
+    ui::banner();
+    ui::prompt("Enter product quantity, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => {
+            if x == 0 {
+               ui::print_error(&format!("Invalid quantity: {}", x));
+               return; 
+            }
+            x
+        }
+        Err(e) => {
+            ui::print_error(&format!("Invalid quantity: {} ({})", feature, e));
+            return;
+        }
+    };
+
+    // sale price
+    ui::banner();
+    ui::prompt("Enter product sale price, or markup N to set N% over purchase price, or press enter to price by margin, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let by_margin = feature.is_empty();
+    let markup: Option<f64> = match parse_markup(&feature) {
+        Some(Ok(m)) => Some(m),
+        Some(Err(e)) => {
+            ui::print_error(&e);
+            return;
+        }
+        None => None,
+    };
+    let mut sale_price = Cents::ZERO;
+    if markup.is_none() && !by_margin {
+        sale_price = match Cents::parse(&feature) {
+            Ok(x) => x,
+            Err(e) => {
+                ui::print_error(&format!("Invalid sale price: {} ({})", feature, e));
+                return;
+            }
+        };
+    }
+
+    // purchase price
+    ui::banner();
+    ui::prompt("Enter product purchase price, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let purchase_price = match Cents::parse(&feature) {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid purchase price: {} ({})", feature, e));
+            return;
+        }
+    };
+    if let Some(m) = markup {
+        sale_price = price_from_markup(purchase_price, m);
+        println!(">>> Sale price set: {}", sale_price);
+    } else if by_margin {
+        sale_price = match margin_sale_price(purchase_price) {
+            Some(x) => x,
+            None => return,
+        };
+    }
+
+    // supplier
+    ui::banner();
+    ui::prompt("Enter supplier name, or press enter to skip, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let supplier = feature.clone();
+
+    let total_cost = match safe_total(quantity, purchase_price) {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&e);
+            return;
+        }
+    };
+    if !confirm_purchase(&name, quantity, purchase_price, total_cost) {
+        println!(">>> Purchase discarded");
+        return;
+    }
+    match commit_purchase(inventory, name, Some((description, sale_price)), quantity, purchase_price, supplier) {
+        Ok(tx) => println!(">>> Product added: {:?}; Total cost: {}", tx, total_cost),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Number of fields of a product entered on one line.
+const PRODUCT_LINE_FIELDS: usize = 5;
+
+/// Parses a new product entered on one line as
+/// `name | description | quantity | sale | purchase`. Returns
+/// an error naming the position of the first invalid field.
+fn parse_product_line(s: &str) -> Result<(String, String, u64, Cents, Cents), String> {
+    let fields: Vec<&str> = s.split('|').map(|f| f.trim()).collect();
+    if fields.len() != PRODUCT_LINE_FIELDS {
+        return Err(format!("Expected {} fields separated by '|', found {}", PRODUCT_LINE_FIELDS, fields.len()));
+    }
+    if fields[0].is_empty() {
+        return Err("Empty name in field 1".to_string());
+    }
+    let quantity: u64 = match fields[2].parse() {
+        Ok(0) => return Err("Invalid quantity in field 3: 0".to_string()),
+        Ok(x) => x,
+        Err(e) => return Err(format!("Invalid quantity in field 3: {} ({})", fields[2], e)),
+    };
+    let sale_price = Cents::parse(fields[3])
+        .map_err(|e| format!("Invalid sale price in field 4: {} ({})", fields[3], e))?;
+    let purchase_price = Cents::parse(fields[4])
+        .map_err(|e| format!("Invalid purchase price in field 5: {} ({})", fields[4], e))?;
+    Ok((fields[0].to_string(), fields[1].to_string(), quantity, sale_price, purchase_price))
+}
+
+/// Adds a new product entered on one line, without a
+/// supplier, after the user confirms the purchase.
+fn fast_add(inventory: &mut Inventory, line: &str) {
+    let (name, description, quantity, sale_price, purchase_price) = match parse_product_line(line) {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&e);
+            return;
+        }
+    };
+    if inventory.get_product(&name).is_some() {
+        ui::print_error(&format!("Product already exists: {}", name));
+        return;
+    }
+    let total_cost = match safe_total(quantity, purchase_price) {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&e);
+            return;
+        }
+    };
+    if !confirm_purchase(&name, quantity, purchase_price, total_cost) {
+        println!(">>> Purchase discarded");
+        return;
+    }
+    match commit_purchase(inventory, name, Some((description, sale_price)), quantity, purchase_price, String::new()) {
+        Ok(tx) => println!(">>> Product added: {:?}; Total cost: {}", tx, total_cost),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Returns `true` if the new price is more than `factor`
+/// times higher or lower than the existing average price,
+/// which likely means a typo. Prices are not compared if the
+/// existing average price is not positive.
+fn is_price_outlier(existing_avg: Cents, new_price: Cents, factor: f64) -> bool {
+    if existing_avg <= Cents::ZERO {
+        return false;
+    }
+    let (existing_avg, new_price) = (existing_avg.0 as f64, new_price.0 as f64);
+    new_price > existing_avg * factor || new_price < existing_avg / factor
+}
+
+/// Displays the summary of a purchase and asks the user to
+/// confirm it.
+fn confirm_purchase(name: &str, quantity: u64, purchase_price: Cents, total_cost: Cents) -> bool {
+    confirm_purchase_from(&mut io::stdin().lock(), name, quantity, purchase_price, total_cost)
+}
+
+/// Displays the summary of a purchase and asks the user to
+/// confirm it, reading the answer from `input`.
+fn confirm_purchase_from(input: &mut impl BufRead, name: &str, quantity: u64, purchase_price: Cents, total_cost: Cents) -> bool {
+    ui::banner();
+    ui::prompt(&format!(
+        "Product: {}; Quantity: {}; Unit price: {}; Total cost: {}",
+        name,
+        quantity,
+        purchase_price,
+        total_cost));
+    ui::confirm_from(input, "Confirm purchase?")
+}
+
+/// Records a purchase confirmed by the user. A new product is
+/// added with the description and sale price given in
+/// `new_product`, while an existing one is restocked.
+fn commit_purchase(
+    inventory: &mut Inventory,
+    name: String,
+    new_product: Option<(String, Cents)>,
+    quantity: u64,
+    purchase_price: Cents,
+    supplier: String) -> Result<PurchaseTx, String> {
+    let tx = PurchaseTx::new(name.clone(), quantity, purchase_price, supplier.clone());
+    match new_product {
+        Some((description, sale_price)) => inventory.add_new_product(name, description, quantity, sale_price, purchase_price, supplier),
+        None => inventory.add_same_product(name, quantity, purchase_price, supplier)?,
+    }
+    Ok(inventory.record_purchase(tx))
+}
+
+/// Parses the `markup N` syntax for setting the sale price
+/// to N percent over cost. Returns `None` if the input does
+/// not use the syntax, or an error if the percentage is not
+/// a non-negative number.
+fn parse_markup(input: &str) -> Option<Result<f64, String>> {
+    let mut words = input.split_whitespace();
+    if words.next() != Some("markup") {
+        return None;
+    }
+    let pct = match (words.next(), words.next()) {
+        (Some(pct), None) => pct,
+        _ => return Some(Err(format!("Invalid markup: {}", input))),
+    };
+    match pct.parse::<f64>() {
+        Ok(x) if x >= 0.0 && x.is_finite() => Some(Ok(x)),
+        Ok(x) => Some(Err(format!("Invalid markup: {}", x))),
+        Err(e) => Some(Err(format!("Invalid markup: {} ({})", pct, e))),
+    }
+}
+
+/// Returns the sale price that is `markup_pct` percent over
+/// the cost, rounded to the cent.
+fn price_from_markup(cost: Cents, markup_pct: f64) -> Cents {
+    Cents((cost.0 as f64 * (1.0 + markup_pct / 100.0)).round() as i64)
+}
+
+/// Returns the sale price at which the margin over the cost
+/// is `margin_pct` percent of the sale price, rounded to the
+/// cent, or `None` if the margin is not at least 0 and below
+/// 100.
+fn price_for_margin(cost: Cents, margin_pct: f64) -> Option<Cents> {
+    if !(0.0..100.0).contains(&margin_pct) {
+        return None;
+    }
+    Some(Cents((cost.0 as f64 / (1.0 - margin_pct / 100.0)).round() as i64))
+}
+
+/// Asks for a target margin and suggests the sale price that
+/// achieves it over the purchase price. If no margin is
+/// entered or the suggestion is declined, asks for the sale
+/// price instead. Returns `None` if the user escapes or
+/// enters an invalid value.
+fn margin_sale_price(purchase_price: Cents) -> Option<Cents> {
+    ui::banner();
+    ui::prompt("Enter target margin % to compute sale price, or press enter to enter it manually, or x to escape:");
+    let mut feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return None;
+    }
+    if !feature.is_empty() {
+        let margin: f64 = match feature.parse() {
+            Ok(x) => x,
+            Err(e) => {
+                ui::print_error(&format!("Invalid margin: {} ({})", feature, e));
+                return None;
+            }
+        };
+        let price = match price_for_margin(purchase_price, margin) {
+            Some(x) => x,
+            None => {
+                ui::print_error(&format!("Invalid margin: {} (must be at least 0 and below 100)", margin));
+                return None;
+            }
+        };
+        ui::banner();
+        ui::prompt(&format!("Suggested sale price: {}; Margin: {}%", price, margin));
+        if ui::confirm("Use this sale price?") {
+            println!(">>> Sale price set: {}", price);
+            return Some(price);
+        }
+    }
+
+    ui::banner();
+    ui::prompt("Enter product sale price, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return None;
+    }
+    match Cents::parse(&feature) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            ui::print_error(&format!("Invalid sale price: {} ({})", feature, e));
+            None
+        }
+    }
+}
+
+/// Edits product information. Displays a menu of the
+/// editable fields and prompts the user for a field number
+/// to change, until the user enters `d` to save the changes.
+/// To discard the changes, enter `x`.
+fn edit_handler(inventory: &mut Inventory) {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter product name to edit, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let product = match inventory.get_product(&feature) {
+        Some(p) => p,
+        None => {
+            ui::print_error(&format!("Unavailable product: {}", feature));
+            return;
+        }
+    };
+
+    let mut new_product = product.clone();
+    let product_price = product.sale_price;
+    let tz_offset_minutes = inventory.settings.tz_offset_minutes;
+    let set_local_notes = move |product: &mut Product, input: &str| set_notes(product, input, tz_offset_minutes);
+    loop {
+        ui::banner();
+        ui::prompt(&format!("Product being set: {:?}", new_product));
+        ui::prompt("Enter field number to change, d when done, or x to escape:");
+        ui::prompt(&format!("{:<40}1", format!("Description ({})", new_product.description)));
+        ui::prompt(&format!("{:<40}2", format!("Sale price ({})", new_product.sale_price)));
+        ui::prompt(&format!("{:<40}3", format!("Minimum order quantity ({})", new_product.moq)));
+        ui::prompt(&format!("{:<40}4", format!("Lead time in days ({})", new_product.lead_time_days)));
+        ui::prompt(&format!("{:<40}5", format!("Category ({})", new_product.category)));
+        ui::prompt(&format!("{:<40}6", format!("Reorder level ({})", new_product.reorder_level)));
+        ui::prompt(&format!("{:<40}7", format!("Tags ({})", new_product.tags.join(", "))));
+        ui::prompt(&format!("{:<40}8", format!("Notes ({})", new_product.notes.lines().count())));
+        ui::prompt(&format!("{:<40}9", format!("Profit target ({})", format_target(new_product.profit_target))));
+        ui::prompt(&format!("{:<40}10", format!("Image URL ({})", new_product.image_url.as_deref().unwrap_or("None"))));
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        if feature == "d" {
+            break;
+        }
+        let (prompt, apply): (&str, &FieldSetter) = match &feature[..] {
+            "1" => ("Enter product description", &set_description),
+            "2" => ("Enter product sale price, or markup N to set N% over average cost", &set_sale_price),
+            "3" => ("Enter product minimum order quantity", &set_moq),
+            "4" => ("Enter product lead time in days", &set_lead_time),
+            "5" => ("Enter product category", &set_category),
+            "6" => ("Enter product reorder level", &set_reorder_level),
+            "7" => ("Enter tag to add, or -tag to remove", &set_tags),
+            "8" => ("Enter note to append", &set_local_notes),
+            "9" => ("Enter product profit target, or none to remove it", &set_profit_target),
+            "10" => ("Enter product image URL, or none to remove it", &set_image_url),
+            _ => continue,
+        };
+        ui::banner();
+        ui::prompt(&format!("{}, or c to keep, or x to escape:", prompt));
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            continue;
+        }
+        let old_price = new_product.sale_price;
+        if let Err(e) = apply(&mut new_product, &feature) {
+            ui::print_error(&e);
+            continue;
+        }
+        if is_price_outlier(old_price, new_product.sale_price, inventory.settings.price_outlier_factor) {
+            ui::print_warning(&format!("Sale price {} is far from the current sale price {}", new_product.sale_price, old_price));
+            if !ui::confirm("Keep this price?") {
+                new_product.sale_price = old_price;
+            }
+        }
+    }
+
+    if new_product.sale_price != product_price {
+        new_product.record_price_change(now());
+    }
+    match inventory.edit_product(new_product.clone()) {
+        Ok(_) => println!(">>> Product edited: {:?}", new_product),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Function applying the user input to a field of the
+/// product being edited. An input of `c` or an empty input
+/// keeps the current value.
+type FieldSetter = dyn Fn(&mut Product, &str) -> Result<(), String>;
+
+/// Sets the description of the product being edited.
+fn set_description(product: &mut Product, input: &str) -> Result<(), String> {
+    product.description = apply_edit(&product.description, input);
+    Ok(())
+}
+
+/// Sets the sale price of the product being edited, either
+/// directly or as a markup over its average cost.
+fn set_sale_price(product: &mut Product, input: &str) -> Result<(), String> {
+    if let Some(markup) = parse_markup(input) {
+        let cost = product.weighted_avg_cost()
+            .ok_or(format!("Unable to calculate average cost: {}", product.name))?;
+        product.sale_price = price_from_markup(cost, markup?);
+    } else if !keeps_value(input) {
+        product.sale_price = Cents::parse(input)
+            .map_err(|e| format!("Invalid sale price: {} ({})", input, e))?;
+    }
+    Ok(())
+}
+
+/// Sets the minimum order quantity of the product being
+/// edited.
+fn set_moq(product: &mut Product, input: &str) -> Result<(), String> {
+    if !keeps_value(input) {
+        product.moq = match input.parse() {
+            Ok(0) => return Err("Invalid minimum order quantity: 0".to_string()),
+            Ok(x) => x,
+            Err(e) => return Err(format!("Invalid minimum order quantity: {} ({})", input, e)),
+        };
+    }
+    Ok(())
+}
+
+/// Sets the lead time of the product being edited.
+fn set_lead_time(product: &mut Product, input: &str) -> Result<(), String> {
+    if !keeps_value(input) {
+        product.lead_time_days = input.parse()
+            .map_err(|e| format!("Invalid lead time: {} ({})", input, e))?;
+    }
+    Ok(())
+}
+
+/// Sets the category of the product being edited.
+fn set_category(product: &mut Product, input: &str) -> Result<(), String> {
+    product.category = apply_edit(&product.category, input);
+    Ok(())
+}
+
+/// Sets the reorder level of the product being edited.
+fn set_reorder_level(product: &mut Product, input: &str) -> Result<(), String> {
+    if !keeps_value(input) {
+        product.reorder_level = input.parse()
+            .map_err(|e| format!("Invalid reorder level: {} ({})", input, e))?;
+    }
+    Ok(())
+}
+
+/// Returns `true` if the input entered for an edit field
+/// keeps its current value, i.e. it is `c` or empty.
+fn keeps_value(input: &str) -> bool {
+    input.is_empty() || input == "c"
+}
+
+/// Returns the new value of a text field being edited. An
+/// input of `c` or an empty input keeps the current value,
+/// so that pressing enter does not blank the field.
+fn apply_edit(current: &str, input: &str) -> String {
+    if keeps_value(input) {
+        current.to_string()
+    } else {
+        input.to_string()
+    }
+}
+
+/// Adds the tag to or removes it from the tags of the product
+/// being edited. A tag prefixed with `-` is removed.
+fn set_tags(product: &mut Product, input: &str) -> Result<(), String> {
+    if keeps_value(input) {
+        return Ok(());
+    }
+    match input.strip_prefix('-') {
+        Some(tag) => remove_tag(&mut product.tags, tag),
+        None => add_tag(&mut product.tags, input),
+    }
+    Ok(())
+}
+
+/// Sets the profit target of the product being edited. An
+/// input of `none` removes the target.
+fn set_profit_target(product: &mut Product, input: &str) -> Result<(), String> {
+    if input == "none" {
+        product.profit_target = None;
+    } else if !keeps_value(input) {
+        product.profit_target = match Cents::parse(input) {
+            Ok(Cents::ZERO) => return Err("Invalid profit target: 0".to_string()),
+            Ok(x) => Some(x),
+            Err(e) => return Err(format!("Invalid profit target: {} ({})", input, e)),
+        };
+    }
+    Ok(())
+}
+
+/// Sets the image URL of the product being edited. An input
+/// of `none` removes the URL.
+fn set_image_url(product: &mut Product, input: &str) -> Result<(), String> {
+    if input == "none" {
+        product.image_url = None;
+    } else if !keeps_value(input) {
+        if !is_valid_url(input) {
+            return Err(format!("Invalid image URL: {} (must start with http:// or https://)", input));
+        }
+        product.image_url = Some(input.to_string());
+    }
+    Ok(())
+}
+
+/// Returns `true` if the text looks like a web address: it
+/// starts with `http://` or `https://` followed by a host,
+/// and contains no whitespace.
+fn is_valid_url(text: &str) -> bool {
+    let rest = text.strip_prefix("https://").or_else(|| text.strip_prefix("http://"));
+    match rest {
+        Some(rest) => !rest.is_empty() && !rest.starts_with('/') && !text.contains(char::is_whitespace),
+        None => false,
+    }
+}
+
+/// Returns the profit target for display, or `None` if it
+/// is not set.
+fn format_target(target: Option<Cents>) -> String {
+    target.map_or("None".to_string(), |t| t.to_string())
+}
+
+/// Appends a note to the notes of the product being edited,
+/// dated in the time zone with the given offset.
+fn set_notes(product: &mut Product, input: &str, tz_offset_minutes: i32) -> Result<(), String> {
+    if !keeps_value(input) {
+        append_note(&mut product.notes, input, now(), tz_offset_minutes);
+    }
+    Ok(())
+}
+
+/// Appends the note on a new line of the notes, prefixed
+/// with the local date and time it was written in the time
+/// zone with the given offset. Earlier notes are kept.
+fn append_note(notes: &mut String, note: &str, timestamp: u64, tz_offset_minutes: i32) {
+    if !notes.is_empty() {
+        notes.push('\n');
+    }
+    notes.push_str(&format!("{} {}", datetime::format_timestamp(timestamp, tz_offset_minutes), note.trim()));
+}
+
+/// Returns the tag in the form it is stored and compared in.
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Adds the tag to the tags unless it is empty or already
+/// present, ignoring case.
+fn add_tag(tags: &mut Vec<String>, tag: &str) {
+    let tag = normalize_tag(tag);
+    if !tag.is_empty() && !tags.contains(&tag) {
+        tags.push(tag);
+    }
+}
+
+/// Removes the tag from the tags, ignoring case.
+fn remove_tag(tags: &mut Vec<String>, tag: &str) {
+    let tag = normalize_tag(tag);
+    tags.retain(|t| *t != tag);
+}
+
+/// Returns the products having the tag, ignoring case.
+fn search_by_tag<'a>(inventory: &'a Inventory, tag: &str) -> Vec<&'a Product> {
+    let tag = normalize_tag(tag);
+    inventory.products.iter().filter(|p| p.tags.contains(&tag)).collect()
+}
+
+/// Removes a product from the system, reading the product
+/// name from `input`.
+fn delete_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    ui::banner();
+    ui::prompt("Enter product name to delete, or x to escape:");
+    let feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    inventory.delete_product(&feature);
+    println!(">>> Product deleted if existed: {}", feature);
+}
+
+/// Prompts the user for a stock threshold and, once
+/// confirmed, deletes all products with less stock than it.
+fn delete_below_handler(inventory: &mut Inventory) {
+    ui::banner();
+    ui::prompt("Enter quantity to delete products with less stock than, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let threshold: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid quantity: {} ({})", feature, e));
+            return;
+        }
+    };
+    let names: Vec<&String> = inventory.products.iter()
+        .filter(|p| p.quantity < threshold)
+        .map(|p| &p.name)
+        .collect();
+    if names.is_empty() {
+        println!(">>> No products with less stock than {}", threshold);
+        return;
+    }
+    ui::banner();
+    ui::prompt(&format!("Products to delete: {:?}", names));
+    if !ui::confirm("Delete these products?") {
+        println!(">>> Deletion discarded");
+        return;
+    }
+    let deleted = inventory.delete_below_quantity(threshold);
+    println!(">>> Products deleted: {:?}", deleted);
+}
+
+/// Displays the deleted products in the trash and restores
+/// the one specified by the user.
+fn restore_handler(inventory: &mut Inventory) {
+    let names: Vec<&String> = inventory.trash.iter().map(|p| &p.name).collect();
+    ui::banner();
+    ui::prompt(&format!("Deleted products: {:?}", names));
+    ui::prompt("Enter product name to restore, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    match inventory.restore_product(&feature) {
+        Ok(_) => println!(">>> Product restored: {}", feature),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Moves stock from one product to another according to
+/// the parameters provided by the user.
+fn transfer_handler(inventory: &mut Inventory) {
+    let mut feature;
+
+    // from
+    ui::banner();
+    ui::prompt("Enter product name to transfer stock from, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let from = feature.clone();
+
+    // to
+    ui::banner();
+    ui::prompt("Enter product name to transfer stock to, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let to = feature.clone();
+
+    // quantity
+    ui::banner();
+    ui::prompt("Enter quantity to transfer, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid quantity: {} ({})", feature, e));
+            return;
+        }
+    };
+
+    match inventory.transfer_stock(&from, &to, quantity) {
+        Ok(_) => println!(">>> Stock transferred: {} from {} to {}", quantity, from, to),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Writes off spoiled or damaged stock of a product
+/// according to the parameters provided by the user.
+fn write_off_handler(inventory: &mut Inventory) {
+    let mut feature;
+
+    // name
+    ui::banner();
+    ui::prompt("Enter product name to write off, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let name = feature.clone();
+
+    // quantity
+    ui::banner();
+    ui::prompt("Enter quantity to write off, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid quantity: {} ({})", feature, e));
+            return;
+        }
+    };
+
+    // reason
+    ui::banner();
+    ui::prompt("Enter reason for the write-off, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+
+    match inventory.write_off(&name, quantity, feature.clone()) {
+        Ok(cost) => println!(">>> Stock written off: {} of {}; Cost: {}", quantity, name, cost),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Splits a purchase lot of a product according to the
+/// parameters provided by the user. Lots are numbered from
+/// 1 in the order they are stored.
+fn split_lot_handler(inventory: &mut Inventory) {
+    let mut feature;
+
+    // name
+    ui::banner();
+    ui::prompt("Enter product name to split a lot of, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let name = match inventory.get_product(&feature) {
+        Some(p) => {
+            ui::prompt(&format!("Purchase quantity and prices: {}", format_lots(&p.purchase_prices)));
+            p.name.clone()
+        }
+        None => {
+            ui::print_error(&format!("Unavailable product: {}", feature));
+            return;
+        }
+    };
+
+    // lot
+    ui::banner();
+    ui::prompt("Enter lot number to split, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let lot_index: usize = match feature.parse::<usize>() {
+        Ok(x) if x >= 1 => x - 1,
+        _ => {
+            ui::print_error(&format!("Invalid lot number: {}", feature));
+            return;
+        }
+    };
+
+    // quantity
+    ui::banner();
+    ui::prompt("Enter quantity to split out, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid quantity: {} ({})", feature, e));
+            return;
+        }
+    };
+
+    match inventory.split_lot(&name, lot_index, quantity) {
+        Ok(_) => {
+            let lots = inventory.get_product(&name).map(|p| format_lots(&p.purchase_prices)).unwrap_or_default();
+            println!(">>> Lot split: {}; Purchase quantity and prices: {}", name, lots);
+        }
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Clamps the sale prices of all products into the range
+/// entered by the user. Either bound can be left out.
+fn clamp_prices_handler(inventory: &mut Inventory) {
+    let mut bounds = Vec::new();
+    for bound in ["minimum", "maximum"] {
+        ui::banner();
+        ui::prompt(&format!("Enter {} sale price, or press enter for none, or x to escape:", bound));
+        let feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        if feature.is_empty() {
+            bounds.push(None);
+            continue;
+        }
+        match Cents::parse(&feature) {
+            Ok(x) => bounds.push(Some(x)),
+            Err(_) => {
+                ui::print_error(&format!("Invalid sale price: {}", feature));
+                return;
+            }
+        }
+    }
+    let (floor, ceil) = (bounds[0], bounds[1]);
+    if let (Some(f), Some(c)) = (floor, ceil) {
+        if f > c {
+            ui::print_error(&format!("Invalid price range: {} to {}", f, c));
+            return;
+        }
+    }
+    println!(">>> Prices changed: {}", inventory.clamp_prices(floor, ceil));
+}
+
+/// Restocks several products at once, e.g. when receiving a
+/// shipment. Reads `name quantity price` lines entered by
+/// the user until an empty line, and purchases each product
+/// in turn. Invalid lines and unknown products are reported
+/// and skipped.
+fn restock_handler(inventory: &mut Inventory) {
+    let mut feature;
+    let mut total_quantity: u64 = 0;
+    let mut total_cost = Cents::ZERO;
+    ui::banner();
+    ui::prompt("Enter product name, quantity and purchase price per line, or an empty line to finish:");
+    loop {
+        feature = ui::read_trimmed_line();
+        if feature.is_empty() {
+            break;
+        }
+        let (name, quantity, purchase_price) = match parse_restock_line(&feature) {
+            Ok(x) => x,
+            Err(e) => {
+                ui::print_error(&e);
+                continue;
+            }
+        };
+        let cost = match safe_total(quantity, purchase_price) {
+            Ok(x) if total_cost.checked_add(x).is_some() => x,
+            Ok(_) => {
+                ui::print_error(&format!("Total cost overflow: {}", feature));
+                continue;
+            }
+            Err(e) => {
+                ui::print_error(&e);
+                continue;
+            }
+        };
+        let tx = PurchaseTx::new(name.clone(), quantity, purchase_price, String::new());
+        match inventory.add_same_product(name, quantity, purchase_price, String::new()) {
+            Ok(_) => {
+                total_quantity += quantity;
+                total_cost += cost;
+                let tx = inventory.record_purchase(tx);
+                println!(">>> Product added: {:?}; Total cost: {}", tx, cost);
+            }
+            Err(e) => ui::print_error(&e)
+        }
+    }
+    println!(">>> Restocked items: {}; Total cost: {}", total_quantity, total_cost);
+}
+
+/// Parses a `name quantity price` line of the batch
+/// restock. The name may contain spaces, as the quantity and
+/// the purchase price are taken from the end of the line.
+/// The words may be separated by any run of whitespace, and
+/// the words of the name are joined with single spaces.
+fn parse_restock_line(s: &str) -> Result<(String, u64, Cents), String> {
+    let words: Vec<&str> = s.split_whitespace().collect();
+    let (name, quantity, price) = match words.len() {
+        n if n >= 3 => (words[..n - 2].join(" "), words[n - 2], words[n - 1]),
+        _ => return Err(format!("Invalid line: {} (expected name quantity price)", s)),
+    };
+    let quantity: u64 = quantity.parse()
+        .map_err(|e| format!("Invalid quantity: {} ({})", quantity, e))?;
+    let purchase_price = Cents::parse(price)
+        .map_err(|e| format!("Invalid purchase price: {} ({})", price, e))?;
+    Ok((name, quantity, purchase_price))
+}
+
+/// Displays information about the product.
+fn get_handler(inventory: &Inventory) {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter product name to get information about, or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    match inventory.get_product(&feature) {
+        Some(product) => {
+            println!(">>> Product information");
+            println!(">>> Name: {}", product.name);
+            println!(">>> Description: {}", product.description);
+            println!(">>> Quantity in stock: {}", product.quantity);
+            println!(">>> Sale price: {}", product.sale_price);
+            if product.backordered > 0 {
+                println!(">>> Backordered: {}", product.backordered);
+            }
+            println!(">>> Minimum order quantity: {}", product.moq);
+            println!(">>> Lead time: {} days", product.lead_time_days);
+            println!(">>> Category: {}", product.category);
+            println!(">>> Reorder level: {}", product.reorder_level);
+            println!(">>> Tags: {}", product.tags.join(", "));
+            println!(">>> Notes:");
+            for note in product.notes.lines() {
+                println!(">>>   {}", note);
+            }
+            println!(">>> Realized profit: {}", product.realized_profit);
+            println!(">>> Profit target: {}", format_target(product.profit_target));
+            println!(">>> Image URL: {}", product.image_url.as_deref().unwrap_or("None"));
+            println!(">>> Purchase quantity and prices: {}", format_lots(&product.purchase_prices));
+            println!(">>> Price history:");
+            for (timestamp, price) in product.price_history.iter() {
+                println!(">>>   {} {}", inventory.timestamp(*timestamp), price);
+            }
+            println!();
+        },
+        None => {
+            ui::print_error(&format!("Unavailable product: {}", feature));
+            return;
+        }
+    }
+    let name = feature.clone();
+
+    // export
+    ui::banner();
+    ui::prompt("Enter path to export product as JSON, or press enter to continue:");
+    feature = ui::read_trimmed_line();
+    if feature.is_empty() || feature == "x" {
+        return;
+    }
+    if let Some(content) = inventory.product_json(&name) {
+        match std::fs::write(&feature, content + "\n") {
+            Ok(_) => println!(">>> Product exported: {}", feature),
+            Err(e) => ui::print_error(&format!("Unable to save file: {} ({})", feature, e)),
+        }
+    }
+}
+
+/// Displays the Sales Management submenu and prompts the
+/// user for an option number to continue. To return to the
+/// main menu, enter `x`.
+pub fn sales_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    let mut feature;
+    loop {
+        let released = inventory.release_expired(now());
+        if released > 0 {
+            println!(">>> Expired reservations released: {}", released);
+        }
+        ui::banner();
+        ui::prompt("Enter feature number to go to, or x to escape:");
+        ui::prompt("Sell product             1");
+        ui::prompt("Import sales from CSV    2");
+        ui::prompt("Sell entire stock        3");
+        ui::prompt("Add bundle               4");
+        ui::prompt("Sell bundle              5");
+        ui::prompt("Reserve stock            6");
+        feature = ui::read_line_from(input);
+        if feature == "x" {
+            return;
+        }
+        sales_feature(inventory, &feature, input);
+    }
+}
+
+/// Runs the Sales Management feature with the given
+/// number. Unknown numbers are ignored. If any sales were
+/// recorded, the session revenue is displayed afterwards.
+pub fn sales_feature(inventory: &mut Inventory, feature: &str, input: &mut impl BufRead) {
+    let sales = inventory.sale_txs.len();
+    match feature {
+        "1" => sell_handler(inventory, input),
+        "2" => import_sales_handler(inventory, input),
+        "3" => sell_all_handler(inventory, input),
+        "4" => add_bundle_handler(inventory, input),
+        "5" => sell_bundle_handler(inventory, input),
+        "6" => reserve_handler(inventory, input),
+        _ => (),
+    }
+    if inventory.sale_txs.len() != sales {
+        println!(">>> Session revenue so far: {}", inventory.money(inventory.session_revenue));
+    }
+}
+
+/// Reserves units of a product for the number of hours
+/// specified by the user.
+fn reserve_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter product name to reserve, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let name = feature;
+
+    // quantity
+    ui::banner();
+    ui::prompt("Enter quantity to reserve, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => { ui::print_error(&format!("Invalid quantity: {} ({})", feature, e)); return; }
+    };
+
+    // expiry
+    ui::banner();
+    ui::prompt("Enter number of hours to hold the reservation, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let hours: u64 = match feature.parse() {
+        Ok(x) if x > 0 => x,
+        _ => { ui::print_error(&format!("Invalid number of hours: {}", feature)); return; }
+    };
+    let expires_at = now().saturating_add(hours.saturating_mul(3_600));
+    match inventory.reserve(&name, quantity, expires_at) {
+        Ok(_) => println!(">>> Stock reserved: {} of {} until {}", quantity, name, inventory.timestamp(expires_at)),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Allows the user to sell products available in the system.
+/// A part of the product name is enough if only one product
+/// matches it; otherwise the matching names are shown so the
+/// user can refine it.
+fn sell_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter product name or a part of it to sell, or x to escape:");
+    let product = loop {
+        feature = ui::read_line_from(input);
+        if feature == "x" {
+            return;
+        }
+        match unique_match(inventory, &feature) {
+            Ok(p) => break p,
+            Err(candidates) if candidates.is_empty() => {
+                ui::print_error(&format!("Unavailable product: {}", feature));
+                return;
+            }
+            Err(candidates) => {
+                ui::banner();
+                ui::prompt(&format!("Matching products: {:?}", candidates));
+                ui::prompt("Enter a longer part of the product name, or x to escape:");
+            }
+        }
+    };
+    let name = product.name.clone();
+    let available = product.quantity;
+    let listed_price = product.sale_price;
+
+    // quantity
+    ui::banner();
+    ui::prompt(&format!("Product being sold: {:?}", product));
+    ui::prompt("Enter product quantity, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => { ui::print_error(&format!("Invalid quantity: {} ({})", feature, e)); return; }
+    };
+
+    // override price
+    ui::banner();
+    ui::prompt(&format!("Enter override sale price, or press enter to keep {}, or x to escape:", listed_price));
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let sale_price = match parse_override_price(&feature, listed_price) {
+        Ok(x) => x,
+        Err(e) => { ui::print_error(&e); return; }
+    };
+
+    // promotion
+    ui::banner();
+    ui::prompt("Enter promotion as N+M to give M free for every N paid, or press enter to skip, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let promo = if feature.is_empty() {
+        None
+    } else {
+        match parse_promo(&feature) {
+            Ok(x) => Some(x),
+            Err(e) => { ui::print_error(&e); return; }
+        }
+    };
+
+    // note
+    ui::banner();
+    ui::prompt("Enter sale note, or press enter to skip, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let note = Some(feature.clone()).filter(|n| !n.is_empty());
+
+    let billed = promo.map_or(quantity, |(paid, free)| apply_promo(quantity, paid, free).0);
+    let total = sale_price.times(billed);
+    if requires_confirmation(total, inventory.settings.large_sale_threshold) {
+        ui::banner();
+        ui::prompt(&format!("Product: {}; Quantity: {}; Total: {}", name, quantity, inventory.money(total)));
+        ui::prompt("Enter CONFIRM to record this large sale, or anything else to discard it:");
+        if ui::read_line_from(input) != "CONFIRM" {
+            println!(">>> Sale discarded");
+            return;
+        }
+    }
+
+    let result = match promo {
+        Some((paid, free)) => inventory.sell_promo(&name, quantity, sale_price, paid, free, now()),
+        None => inventory.sell_product(&name, quantity, Some(sale_price), now()),
+    };
+    match result {
+        Ok(mut tx) => {
+            if let Some(note) = note {
+                if let Err(e) = inventory.set_sale_note(tx.id, note.clone()) {
+                    ui::print_error(&e);
+                }
+                tx.note = Some(note);
+            }
+            println!(">>> Product sold: {:?}", tx);
+            if quantity > available {
+                ui::print_warning(&format!("Backordered: {}", quantity - available));
+            }
+        }
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Parses a promotion entered as `N+M`, meaning M units are
+/// free for every N units paid, into N and M. N must be at
+/// least one.
+fn parse_promo(input: &str) -> Result<(u64, u64), String> {
+    let (paid, free) = input.split_once('+')
+        .ok_or(format!("Invalid promotion: {} (expected N+M)", input))?;
+    let paid: u64 = paid.trim().parse()
+        .map_err(|e| format!("Invalid promotion: {} ({})", input, e))?;
+    let free: u64 = free.trim().parse()
+        .map_err(|e| format!("Invalid promotion: {} ({})", input, e))?;
+    if paid == 0 {
+        return Err(format!("Invalid promotion: {} (N must be at least 1)", input));
+    }
+    Ok((paid, free))
+}
+
+/// Returns the units billed and the units given free when
+/// `qty` units are sold under a buy `paid` get `free`
+/// promotion. Every complete group of `paid + free` units
+/// has its last `free` units free, while the remaining units
+/// are all billed. `paid` must not be zero.
+fn apply_promo(qty: u64, paid: u64, free: u64) -> (u64, u64) {
+    let group = paid.saturating_add(free);
+    let free_units = qty / group * free;
+    (qty - free_units, free_units)
+}
+
+/// Returns `true` if a sale with the given total must be
+/// confirmed, i.e. the threshold is set and the total is
+/// above it.
+fn requires_confirmation(total: Cents, threshold: Cents) -> bool {
+    threshold > Cents::ZERO && total > threshold
+}
+
+/// Returns the product whose name is `partial`, or else the
+/// only product whose name contains it, ignoring case. If no
+/// product or several products match, returns the names of
+/// the matching products, sorted.
+fn unique_match<'a>(inventory: &'a Inventory, partial: &str) -> Result<&'a Product, Vec<String>> {
+    if let Some(product) = inventory.get_product(partial) {
+        return Ok(product);
+    }
+    let partial = partial.to_lowercase();
+    let matches: Vec<&Product> = inventory.products.iter()
+        .filter(|p| p.name.to_lowercase().contains(&partial))
+        .collect();
+    match matches[..] {
+        [product] => Ok(product),
+        _ => {
+            let mut names: Vec<String> = matches.iter().map(|p| p.name.clone()).collect();
+            names.sort();
+            Err(names)
+        }
+    }
+}
+
+/// Parses the sale price entered for a single sale. An empty
+/// input keeps the listed sale price of the product.
+fn parse_override_price(input: &str, listed_price: Cents) -> Result<Cents, String> {
+    if input.is_empty() {
+        return Ok(listed_price);
+    }
+    Cents::parse(input).map_err(|e| format!("Invalid sale price: {} ({})", input, e))
+}
+
+/// Sells the entire remaining stock of a product specified
+/// by the user in a single transaction.
+fn sell_all_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    ui::banner();
+    ui::prompt("Enter product name to sell entire stock of, or x to escape:");
+    let feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let quantity = match inventory.unreserved_quantity(&feature, now()) {
+        Some(x) => x,
+        None => {
+            ui::print_error(&format!("Unavailable product: {}", feature));
+            return;
+        }
+    };
+    if quantity == 0 {
+        ui::print_warning(&format!("Nothing to sell: {}", feature));
+        return;
+    }
+    match inventory.sell_product(&feature, quantity, None, now()) {
+        Ok(tx) => println!(">>> Product sold: {:?}", tx),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Allows the user to define a bundle of products in the
+/// system, entering its components one per line.
+fn add_bundle_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter bundle name, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let name = feature;
+
+    // sale price
+    ui::banner();
+    ui::prompt("Enter bundle sale price, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let sale_price = match Cents::parse(&feature) {
+        Ok(x) => x,
+        Err(e) => { ui::print_error(&format!("Invalid sale price: {} ({})", feature, e)); return; }
+    };
+
+    // components
+    let mut components = Vec::new();
+    ui::banner();
+    ui::prompt("Enter component product name and quantity per line, or an empty line to finish:");
+    loop {
+        feature = ui::read_line_from(input);
+        if feature.is_empty() {
+            break;
+        }
+        match parse_component_line(&feature) {
+            Ok(x) => components.push(x),
+            Err(e) => ui::print_error(&e),
+        }
+    }
+    let bundle = Bundle { name, components, sale_price };
+    let added = format!("{:?}", bundle);
+    match inventory.add_bundle(bundle) {
+        Ok(_) => println!(">>> Bundle added: {}", added),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Parses a `name quantity` line of the bundle components.
+/// The name may contain spaces, as the quantity is taken
+/// from the end of the line.
+fn parse_component_line(s: &str) -> Result<(String, u64), String> {
+    let mut words = s.trim().rsplitn(2, char::is_whitespace);
+    let (quantity, name) = match (words.next(), words.next()) {
+        (Some(q), Some(n)) if !n.trim().is_empty() => (q, n.trim()),
+        _ => return Err(format!("Invalid line: {} (expected name quantity)", s)),
+    };
+    let quantity: u64 = quantity.parse()
+        .map_err(|e| format!("Invalid quantity: {} ({})", quantity, e))?;
+    Ok((name.to_string(), quantity))
+}
+
+/// Sells a bundle specified by the user, selling all of its
+/// components or none of them.
+fn sell_bundle_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter bundle name to sell, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let bundle = match inventory.bundles.iter().find(|b| b.name == feature) {
+        Some(b) => b.clone(),
+        None => {
+            ui::print_error(&format!("Unavailable bundle: {}", feature));
+            return;
+        }
+    };
+
+    // quantity
+    ui::banner();
+    ui::prompt(&format!("Bundle being sold: {:?}", bundle));
+    ui::prompt("Enter bundle quantity, or x to escape:");
+    feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let quantity: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => { ui::print_error(&format!("Invalid quantity: {} ({})", feature, e)); return; }
+    };
+    match inventory.sell_bundle(&bundle, quantity) {
+        Ok(_) => println!(">>> Bundle sold: {}; Quantity: {}", bundle.name, quantity),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Imports sales from a CSV file specified by the user.
+fn import_sales_handler(inventory: &mut Inventory, input: &mut impl BufRead) {
+    ui::banner();
+    ui::prompt("Enter path to CSV file with product,quantity[,timestamp] rows, or x to escape:");
+    let feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    let path = feature;
+
+    // dry run
+    ui::banner();
+    ui::prompt("Enter c to only check the file for problems, or press enter to import it, or x to escape:");
+    let feature = ui::read_line_from(input);
+    if feature == "x" {
+        return;
+    }
+    if feature == "c" {
+        match inventory.validate_sales_csv(Path::new(&path)) {
+            Ok(problems) if problems.is_empty() => println!(">>> No problems found"),
+            Ok(problems) => {
+                for problem in problems.iter() {
+                    ui::print_warning(problem);
+                }
+            }
+            Err(e) => ui::print_error(&e),
+        }
+        return;
+    }
+    match inventory.import_sales_csv(Path::new(&path)) {
+        Ok(n) => println!(">>> Sales imported: {}", n),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Allows the user to purchase products and store them in the system.
+pub fn purchase_handler(inventory: &mut Inventory) {
+    add_handler(inventory);
+}
+
+/// Returns a line with the number of products, sales and
+/// purchases, shown above the Reporting submenu.
+fn metrics_line(inventory: &Inventory) -> String {
+    format!(
+        "Products: {}; Sales: {}; Purchases: {}",
+        inventory.products.len(),
+        inventory.sale_txs.len(),
+        inventory.purchase_txs.len())
+}
+
+/// Allows the user to generate reports. Displays the
+/// metrics line and the Reporting submenu, and prompts the
+/// user for an option number to continue. To return to the
+/// main menu, enter `x`.
+pub fn report_handler(inventory: &mut Inventory) {
+    let mut feature;
+    loop {
+        ui::banner();
+        ui::prompt(&metrics_line(inventory));
+        ui::prompt("Enter feature number to go to, or x to escape:");
+        ui::prompt("Generate product report                   1");
+        ui::prompt("Generate sales report for each product    2");
+        ui::prompt("Display sales history                     3");
+        ui::prompt("Generate purchase report for each product 4");
+        ui::prompt("Display purchase history                  5");
+        ui::prompt("Display stock ledger for a product        6");
+        ui::prompt("Display backordered products              7");
+        ui::prompt("Display sales over an amount              8");
+        ui::prompt("Generate average sale price report        9");
+        ui::prompt("Generate lot profit report for a product  10");
+        ui::prompt("Generate stock valuation report           11");
+        ui::prompt("Export all reports to a file              12");
+        ui::prompt("Display products to reorder by lead time  13");
+        ui::prompt("Display products purchased only once      14");
+        ui::prompt("Generate latest cost valuation report     15");
+        ui::prompt("Display top products by margin            16");
+        ui::prompt("Display write-offs                        17");
+        ui::prompt("Display products with a tag               18");
+        ui::prompt("Generate sales forecast                   19");
+        ui::prompt("Generate profit report for each category  20");
+        ui::prompt("Generate cash flow report                 21");
+        ui::prompt("Display products with stale prices        22");
+        ui::prompt("Generate sales report by hour of day      23");
+        ui::prompt("Display sales at a loss                   24");
+        ui::prompt("Generate product report as TSV            25");
+        ui::prompt("Display projected stock-out dates         26");
+        ui::prompt("Generate sorted product report            27");
+        ui::prompt("Generate sales report by week             28");
+        ui::prompt("Display products in a price range         29");
+        ui::prompt("Generate Pareto report of profit          30");
+        ui::prompt("Generate break-even report                31");
+        ui::prompt("Display active reservations               32");
+        ui::prompt("Display price history for a product       33");
+        ui::prompt("Display progress toward profit targets    34");
+        ui::prompt("Generate purchase spend report by month   35");
+        ui::prompt("Generate stock cost report by category    36");
+        ui::prompt("Display products priced below cost        37");
+        ui::prompt("Display sale price variance               38");
+        ui::prompt("Export product catalog as JSON            39");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        report_feature(inventory, &feature);
+    }
+}
+
+/// Runs the Reporting feature with the given number.
+/// Unknown numbers are ignored.
+pub fn report_feature(inventory: &mut Inventory, feature: &str) {
+    let mut out = io::stdout();
+    let result = match feature {
+        "1" => write!(out, "{}", renderer(inventory.settings.report_format).render_products(&inventory.products)),
+        "2" => report_sales(inventory, Some(&inventory.settings.excluded_products), &mut out),
+        "3" => display_sales(inventory, &mut out),
+        "4" => report_purchases(inventory, &mut out),
+        "5" => display_purchases(inventory, &mut out),
+        "6" => stock_ledger_handler(inventory),
+        "7" => report_backorders(inventory, &mut out),
+        "8" => sales_over_handler(inventory),
+        "9" => report_avg_sale_price(inventory, &mut out),
+        "10" => lot_profit_handler(inventory),
+        "11" => report_valuation(inventory, &mut out),
+        "12" => export_reports(inventory),
+        "13" => report_reorder_by_lead_time(inventory, now(), &mut out),
+        "14" => one_time_purchases_handler(inventory),
+        "15" => report_value_at_latest_cost(inventory, &mut out),
+        "16" => top_margin_handler(inventory),
+        "17" => report_write_offs(inventory, &mut out),
+        "18" => tag_handler(inventory),
+        "19" => forecast_handler(inventory),
+        "20" => report_profit_by_category(inventory, &mut out),
+        "21" => report_cash_flow(inventory, &mut out),
+        "22" => stale_prices_handler(inventory),
+        "23" => sales_by_hour_handler(inventory),
+        "24" => report_loss_sales(inventory, &mut out),
+        "25" => write!(out, "{}", report_products_tsv(inventory)),
+        "26" => report_stockout_dates(inventory, now(), &mut out),
+        "27" => sorted_products_handler(inventory),
+        "28" => report_sales_by_week(inventory, &mut out),
+        "29" => price_range_handler(inventory),
+        "30" => report_pareto(inventory, &mut out),
+        "31" => break_even_handler(inventory),
+        "32" => report_reservations(inventory, now(), &mut out),
+        "33" => price_history_handler(inventory),
+        "34" => report_target_progress(inventory, &mut out),
+        "35" => report_monthly_spend(inventory, &mut out),
+        "36" => report_stock_cost_by_category(inventory, &mut out),
+        "37" => report_underpriced(inventory, &mut out),
+        "38" => report_price_variance(inventory, &mut out),
+        "39" => catalog_export_handler(inventory),
+        _ => Ok(()),
+    };
+    if let Err(e) = result {
+        ui::print_error(&format!("Unable to write report ({})", e));
+    }
+}
+
+/// Displays a report of products in plain text.
+fn report_products(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    write!(out, "{}", TextRenderer.render_products(&inventory.products))
+}
+
+/// Returns the entry of the product in the plain text
+/// product report.
+fn product_entry(product: &Product) -> String {
+    format!(
+        "Product: {}\nDescription: {}\nQuantity in stock: {}\nSale price: {}\nPurchase quantity and prices: {}\n{}\n",
+        product.name,
+        product.description,
+        product.quantity,
+        product.sale_price,
+        format_lots(&product.purchase_prices),
+        "= = = = = = = = = = = = = = = = = = = = = = = = = = = = = =")
+}
+
+/// Formats the gathered report data into text of a given
+/// format, keeping the formatting apart from the reports.
+trait ReportRenderer {
+    fn render_products(&self, products: &[Product]) -> String;
+}
+
+/// Renders reports as plain text.
+struct TextRenderer;
+
+/// Renders reports as comma-separated values with a header
+/// row. Fields containing commas, quotes or line breaks are
+/// quoted.
+struct CsvRenderer;
+
+/// Renders reports as Markdown tables.
+struct MarkdownRenderer;
+
+impl ReportRenderer for TextRenderer {
+    fn render_products(&self, products: &[Product]) -> String {
+        let mut text = format!("{}\nProduct report\n", ui::separator());
+        for product in products {
+            text += &product_entry(product);
+        }
+        text
+    }
+}
+
+impl ReportRenderer for CsvRenderer {
+    fn render_products(&self, products: &[Product]) -> String {
+        let field = |s: &str| {
+            if s.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", s.replace('"', "\"\""))
+            } else {
+                s.to_string()
+            }
+        };
+        let mut csv = String::from("name,description,quantity,sale_price\n");
+        for product in products {
+            csv += &format!(
+                "{},{},{},{}\n",
+                field(&product.name),
+                field(&product.description),
+                product.quantity,
+                product.sale_price);
+        }
+        csv
+    }
+}
+
+impl ReportRenderer for MarkdownRenderer {
+    fn render_products(&self, products: &[Product]) -> String {
+        let cell = |s: &str| s.replace('|', "\\|").replace(['\n', '\r'], " ");
+        let mut markdown = String::from("## Product report\n\n| Name | Description | Quantity | Sale price |\n| --- | --- | ---: | ---: |\n");
+        for product in products {
+            markdown += &format!(
+                "| {} | {} | {} | {} |\n",
+                cell(&product.name),
+                cell(&product.description),
+                product.quantity,
+                product.sale_price);
+        }
+        markdown
+    }
+}
+
+/// Returns the renderer of the report format.
+fn renderer(format: ReportFormat) -> Box<dyn ReportRenderer> {
+    match format {
+        ReportFormat::Text => Box::new(TextRenderer),
+        ReportFormat::Csv => Box::new(CsvRenderer),
+        ReportFormat::Markdown => Box::new(MarkdownRenderer),
+    }
+}
+
+/// Key the product report can be sorted by:
+/// - `Name`: Product name
+/// - `Quantity`: Quantity in stock
+/// - `SalePrice`: Sale price per unit
+/// - `Value`: Value of the stock at the weighted-average
+///   purchase price
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Quantity,
+    SalePrice,
+    Value,
+}
+
+impl SortKey {
+    /// Returns the sort key entered by the user.
+    fn from_name(name: &str) -> Result<SortKey, String> {
+        match name {
+            "name" => Ok(SortKey::Name),
+            "quantity" => Ok(SortKey::Quantity),
+            "price" => Ok(SortKey::SalePrice),
+            "value" => Ok(SortKey::Value),
+            _ => Err(format!("Invalid sort key: {}", name)),
+        }
+    }
+}
+
+/// Returns the products sorted by the key in ascending or
+/// descending order. Products with equal keys keep their
+/// order.
+fn sorted_products(inventory: &Inventory, key: SortKey, ascending: bool) -> Vec<&Product> {
+    let value = |p: &Product| p.weighted_avg_cost().unwrap_or(Cents::ZERO).times(p.quantity);
+    let mut products: Vec<&Product> = inventory.products.iter().collect();
+    products.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Name => a.name.cmp(&b.name),
+            SortKey::Quantity => a.quantity.cmp(&b.quantity),
+            SortKey::SalePrice => a.sale_price.cmp(&b.sale_price),
+            SortKey::Value => value(a).cmp(&value(b)),
+        };
+        if ascending { ordering } else { ordering.reverse() }
+    });
+    products
+}
+
+/// Displays a report of products sorted by the key.
+fn report_products_sorted(inventory: &Inventory, key: SortKey, ascending: bool, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Product report sorted by {:?} ({})", key, if ascending { "ascending" } else { "descending" })?;
+    for product in sorted_products(inventory, key, ascending) {
+        write!(out, "{}", product_entry(product))?;
+    }
+    Ok(())
+}
+
+/// Prompts the user for a sort key and direction, and
+/// displays the sorted product report.
+fn sorted_products_handler(inventory: &Inventory) -> io::Result<()> {
+    let mut feature;
+    ui::banner();
+    ui::prompt("Enter sort key (name, quantity, price or value), or x to escape:");
+    feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let key = match SortKey::from_name(&feature) {
+        Ok(k) => k,
+        Err(e) => {
+            ui::print_error(&e);
+            return Ok(());
+        }
+    };
+    ui::banner();
+    ui::prompt("Enter a for ascending or d for descending order, or x to escape:");
+    feature = ui::read_trimmed_line();
+    let ascending = match &feature[..] {
+        "a" => true,
+        "d" => false,
+        "x" => return Ok(()),
+        _ => {
+            ui::print_error(&format!("Invalid order: {}", feature));
+            return Ok(());
+        }
+    };
+    report_products_sorted(inventory, key, ascending, &mut io::stdout())
+}
+
+/// Returns the products whose sale price is between `min`
+/// and `max` inclusive, sorted by sale price from the
+/// lowest. Products with equal prices keep their order.
+fn products_in_price_range(inventory: &Inventory, min: Cents, max: Cents) -> Vec<&Product> {
+    let mut products: Vec<&Product> = inventory.products.iter()
+        .filter(|p| (min..=max).contains(&p.sale_price))
+        .collect();
+    products.sort_by_key(|p| p.sale_price);
+    products
+}
+
+/// Displays the products whose sale price is between `min`
+/// and `max` inclusive, from the lowest price.
+fn report_price_range(inventory: &Inventory, min: Cents, max: Cents, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Products priced from {} to {}", min, max)?;
+    for product in products_in_price_range(inventory, min, max) {
+        write!(out, "{}", product_entry(product))?;
+    }
+    Ok(())
+}
+
+/// Prompts the user for the minimum and maximum sale price,
+/// and displays the products priced within the range.
+fn price_range_handler(inventory: &Inventory) -> io::Result<()> {
+    let mut bounds = Vec::new();
+    for bound in ["minimum", "maximum"] {
+        ui::banner();
+        ui::prompt(&format!("Enter {} sale price, or x to escape:", bound));
+        let feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return Ok(());
+        }
+        match Cents::parse(&feature) {
+            Ok(x) => bounds.push(x),
+            Err(e) => {
+                ui::print_error(&format!("Invalid sale price: {} ({})", feature, e));
+                return Ok(());
+            }
+        }
+    }
+    let (min, max) = (bounds[0], bounds[1]);
+    if min > max {
+        ui::print_error(&format!("Invalid price range: {} to {}", min, max));
+        return Ok(());
+    }
+    report_price_range(inventory, min, max, &mut io::stdout())
+}
+
+/// Columns of the product report in TSV format.
+const PRODUCTS_TSV_HEADER: [&str; 7] = ["name", "description", "category", "quantity", "sale_price", "backordered", "reorder_level"];
+
+/// Returns the product report as tab-separated values with a
+/// header row, one row per product. Tabs and line breaks are
+/// removed from the fields, so no quoting is needed.
+fn report_products_tsv(inventory: &Inventory) -> String {
+    let field = |s: &str| s.chars().filter(|c| !matches!(c, '\t' | '\n' | '\r')).collect::<String>();
+    let mut tsv = PRODUCTS_TSV_HEADER.join("\t") + "\n";
+    for product in inventory.products.iter() {
+        let row = [
+            field(&product.name),
+            field(&product.description),
+            field(&product.category),
+            product.quantity.to_string(),
+            product.sale_price.to_string(),
+            product.backordered.to_string(),
+            product.reorder_level.to_string(),
+        ];
+        tsv += &(row.join("\t") + "\n");
+    }
+    tsv
+}
+
+/// Formats the quantity, purchase price and supplier of
+/// each purchase lot for display, e.g.
+/// `[(100, 12.00, "Farm Co")]`.
+fn format_lots(lots: &[Lot]) -> String {
+    format!("{:?}", lots.iter().map(|(q, p, s, _)| (q, p, s)).collect::<Vec<_>>())
+}
+
+/// Allocates the sales of a product, in the order they were
+/// recorded, to its purchase lots. A sale can only be taken
+/// from the lots purchased before it, i.e. whose first sale
+/// ID is not above its ID, and takes their oldest unsold
+/// units for FIFO and the newest ones for LIFO. The units a
+/// sale lacks are backordered and taken from the next lots
+/// purchased, before any later sale. Returns the lot indexes
+/// and units taken by each sale, and the units sold of each
+/// lot.
+fn allocate_lots(lots: &[Lot], sales: &[&SaleTx], method: CostMethod) -> (Vec<Vec<(usize, u64)>>, Vec<u64>) {
+    // lot indexes in the order the lots were purchased
+    let mut order: Vec<usize> = (0..lots.len()).collect();
+    order.sort_by_key(|i| lots[*i].3);
+    let mut sold: Vec<u64> = vec![0; lots.len()];
+    let mut taken: Vec<Vec<(usize, u64)>> = vec![Vec::new(); sales.len()];
+    // sale indexes and units backordered, oldest first
+    let mut backorders: VecDeque<(usize, u64)> = VecDeque::new();
+    let mut purchased = 0;
+    for (i, tx) in sales.iter().enumerate() {
+        while purchased < order.len() && lots[order[purchased]].3 <= tx.id {
+            fill_backorders(lots, order[purchased], &mut sold, &mut backorders, &mut taken);
+            purchased += 1;
+        }
+        let mut available = order[..purchased].to_vec();
+        if method == CostMethod::Lifo {
+            available.reverse();
+        }
+        let mut remaining = tx.quantity;
+        for lot in available {
+            if remaining == 0 {
+                break;
+            }
+            let units = remaining.min(lots[lot].0.saturating_sub(sold[lot]));
+            if units > 0 {
+                sold[lot] += units;
+                remaining -= units;
+                taken[i].push((lot, units));
+            }
+        }
+        if remaining > 0 {
+            backorders.push_back((i, remaining));
+        }
+    }
+    for lot in order[purchased..].iter() {
+        fill_backorders(lots, *lot, &mut sold, &mut backorders, &mut taken);
+    }
+    (taken, sold)
+}
+
+/// Takes the backordered units of the earlier sales from the
+/// unsold units of the newly purchased lot at index `lot`,
+/// oldest backorder first.
+fn fill_backorders(lots: &[Lot], lot: usize, sold: &mut [u64], backorders: &mut VecDeque<(usize, u64)>, taken: &mut [Vec<(usize, u64)>]) {
+    while let Some(&(i, units)) = backorders.front() {
+        let filled = units.min(lots[lot].0.saturating_sub(sold[lot]));
+        if filled == 0 {
+            break;
+        }
+        sold[lot] += filled;
+        taken[i].push((lot, filled));
+        if filled == units {
+            backorders.pop_front();
+        } else {
+            backorders[0].1 -= filled;
+        }
+    }
+}
+
+/// Returns the profit from each of the sales of a product,
+/// allocated to its purchase lots with `allocate_lots`.
+/// Backordered units not yet purchased have no cost.
+fn sale_profits_from_lots(lots: &[Lot], sales: &[&SaleTx], method: CostMethod) -> Vec<Cents> {
+    let (taken, _) = allocate_lots(lots, sales, method);
+    sales.iter().zip(taken.iter())
+        .map(|(tx, taken)| {
+            let cost: Cents = taken.iter().map(|(lot, units)| lots[*lot].1.times(*units)).sum();
+            tx.revenue() - cost
+        })
+        .collect()
+}
+
+/// Structure for the sales of a single product:
+/// - `product_name`: Name of the sold product (string)
+/// - `quantity`: Total quantity sold (unsigned integer)
+/// - `total_sale_price`: Total sale price of the sold
+///   quantity (amount in cents)
+/// - `profit`: Profit from the sales, or `None` if it is
+///   unable to be calculated because the product was
+///   deleted (optional amount in cents)
+#[derive(Debug, Clone)]
+struct ProductSales {
+    product_name: String,
+    quantity: u64,
+    total_sale_price: Cents,
+    profit: Option<Cents>,
+}
+
+/// Structure for the summary of all sales:
+/// - `products`: Sales grouped by product in order of the
+///   first sale (vector of product sales)
+/// - `revenue`: Total sale price of all sales (amount in
+///   cents)
+/// - `profit`: Total profit of the sales with a known
+///   profit (amount in cents)
+#[derive(Debug, Clone)]
+struct SalesSummary {
+    products: Vec<ProductSales>,
+    revenue: Cents,
+    profit: Cents,
+}
+
+/// Groups sales by product and calculates the quantity and
+/// total sale price of each one, as well as the totals. The
+/// profit is read from the realized profit of the product.
+/// Products in the `excluded` set are omitted.
+///
+/// The realized profits are looked up by name once before
+/// the single pass over the sales, so that the summary
+/// stays linear in the number of sales and products.
+fn compute_sales_summary(inventory: &Inventory, excluded: Option<&HashSet<String>>) -> SalesSummary {
+    let mut realized: HashMap<&str, Cents> = HashMap::new();
+    for product in inventory.products.iter() {
+        realized.entry(product.name.as_str()).or_insert(product.realized_profit);
+    }
+    let mut products: Vec<ProductSales> = Vec::new();
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    let mut revenue = Cents::ZERO;
+    let mut profit = Cents::ZERO;
+    for tx in inventory.sale_txs.iter() {
+        if excluded.is_some_and(|e| e.contains(&tx.product_name)) {
+            continue;
+        }
+        let i = *index.entry(tx.product_name.as_str()).or_insert_with(|| {
+            let product_profit = realized.get(tx.product_name.as_str()).copied();
+            profit += product_profit.unwrap_or(Cents::ZERO);
+            products.push(ProductSales {
+                product_name: tx.product_name.clone(),
+                quantity: 0,
+                total_sale_price: Cents::ZERO,
+                profit: product_profit,
+            });
+            products.len() - 1
+        });
+        let total = tx.revenue();
+        products[i].quantity += tx.quantity;
+        products[i].total_sale_price += total;
+        revenue += total;
+    }
+
+    SalesSummary {
+        products,
+        revenue,
+        profit,
+    }
+}
+
+/// Displays a report of sales grouped by product, and total
+/// revenue and profit. Products in the `excluded` set are
+/// omitted.
+fn report_sales(inventory: &Inventory, excluded: Option<&HashSet<String>>, out: &mut dyn Write) -> io::Result<()> {
+    let summary = compute_sales_summary(inventory, excluded);
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales report")?;
+    for sales in summary.products.iter() {
+        write!(out, "Product: {}; Quantity: {}; Total sale price: {}; Profit: ", sales.product_name, sales.quantity, inventory.money(sales.total_sale_price))?;
+        match sales.profit {
+            Some(profit) => writeln!(out, "{}", inventory.money(profit))?,
+            None => writeln!(out, "Error (Unable to calculate)")?,
+        }
+    }
+    if let Some(excluded) = excluded.filter(|e| !e.is_empty()) {
+        let mut names: Vec<&String> = excluded.iter().collect();
+        names.sort();
+        writeln!(out, "Excluded products: {:?}", names)?;
+    }
+    writeln!(out, "Total Revenue: {}", inventory.money(summary.revenue))?;
+    writeln!(out, "Total Profit: {}", inventory.money(summary.profit))?;
+    Ok(())
+}
+
+/// Number of events displayed in the recent activity feed of
+/// the dashboard.
+const RECENT_ACTIVITY_COUNT: usize = 10;
+
+/// Displays the dashboard with the inventory health score and
+/// the recent activity feed.
+pub fn dashboard_handler(inventory: &Inventory) {
+    let mut out = io::stdout();
+    let result = report_inventory_health(inventory, &mut out)
+        .and_then(|_| report_recent_activity(inventory, RECENT_ACTIVITY_COUNT, &mut out));
+    if let Err(e) = result {
+        ui::print_error(&format!("Unable to write report ({})", e));
+    }
+}
+
+/// Number of products displayed on each page of the
+/// overview, so that a page fits on a 25 line screen.
+const OVERVIEW_PAGE_SIZE: usize = 20;
+
+/// Returns the stock status of the product: `OUT` if none is
+/// in stock, `LOW` if the quantity in stock is at or below
+/// its reorder level, and `OK` otherwise.
+fn stock_status(product: &Product) -> &'static str {
+    if product.quantity == 0 {
+        "OUT"
+    } else if product.quantity <= product.reorder_level {
+        "LOW"
+    } else {
+        "OK"
+    }
+}
+
+/// Width of the quantity, sale price and status columns of
+/// the overview with the spaces before them, plus a spare
+/// column so that the lines do not wrap.
+const OVERVIEW_NUMBERS_WIDTH: usize = 32;
+/// Narrowest name column of the overview, used when the
+/// terminal is too narrow for the table.
+const OVERVIEW_MIN_NAME_WIDTH: usize = 10;
+
+/// Returns the first `width` characters of the text, with
+/// the last one replaced by `~` if any were cut off.
+fn fit_width(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut fitted: String = text.chars().take(width.saturating_sub(1)).collect();
+    fitted.push('~');
+    fitted
+}
+
+/// Displays a compact table of the products with their
+/// quantity in stock, sale price and stock status, one page
+/// at a time, with the name column filling the width of the
+/// terminal. To stop before the last page, enter `x`.
+pub fn overview_handler(inventory: &Inventory) {
+    let name_width = ui::terminal_width()
+        .saturating_sub(OVERVIEW_NUMBERS_WIDTH)
+        .max(OVERVIEW_MIN_NAME_WIDTH);
+    println!("{}", ui::separator());
+    println!("{:<name_width$} {:>10} {:>12} {:>6}", "Product", "Quantity", "Sale price", "Status");
+    let all_shown = ui::paginate(&inventory.products, OVERVIEW_PAGE_SIZE, |product| {
+        println!(
+            "{:<name_width$} {:>10} {:>12} {:>6}",
+            fit_width(&product.name, name_width),
+            product.quantity,
+            inventory.money(product.sale_price),
+            stock_status(product));
+    });
+    if all_shown {
+        println!("Products: {}", inventory.products.len());
+    }
+}
+
+/// Weights of the stock, margin and turnover components of
+/// the inventory health score, adding up to 1.
+const HEALTH_WEIGHTS: (f64, f64, f64) = (0.4, 0.3, 0.3);
+
+/// Returns the components of the inventory health score as
+/// percentages:
+/// - stock: Share of products with more in stock than their
+///   reorder level
+/// - margin: Share of sold products with a positive realized
+///   profit
+/// - turnover: Share of the units sold among all units sold
+///   or still in stock
+fn health_components(inventory: &Inventory) -> (f64, f64, f64) {
+    let percentage = |part: usize, whole: usize| {
+        if whole == 0 { 0.0 } else { part as f64 / whole as f64 * 100.0 }
+    };
+    let stocked = inventory.products.iter().filter(|p| p.quantity > p.reorder_level).count();
+    let sold: Vec<&Product> = inventory.products.iter()
+        .filter(|p| inventory.sale_txs.iter().any(|tx| tx.product_name == p.name))
+        .collect();
+    let profitable = sold.iter().filter(|p| p.realized_profit > Cents::ZERO).count();
+    let units_sold: u64 = inventory.sale_txs.iter().map(|tx| tx.quantity).sum();
+    let units_in_stock: u64 = inventory.products.iter().map(|p| p.quantity).sum();
+    (
+        percentage(stocked, inventory.products.len()),
+        percentage(profitable, sold.len()),
+        percentage(units_sold as usize, (units_sold + units_in_stock) as usize),
+    )
+}
+
+/// Returns the inventory health score from 0 to 100, the
+/// weighted sum of its components: 40% stock, 30% margin and
+/// 30% turnover.
+fn inventory_health(inventory: &Inventory) -> f64 {
+    let (stock, margin, turnover) = health_components(inventory);
+    stock * HEALTH_WEIGHTS.0 + margin * HEALTH_WEIGHTS.1 + turnover * HEALTH_WEIGHTS.2
+}
+
+/// Displays the inventory health score and its components.
+fn report_inventory_health(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    let (stock, margin, turnover) = health_components(inventory);
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Inventory health: {:.0}/100", inventory_health(inventory))?;
+    writeln!(out, "Stocked above reorder level: {:.0}% (weight {:.0}%)", stock, HEALTH_WEIGHTS.0 * 100.0)?;
+    writeln!(out, "Sold with a positive margin: {:.0}% (weight {:.0}%)", margin, HEALTH_WEIGHTS.1 * 100.0)?;
+    writeln!(out, "Stock turnover: {:.0}% (weight {:.0}%)", turnover, HEALTH_WEIGHTS.2 * 100.0)?;
+    Ok(())
+}
+
+/// Displays the last `n` sales and purchases merged into a
+/// single feed in chronological order.
+fn report_recent_activity(inventory: &Inventory, n: usize, out: &mut dyn Write) -> io::Result<()> {
+    // purchases go first so that they precede the sales
+    // recorded in the same second after the stable sort
+    let mut events: Vec<(u64, &str, u64, &str, u64, Cents)> = inventory.purchase_txs.iter()
+        .map(|tx| (tx.timestamp, "BUY", tx.id, &tx.product_name[..], tx.quantity, tx.purchase_price))
+        .chain(inventory.sale_txs.iter()
+            .map(|tx| (tx.timestamp, "SALE", tx.id, &tx.product_name[..], tx.quantity, tx.sale_price)))
+        .collect();
+    events.sort_by_key(|e| e.0);
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Recent activity")?;
+    for (timestamp, kind, id, name, quantity, price) in events.iter().skip(events.len().saturating_sub(n)) {
+        writeln!(out, 
+            "{:<4} ID: {}; Date: {}; Product: {}; Quantity: {}; Unit price: {}",
+            kind,
+            id,
+            inventory.timestamp(*timestamp),
+            name,
+            quantity,
+            price)?;
+    }
+    Ok(())
+}
+
+/// Returns the profit from each sale in the order of the
+/// sales history, allocating the sales to the purchase lots
+/// with `allocate_lots`. The profit is `None` for sales of
+/// deleted products.
+fn sale_profits(inventory: &Inventory) -> Vec<Option<Cents>> {
+    let method = inventory.settings.cost_method;
+    let mut profits: HashMap<&str, VecDeque<Cents>> = HashMap::new();
+    for product in inventory.products.iter() {
+        let txs: Vec<&SaleTx> = inventory.sale_txs.iter().filter(|tx| tx.product_name == product.name).collect();
+        profits.entry(product.name.as_str())
+            .or_insert_with(|| sale_profits_from_lots(&product.purchase_prices, &txs, method).into());
+    }
+    inventory.sale_txs.iter()
+        .map(|tx| profits.get_mut(tx.product_name.as_str()).and_then(|p| p.pop_front()))
+        .collect()
+}
+
+/// Displays a history of sales and profit from each transaction.
+fn display_sales(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales history")?;
+    for (tx, profit) in inventory.sale_txs.iter().zip(sale_profits(inventory)) {
+        let profit = match profit {
+            Some(x) => x.to_string(),
+            None => "Error (Unable to calculate)".to_string(),
+        };
+        write!(out, 
+            "ID: {}; Date: {}; Product: {}; Quantity: {}; Sale price: {}; Profit: {}",
+            tx.id,
+            inventory.timestamp(tx.timestamp),
+            tx.product_name,
+            tx.quantity,
+            tx.sale_price,
+            profit)?;
+        if tx.free > 0 {
+            write!(out, "; Free: {}", tx.free)?;
+        }
+        if let Some(note) = &tx.note {
+            write!(out, "; Note: {}", note)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Displays the sales made below cost, i.e. with a negative
+/// profit, and the loss from each of them.
+fn report_loss_sales(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales at a loss")?;
+    for (tx, profit) in inventory.sale_txs.iter().zip(sale_profits(inventory)) {
+        if let Some(profit) = profit.filter(|x| *x < Cents::ZERO) {
+            writeln!(out, 
+                "ID: {}; Product: {}; Quantity: {}; Sale price: {}; Loss: {}",
+                tx.id,
+                tx.product_name,
+                tx.quantity,
+                tx.sale_price,
+                inventory.money(-profit))?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns how much the sale price of the product is below
+/// the average cost of its remaining stock, or `None` if it
+/// is not below it or no purchased units are left.
+fn underpriced_gap(inventory: &Inventory, product: &Product) -> Option<Cents> {
+    inventory.stock_avg_cost(product)
+        .filter(|cost| product.sale_price < *cost)
+        .map(|cost| cost - product.sale_price)
+}
+
+/// Displays the products whose current sale price is below
+/// the average cost of their remaining stock, so that each
+/// further sale loses money, and the gap per unit. Products
+/// without purchased units left have no known cost and are
+/// skipped.
+fn report_underpriced(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Products priced below cost")?;
+    for product in inventory.products.iter() {
+        if let Some(gap) = underpriced_gap(inventory, product) {
+            writeln!(out, 
+                "Product: {}; Sale price: {}; Average cost: {}; Gap: {}",
+                product.name,
+                inventory.money(product.sale_price),
+                inventory.money(product.sale_price + gap),
+                inventory.money(gap))?;
+        }
+    }
+    Ok(())
+}
+
+/// Ratio of the highest to the lowest sale price of a
+/// product above which its prices are flagged as
+/// inconsistent in the price variance report.
+const PRICE_VARIANCE_RATIO: f64 = 2.0;
+
+/// Returns the lowest and highest of the prices and their
+/// standard deviation in cents, or `None` if there are no
+/// prices.
+fn price_spread(prices: &[Cents]) -> Option<(Cents, Cents, f64)> {
+    let min = *prices.iter().min()?;
+    let max = *prices.iter().max()?;
+    let n = prices.len() as f64;
+    let mean = prices.iter().map(|p| p.0 as f64).sum::<f64>() / n;
+    let variance = prices.iter().map(|p| (p.0 as f64 - mean).powi(2)).sum::<f64>() / n;
+    Some((min, max, variance.sqrt()))
+}
+
+/// Returns `true` if the highest price is more than `ratio`
+/// times the lowest one. Any positive price counts as more
+/// than that when the lowest price is zero.
+fn is_inconsistent_price(min: Cents, max: Cents, ratio: f64) -> bool {
+    max.0 as f64 > min.0 as f64 * ratio
+}
+
+/// Displays the lowest, highest and standard deviation of
+/// the sale prices of each sold product, in alphabetical
+/// order, flagging the products whose highest price is more
+/// than `PRICE_VARIANCE_RATIO` times the lowest one, which
+/// may point to pricing errors or unauthorized discounts.
+fn report_price_variance(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    let mut prices: BTreeMap<&str, Vec<Cents>> = BTreeMap::new();
+    for tx in inventory.sale_txs.iter() {
+        prices.entry(tx.product_name.as_str()).or_default().push(tx.sale_price);
+    }
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sale price variance report")?;
+    for (name, prices) in prices.iter() {
+        if let Some((min, max, std_dev)) = price_spread(prices) {
+            write!(out, 
+                "Product: {}; Sales: {}; Min: {}; Max: {}; Std dev: {}",
+                name,
+                prices.len(),
+                inventory.money(min),
+                inventory.money(max),
+                inventory.money(Cents(std_dev.round() as i64)))?;
+            if is_inconsistent_price(min, max, PRICE_VARIANCE_RATIO) {
+                write!(out, "; Inconsistent")?;
+            }
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Displays a report of purchases grouped by product.
+fn report_purchases(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Purchases report")?;
+    for product in inventory.products.iter() {
+        let mut quantity: u64 = 0;
+        let mut purchase_price: Option<Cents> = Some(Cents::ZERO);
+        for (q, p, _, _) in product.purchase_prices.iter() {
+            quantity += *q;
+            purchase_price = purchase_price.and_then(|total| total.checked_add(p.checked_times(*q)?));
+        }
+        write!(out, "Product: {}; Quantity: {}; Total purchase price: ", product.name, quantity)?;
+        match purchase_price {
+            Some(x) => writeln!(out, "{}", x)?,
+            None => writeln!(out, "Error (Unable to calculate)")?,
+        }
+    }
+    Ok(())
+}
+
+/// Displays a history of purchases.
+fn display_purchases(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Purchase history")?;
+    for tx in inventory.purchase_txs.iter() {
+        writeln!(out, 
+            "ID: {}; Date: {}; Product: {}; Quantity: {}; Purchase price: {}; Total cost: {}",
+            tx.id,
+            inventory.timestamp(tx.timestamp),
+            tx.product_name,
+            tx.quantity,
+            tx.purchase_price,
+            safe_total(tx.quantity, tx.purchase_price).map_or("Error (Unable to calculate)".to_string(), |x| x.to_string()))?;
+    }
+    let (units, cost) = purchase_totals(&inventory.purchase_txs);
+    writeln!(out, "Total units purchased: {}; Total cost: {}", units, inventory.money(cost))?;
+    Ok(())
+}
+
+/// Returns the total units and the total cost of the
+/// purchases.
+fn purchase_totals(txs: &[PurchaseTx]) -> (u64, Cents) {
+    let units = txs.iter().map(|tx| tx.quantity).sum();
+    let cost = txs.iter().map(|tx| tx.purchase_price.times(tx.quantity)).sum();
+    (units, cost)
+}
+
+/// Prompts the user for a product name and displays its
+/// stock ledger.
+fn stock_ledger_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter product name to display stock ledger for, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    report_stock_ledger(inventory, &feature, &mut io::stdout())
+}
+
+/// Displays a chronological view of all purchases and sales
+/// of the product with a running stock balance after each
+/// event. Purchases made at the same time as sales are
+/// shown first.
+fn report_stock_ledger(inventory: &Inventory, name: &str, out: &mut dyn Write) -> io::Result<()> {
+    let mut events: Vec<(u64, &str, i64)> = Vec::new();
+    for tx in inventory.purchase_txs.iter().filter(|tx| tx.product_name == name) {
+        events.push((tx.timestamp, "Purchase", tx.quantity as i64));
+    }
+    for tx in inventory.sale_txs.iter().filter(|tx| tx.product_name == name) {
+        events.push((tx.timestamp, "Sale", -(tx.quantity as i64)));
+    }
+    for tx in inventory.write_off_txs.iter().filter(|tx| tx.product_name == name) {
+        events.push((tx.timestamp, "Write-off", -(tx.quantity as i64)));
+    }
+    for tx in inventory.transfer_txs.iter() {
+        if tx.from == name {
+            events.push((tx.timestamp, "Transfer out", -(tx.quantity as i64)));
+        }
+        if tx.to == name {
+            events.push((tx.timestamp, "Transfer in", tx.quantity as i64));
+        }
+    }
+    events.sort_by_key(|(ts, _, _)| *ts);
+
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Stock ledger: {}", name)?;
+    let mut balance: i64 = 0;
+    for (ts, kind, quantity) in events.iter() {
+        balance += quantity;
+        writeln!(out, 
+            "Date: {}; {}: {:+}; Balance: {}",
+            inventory.timestamp(*ts),
+            kind,
+            quantity,
+            balance)?;
+    }
+    Ok(())
+}
+
+/// Displays a report of products that were sold while out
+/// of stock and are waiting for a purchase.
+fn report_backorders(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Backorder report")?;
+    for product in inventory.products.iter().filter(|p| p.backordered > 0) {
+        writeln!(out, "Product: {}; Backordered: {}", product.name, product.backordered)?;
+    }
+    Ok(())
+}
+
+/// Prompts the user for a minimum sale total and displays
+/// the sales that meet it.
+fn sales_over_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter minimum sale total, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let min_total = match Cents::parse(&feature) {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid sale total: {} ({})", feature, e));
+            return Ok(());
+        }
+    };
+    report_sales_over(inventory, min_total, &mut io::stdout())
+}
+
+/// Displays the sales whose total (quantity times sale
+/// price) meets or exceeds `min_total`.
+fn report_sales_over(inventory: &Inventory, min_total: Cents, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales of {} or more", min_total)?;
+    for tx in inventory.sale_txs.iter() {
+        let total = match safe_total(tx.quantity - tx.free, tx.sale_price) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if total >= min_total {
+            writeln!(out, 
+                "ID: {}; Date: {}; Product: {}; Quantity: {}; Sale price: {}; Total: {}",
+                tx.id,
+                inventory.timestamp(tx.timestamp),
+                tx.product_name,
+                tx.quantity,
+                tx.sale_price,
+                total)?;
+        }
+    }
+    Ok(())
+}
+
+/// Displays the average price each product was actually
+/// sold for, weighted by the billed quantity, alongside its
+/// listed sale price.
+fn report_avg_sale_price(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Average sale price report")?;
+    for product in inventory.products.iter() {
+        let mut quantity: u64 = 0;
+        let mut total_sale_price = Cents::ZERO;
+        for tx in inventory.sale_txs.iter().filter(|tx| tx.product_name == product.name) {
+            quantity += tx.quantity - tx.free;
+            total_sale_price += tx.revenue();
+        }
+        write!(out, "Product: {}; Listed sale price: {}; Average sale price: ", product.name, product.sale_price)?;
+        if quantity == 0 {
+            writeln!(out, "No sales")?;
+        } else {
+            writeln!(out, "{}", total_sale_price.per_unit(quantity))?;
+        }
+    }
+    Ok(())
+}
+
+/// Displays the Backups submenu and prompts the user for an
+/// option number to continue. To return to the main menu,
+/// enter `x`.
+pub fn backup_handler(inventory: &mut Inventory) {
+    let mut feature;
+    loop {
+        ui::banner();
+        ui::prompt("Enter feature number to go to, or x to escape:");
+        ui::prompt("Save backup        1");
+        ui::prompt("Compare backups    2");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        backup_feature(inventory, &feature);
+    }
+}
+
+/// Runs the Backups feature with the given number.
+/// Unknown numbers are ignored.
+pub fn backup_feature(inventory: &mut Inventory, feature: &str) {
+    match feature {
+        "1" => save_backup_handler(inventory),
+        "2" => compare_backups_handler(),
+        _ => (),
+    }
+}
+
+/// Saves the products and transactions to a file specified
+/// by the user.
+fn save_backup_handler(inventory: &Inventory) {
+    ui::banner();
+    ui::prompt("Enter path to backup file to save, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return;
+    }
+    match inventory.save_to_file(Path::new(&feature)) {
+        Ok(_) => println!(">>> Backup saved: {}", feature),
+        Err(e) => ui::print_error(&e)
+    }
+}
+
+/// Loads two backup files specified by the user and
+/// displays what changed between them.
+fn compare_backups_handler() {
+    let mut feature;
+    let mut inventories: Vec<Inventory> = Vec::new();
+    for prompt in ["older", "newer"] {
+        ui::banner();
+        ui::prompt(&format!("Enter path to {} backup file, or x to escape:", prompt));
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        match Inventory::load_from_file(Path::new(&feature)) {
+            Ok(inventory) => inventories.push(inventory),
+            Err(e) => {
+                ui::print_error(&e);
+                return;
+            }
+        }
+    }
+    let changes = diff_backups(&inventories[0], &inventories[1]);
+    if changes.is_empty() {
+        println!(">>> No changes");
+    }
+    for change in changes.iter() {
+        println!(">>> {}", change);
+    }
+}
+
+/// Compares the products of two inventories and returns a
+/// line for each product removed from `a`, added in `b`, or
+/// whose quantity or sale price changed.
+fn diff_backups(a: &Inventory, b: &Inventory) -> Vec<String> {
+    let mut changes: Vec<String> = Vec::new();
+    for old in a.products.iter() {
+        match b.get_product(&old.name) {
+            Some(new) => {
+                if old.quantity != new.quantity {
+                    changes.push(format!("Quantity changed: {} ({} -> {})", old.name, old.quantity, new.quantity));
+                }
+                if old.sale_price != new.sale_price {
+                    changes.push(format!("Sale price changed: {} ({} -> {})", old.name, old.sale_price, new.sale_price));
+                }
+            }
+            None => changes.push(format!("Removed: {}", old.name)),
+        }
+    }
+    for new in b.products.iter() {
+        if a.get_product(&new.name).is_none() {
+            changes.push(format!("Added: {}", new.name));
+        }
+    }
+    changes
+}
+
+/// Prompts the user for a product name and displays the
+/// profit attributable to each of its purchase lots.
+fn lot_profit_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter product name to generate lot profit report for, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    report_lot_profit(inventory, &feature, &mut io::stdout())
+}
+
+/// Displays, for each purchase lot of the product, how many
+/// units from that lot were sold, at what average sale
+/// price, and the profit attributable to the lot. Sales are
+/// allocated to lots in the order of the cost method, so
+/// the lot profits add up to the profit in the sales
+/// report.
+fn report_lot_profit(inventory: &Inventory, name: &str, out: &mut dyn Write) -> io::Result<()> {
+    let product = match inventory.get_product(name) {
+        Some(p) => p,
+        None => {
+            ui::print_error(&format!("Unavailable product: {}", name));
+            return Ok(());
+        }
+    };
+    let txs: Vec<&SaleTx> = inventory.sale_txs.iter().filter(|tx| tx.product_name == name).collect();
+    let (taken, _) = allocate_lots(&product.purchase_prices, &txs, inventory.settings.cost_method);
+    // sold quantity and total sale price per lot
+    let mut sold: Vec<(u64, Cents)> = vec![(0, Cents::ZERO); product.purchase_prices.len()];
+    let mut unallocated: u64 = 0;
+    for (tx, taken) in txs.iter().zip(taken.iter()) {
+        // the free units of a promotion are the last ones taken
+        let mut billed = tx.quantity - tx.free;
+        for (lot, units) in taken.iter() {
+            sold[*lot].0 += *units;
+            sold[*lot].1 += tx.sale_price.times((*units).min(billed));
+            billed -= (*units).min(billed);
+        }
+        unallocated += tx.quantity - taken.iter().map(|(_, units)| units).sum::<u64>();
+    }
+
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Lot profit report: {}", name)?;
+    for (i, ((q, p, s, _), (sold_quantity, total_sale_price))) in product.purchase_prices.iter().zip(sold.iter()).enumerate() {
+        write!(out, "Lot: {}; ", i + 1)?;
+        if !s.is_empty() {
+            write!(out, "Supplier: {}; ", s)?;
+        }
+        write!(out, "Quantity: {}; Purchase price: {}; Sold: {}; Average sale price: ", q, p, sold_quantity)?;
+        if *sold_quantity == 0 {
+            write!(out, "No sales")?;
+        } else {
+            write!(out, "{}", total_sale_price.per_unit(*sold_quantity))?;
+        }
+        writeln!(out, "; Profit: {}", *total_sale_price - p.times(*sold_quantity))?;
+    }
+    if unallocated > 0 {
+        writeln!(out, "Sold without a purchase lot: {}", unallocated)?;
+    }
+    Ok(())
+}
+
+/// Displays the value of the stock of each product at its
+/// weighted-average purchase price, and the total value.
+fn report_valuation(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Stock valuation report")?;
+    let mut total = Cents::ZERO;
+    for product in inventory.products.iter() {
+        write!(out, "Product: {}; Quantity: {}; Average cost: ", product.name, product.quantity)?;
+        match product.weighted_avg_cost() {
+            Some(cost) => {
+                writeln!(out, "{}; Value: {}", inventory.money(cost), inventory.money(cost.times(product.quantity)))?;
+                total += cost.times(product.quantity);
+            }
+            None => writeln!(out, "Error (Unable to calculate)")?,
+        }
+    }
+    writeln!(out, "Total value: {}", inventory.money(total))?;
+    Ok(())
+}
+
+/// Returns the average number of units of the product sold
+/// per day from its first sale until `now`, counting at
+/// least one day, or `None` if it has never been sold.
+fn avg_daily_sales(inventory: &Inventory, product_name: &str, now: u64) -> Option<f64> {
+    let sales: Vec<&SaleTx> = inventory.sale_txs.iter().filter(|tx| tx.product_name == product_name).collect();
+    let first = sales.iter().map(|tx| tx.timestamp).min()?;
+    let quantity: u64 = sales.iter().map(|tx| tx.quantity).sum();
+    let days = (now.saturating_sub(first) / datetime::SECS_PER_DAY).max(1);
+    Some(quantity as f64 / days as f64)
+}
+
+/// Returns the number of days the quantity in stock lasts
+/// at the given daily sales, or `None` if nothing is sold.
+fn days_until_stockout(quantity: u64, daily_sales: f64) -> Option<f64> {
+    if daily_sales > 0.0 {
+        Some(quantity as f64 / daily_sales)
+    } else {
+        None
+    }
+}
+
+/// Displays for each product the number of days until its
+/// stock runs out at the average daily sales, and the
+/// projected stock-out date.
+fn report_stockout_dates(inventory: &Inventory, now: u64, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Stock-out dates")?;
+    for product in inventory.products.iter() {
+        let daily_sales = avg_daily_sales(inventory, &product.name, now).unwrap_or(0.0);
+        match days_until_stockout(product.quantity, daily_sales) {
+            Some(days) => {
+                let stockout = now.saturating_add((days * datetime::SECS_PER_DAY as f64) as u64);
+                let (year, month, day) = datetime::civil_from_days((stockout / datetime::SECS_PER_DAY) as i64);
+                writeln!(out, 
+                    "Product: {}; Quantity: {}; Daily sales: {:.2}; Days left: {:.1}; Stock-out date: {:04}-{:02}-{:02}",
+                    product.name,
+                    product.quantity,
+                    daily_sales,
+                    days,
+                    year,
+                    month,
+                    day)?;
+            }
+            None => writeln!(out, "Product: {}; Quantity: {}; No depletion", product.name, product.quantity)?,
+        }
+    }
+    Ok(())
+}
+
+/// Displays the products whose stock will run out before a
+/// reorder could arrive, i.e. the average daily sales over
+/// the lead time exceed the quantity in stock.
+fn report_reorder_by_lead_time(inventory: &Inventory, now: u64, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Reorder report by lead time")?;
+    for product in inventory.products.iter() {
+        let daily_sales = match avg_daily_sales(inventory, &product.name, now) {
+            Some(d) => d,
+            None => continue,
+        };
+        let lead_time_sales = daily_sales * product.lead_time_days as f64;
+        if lead_time_sales > product.quantity as f64 {
+            writeln!(out, 
+                "Product: {}; Quantity: {}; Daily sales: {:.2}; Lead time: {} days; Sales during lead time: {:.2}",
+                product.name,
+                product.quantity,
+                daily_sales,
+                product.lead_time_days,
+                lead_time_sales)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prompts the user for a number of days and displays the
+/// products purchased only once at least that long ago.
+fn one_time_purchases_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter minimum number of days since the purchase, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let min_age_days: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid number of days: {} ({})", feature, e));
+            return Ok(());
+        }
+    };
+    report_one_time_purchases(inventory, min_age_days, now(), &mut io::stdout())
+}
+
+/// Displays the products with exactly one purchase that was
+/// made at least `min_age_days` days before `now`, which are
+/// candidates for pruning from the catalog.
+fn report_one_time_purchases(inventory: &Inventory, min_age_days: u64, now: u64, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Products purchased only once {} or more days ago", min_age_days)?;
+    for product in inventory.products.iter() {
+        let purchases: Vec<&PurchaseTx> = inventory.purchase_txs.iter().filter(|tx| tx.product_name == product.name).collect();
+        if let [tx] = purchases[..] {
+            if now.saturating_sub(tx.timestamp) >= min_age_days.saturating_mul(datetime::SECS_PER_DAY) {
+                writeln!(out, 
+                    "Product: {}; Purchased: {}; Quantity: {}; Quantity in stock: {}",
+                    product.name,
+                    inventory.timestamp(tx.timestamp),
+                    tx.quantity,
+                    product.quantity)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Displays the value of the stock of each product at the
+/// purchase price of its most recent lot, i.e. its
+/// replacement cost, and the total value.
+fn report_value_at_latest_cost(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Latest cost valuation report")?;
+    let mut total = Cents::ZERO;
+    for product in inventory.products.iter() {
+        write!(out, "Product: {}; Quantity: {}; Latest cost: ", product.name, product.quantity)?;
+        match product.purchase_prices.last() {
+            Some((_, cost, _, _)) => {
+                writeln!(out, "{}; Value: {}", inventory.money(*cost), inventory.money(cost.times(product.quantity)))?;
+                total += cost.times(product.quantity);
+            }
+            None => writeln!(out, "Error (Unable to calculate)")?,
+        }
+    }
+    writeln!(out, "Total value: {}", inventory.money(total))?;
+    Ok(())
+}
+
+/// Prompts the user for a number of products and displays
+/// the products with the highest margin.
+fn top_margin_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter number of products to display, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let n: usize = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid number of products: {} ({})", feature, e));
+            return Ok(());
+        }
+    };
+    report_top_margin(inventory, n, &mut io::stdout())
+}
+
+/// Returns the average profit per unit sold, rounded to the
+/// cent, or `None` if no units were sold.
+fn profit_per_unit(profit: Cents, units: u64) -> Option<Cents> {
+    if units == 0 {
+        None
+    } else {
+        Some(profit.per_unit(units))
+    }
+}
+
+/// Displays the `n` products with the highest margin, i.e.
+/// profit as a percentage of revenue, from the sales
+/// summary, with the average profit per unit sold. Ties are
+/// broken by the higher revenue. Products without revenue
+/// or with an unknown profit, and those excluded from the
+/// sales report, are omitted.
+fn report_top_margin(inventory: &Inventory, n: usize, out: &mut dyn Write) -> io::Result<()> {
+    let summary = compute_sales_summary(inventory, Some(&inventory.settings.excluded_products));
+    let mut margins: Vec<(&ProductSales, f64)> = summary.products.iter()
+        .filter(|s| s.total_sale_price > Cents::ZERO)
+        .filter_map(|s| s.profit.map(|p| (s, p.0 as f64 / s.total_sale_price.0 as f64 * 100.0)))
+        .collect();
+    margins.sort_by(|(a, ma), (b, mb)| mb.total_cmp(ma)
+        .then(b.total_sale_price.cmp(&a.total_sale_price)));
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Top {} products by margin", n)?;
+    for (sales, margin) in margins.iter().take(n) {
+        let profit = sales.profit.unwrap_or(Cents::ZERO);
+        let per_unit = match profit_per_unit(profit, sales.quantity) {
+            Some(p) => inventory.money(p),
+            None => "None".to_string(),
+        };
+        writeln!(out, 
+            "Product: {}; Margin: {:.2}%; Revenue: {}; Profit: {}; Profit per unit: {}",
+            sales.product_name,
+            margin,
+            inventory.money(sales.total_sale_price),
+            inventory.money(profit),
+            per_unit)?;
+    }
+    Ok(())
+}
+
+/// Displays a history of write-offs and their total cost.
+fn report_write_offs(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Write-off report")?;
+    let mut total = Cents::ZERO;
+    for tx in inventory.write_off_txs.iter() {
+        writeln!(out, 
+            "Date: {}; Product: {}; Quantity: {}; Cost: {}; Reason: {}",
+            inventory.timestamp(tx.timestamp),
+            tx.product_name,
+            tx.quantity,
+            inventory.money(tx.cost),
+            tx.reason)?;
+        total += tx.cost;
+    }
+    writeln!(out, "Total cost: {}", inventory.money(total))?;
+    Ok(())
+}
+
+/// Prompts the user for a tag and displays the products
+/// having it.
+fn tag_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter tag to display products with, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    report_products_by_tag(inventory, &feature, &mut io::stdout())
+}
+
+/// Displays the products having the tag.
+fn report_products_by_tag(inventory: &Inventory, tag: &str, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Products tagged: {}", normalize_tag(tag))?;
+    for product in search_by_tag(inventory, tag) {
+        writeln!(out, 
+            "Product: {}; Quantity in stock: {}; Sale price: {}; Tags: {}",
+            product.name,
+            product.quantity,
+            product.sale_price,
+            product.tags.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Returns the forecast of units of the product sold on the
+/// next day, i.e. the simple moving average of the units
+/// sold per day over the last `periods` complete days before
+/// the day of `now`. Returns `None` if there is no sales
+/// history covering all of these days.
+fn forecast_next_period(inventory: &Inventory, name: &str, periods: usize, now: u64) -> Option<f64> {
+    let today = now / datetime::SECS_PER_DAY;
+    let first_day = today.checked_sub(periods as u64)?;
+    let first_sale_day = inventory.sale_txs.iter()
+        .filter(|tx| tx.product_name == name)
+        .map(|tx| tx.timestamp / datetime::SECS_PER_DAY)
+        .min()?;
+    if periods == 0 || first_sale_day > first_day {
+        return None;
+    }
+    let quantity: u64 = inventory.sale_txs.iter()
+        .filter(|tx| tx.product_name == name)
+        .filter(|tx| (first_day..today).contains(&(tx.timestamp / datetime::SECS_PER_DAY)))
+        .map(|tx| tx.quantity)
+        .sum();
+    Some(quantity as f64 / periods as f64)
+}
+
+/// Prompts the user for a number of days and displays the
+/// sales forecast for each product.
+fn forecast_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter number of days to average sales over, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let periods: usize = match feature.parse() {
+        Ok(x) if x > 0 => x,
+        Ok(x) => {
+            ui::print_error(&format!("Invalid number of days: {}", x));
+            return Ok(());
+        }
+        Err(e) => {
+            ui::print_error(&format!("Invalid number of days: {} ({})", feature, e));
+            return Ok(());
+        }
+    };
+    report_forecast(inventory, periods, now(), &mut io::stdout())
+}
+
+/// Displays the projected sales of each product on the next
+/// day based on the moving average of the last `periods`
+/// days.
+fn report_forecast(inventory: &Inventory, periods: usize, now: u64, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales forecast from the last {} days", periods)?;
+    for product in inventory.products.iter() {
+        write!(out, "Product: {}; Projected next-day sales: ", product.name)?;
+        match forecast_next_period(inventory, &product.name, periods, now) {
+            Some(forecast) => writeln!(out, "{:.2}", forecast)?,
+            None => writeln!(out, "Insufficient history")?,
+        }
+    }
+    Ok(())
+}
+
+/// Displays the revenue, cost of goods sold and profit of the
+/// sales grouped by product category, and the totals, which
+/// match the sales report. Products without a category are
+/// grouped as `Uncategorized`. The cost of sales of deleted
+/// products is unknown, so only their revenue is counted.
+fn report_profit_by_category(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    let summary = compute_sales_summary(inventory, Some(&inventory.settings.excluded_products));
+    // category name, revenue, cost of goods sold and profit
+    let mut categories: Vec<(&str, Cents, Cents, Cents)> = Vec::new();
+    for sales in summary.products.iter() {
+        let category = match inventory.get_product(&sales.product_name) {
+            Some(product) if !product.category.is_empty() => &product.category[..],
+            _ => "Uncategorized",
+        };
+        let i = match categories.iter().position(|(c, _, _, _)| *c == category) {
+            Some(i) => i,
+            None => {
+                categories.push((category, Cents::ZERO, Cents::ZERO, Cents::ZERO));
+                categories.len() - 1
+            }
+        };
+        categories[i].1 += sales.total_sale_price;
+        if let Some(profit) = sales.profit {
+            categories[i].2 += sales.total_sale_price - profit;
+            categories[i].3 += profit;
+        }
+    }
+    categories.sort_by(|a, b| a.0.cmp(b.0));
+
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Profit report by category")?;
+    for (category, revenue, cogs, profit) in categories.iter() {
+        writeln!(out, 
+            "Category: {}; Revenue: {}; Cost of goods sold: {}; Profit: {}",
+            category,
+            inventory.money(*revenue),
+            inventory.money(*cogs),
+            inventory.money(*profit))?;
+    }
+    writeln!(out, "Total Revenue: {}", inventory.money(summary.revenue))?;
+    writeln!(out, "Total Profit: {}", inventory.money(summary.profit))?;
+    Ok(())
+}
+
+/// Displays the total spent on purchases, the total received
+/// from sales, and the net cash flow. Unlike the profit, the
+/// cash flow counts the whole cost of the purchased stock,
+/// whether it is sold or not.
+fn report_cash_flow(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    let spent: Cents = inventory.purchase_txs.iter().map(|tx| tx.purchase_price.times(tx.quantity)).sum();
+    let received: Cents = inventory.sale_txs.iter().map(|tx| tx.revenue()).sum();
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Cash flow report")?;
+    writeln!(out, "Spent on purchases: {}", inventory.money(spent))?;
+    writeln!(out, "Received from sales: {}", inventory.money(received))?;
+    writeln!(out, "Net cash flow: {}", inventory.money(received - spent))?;
+    Ok(())
+}
+
+/// Prompts the user for a number of days and displays the
+/// products whose sale price has not changed for that long.
+fn stale_prices_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter minimum number of days since the price change, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let older_than_days: u64 = match feature.parse() {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid number of days: {} ({})", feature, e));
+            return Ok(());
+        }
+    };
+    report_stale_prices(inventory, older_than_days, now(), &mut io::stdout())
+}
+
+/// Displays the products whose sale price was last set at
+/// least `older_than_days` days before `now`.
+fn report_stale_prices(inventory: &Inventory, older_than_days: u64, now: u64, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Prices unchanged for {} or more days", older_than_days)?;
+    for product in inventory.products.iter() {
+        if now.saturating_sub(product.price_updated_at) >= older_than_days.saturating_mul(datetime::SECS_PER_DAY) {
+            writeln!(out, 
+                "Product: {}; Sale price: {}; Price updated: {}",
+                product.name,
+                product.sale_price,
+                inventory.timestamp(product.price_updated_at))?;
+        }
+    }
+    Ok(())
+}
+
+/// Displays the units sold, the revenue and the profit of
+/// each ISO week with sales, in chronological order.
+fn report_sales_by_week(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales by week")?;
+    for ((year, week), (units, revenue, profit)) in sales_by_week(inventory) {
+        writeln!(out, 
+            "Week: {}-W{:02}; Units: {}; Revenue: {}; Profit: {}",
+            year,
+            week,
+            units,
+            inventory.money(revenue),
+            inventory.money(profit))?;
+    }
+    Ok(())
+}
+
+/// Returns the units sold, the revenue and the profit of each
+/// ISO week with sales, keyed by the ISO year and week. Sales
+/// of deleted products add no profit.
+fn sales_by_week(inventory: &Inventory) -> BTreeMap<(i64, u32), (u64, Cents, Cents)> {
+    let mut weeks: BTreeMap<(i64, u32), (u64, Cents, Cents)> = BTreeMap::new();
+    for (tx, profit) in inventory.sale_txs.iter().zip(sale_profits(inventory)) {
+        let week = datetime::iso_week((tx.timestamp / datetime::SECS_PER_DAY) as i64);
+        let entry = weeks.entry(week).or_insert((0, Cents::ZERO, Cents::ZERO));
+        entry.0 += tx.quantity;
+        entry.1 += tx.revenue();
+        entry.2 += profit.unwrap_or(Cents::ZERO);
+    }
+    weeks
+}
+
+/// Displays the total cost of the purchases made in each
+/// calendar month with purchases, in chronological order,
+/// and the grand total.
+fn report_monthly_spend(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Purchase spend by month")?;
+    let months = spend_by_month(inventory);
+    for ((year, month), spend) in months.iter() {
+        writeln!(out, "Month: {}-{:02}; Spend: {}", year, month, inventory.money(*spend))?;
+    }
+    writeln!(out, "Total spend: {}", inventory.money(months.values().copied().sum()))?;
+    Ok(())
+}
+
+/// Returns the total cost of the purchases of each calendar
+/// month with purchases, keyed by the year and month.
+fn spend_by_month(inventory: &Inventory) -> BTreeMap<(i64, u32), Cents> {
+    let mut months: BTreeMap<(i64, u32), Cents> = BTreeMap::new();
+    for tx in inventory.purchase_txs.iter() {
+        let (year, month, _) = datetime::civil_from_days((tx.timestamp / datetime::SECS_PER_DAY) as i64);
+        *months.entry((year, month)).or_insert(Cents::ZERO) += tx.purchase_price.times(tx.quantity);
+    }
+    months
+}
+
+/// Displays the cost of the stock of each product category,
+/// valued at the average purchase price weighted by
+/// quantity like the stock valuation, in alphabetical order,
+/// and the grand total.
+/// Products without a category are grouped as
+/// `Uncategorized`.
+fn report_stock_cost_by_category(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Stock cost report by category")?;
+    let categories = stock_cost_by_category(inventory);
+    for (category, cost) in categories.iter() {
+        writeln!(out, "Category: {}; Stock cost: {}", category, inventory.money(*cost))?;
+    }
+    writeln!(out, "Total stock cost: {}", inventory.money(categories.values().copied().sum()))?;
+    Ok(())
+}
+
+/// Returns the cost of the stock of each product category,
+/// keyed by the category name. Products without purchase
+/// lots have no known cost and are not counted.
+fn stock_cost_by_category(inventory: &Inventory) -> BTreeMap<&str, Cents> {
+    let mut categories: BTreeMap<&str, Cents> = BTreeMap::new();
+    for product in inventory.products.iter() {
+        let cost = match product.weighted_avg_cost() {
+            Some(cost) => cost,
+            None => continue,
+        };
+        let category = if product.category.is_empty() { "Uncategorized" } else { &product.category[..] };
+        *categories.entry(category).or_insert(Cents::ZERO) += cost.times(product.quantity);
+    }
+    categories
+}
+
+/// Cumulative share of the total profit marked in the Pareto
+/// report, in percent.
+const PARETO_THRESHOLD: f64 = 80.0;
+
+/// Returns the products sorted by realized profit from the
+/// highest, each with its share of the total realized profit
+/// and the cumulative share up to and including it, both in
+/// percent. Returns no products if the total is not
+/// positive, as the shares are then meaningless.
+fn pareto_shares(inventory: &Inventory) -> Vec<(&Product, f64, f64)> {
+    let total: Cents = inventory.products.iter().map(|p| p.realized_profit).sum();
+    if total <= Cents::ZERO {
+        return Vec::new();
+    }
+    let mut products: Vec<&Product> = inventory.products.iter().collect();
+    products.sort_by_key(|p| std::cmp::Reverse(p.realized_profit));
+    let mut cumulative = Cents::ZERO;
+    products.into_iter()
+        .map(|p| {
+            cumulative += p.realized_profit;
+            let percent = |x: Cents| x.0 as f64 / total.0 as f64 * 100.0;
+            (p, percent(p.realized_profit), percent(cumulative))
+        })
+        .collect()
+}
+
+/// Displays the products by their contribution to the total
+/// realized profit, with the cumulative share, marking the
+/// product at which the cumulative share reaches 80%.
+fn report_pareto(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Pareto report of profit")?;
+    let shares = pareto_shares(inventory);
+    if shares.is_empty() {
+        writeln!(out, "No profit to analyze")?;
+    }
+    let mut marked = false;
+    for (product, share, cumulative) in shares {
+        write!(out, 
+            "Product: {}; Profit: {}; Share: {:.1}%; Cumulative: {:.1}%",
+            product.name,
+            inventory.money(product.realized_profit),
+            share,
+            cumulative)?;
+        if !marked && cumulative >= PARETO_THRESHOLD {
+            marked = true;
+            write!(out, " <- {:.0}% of profit", PARETO_THRESHOLD)?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// Returns the realized profit as a percentage of the profit
+/// target, which is above 100 once the target is exceeded.
+/// The target must be positive.
+fn target_progress(realized_profit: Cents, target: Cents) -> f64 {
+    realized_profit.0 as f64 / target.0 as f64 * 100.0
+}
+
+/// Displays the realized profit of each product with a
+/// profit target against the target, with the percentage
+/// achieved. Products without a target are skipped.
+fn report_target_progress(inventory: &Inventory, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Progress toward profit targets")?;
+    let mut any = false;
+    for product in inventory.products.iter() {
+        let target = match product.profit_target {
+            Some(t) => t,
+            None => continue,
+        };
+        any = true;
+        writeln!(out, 
+            "Product: {}; Realized profit: {}; Target: {}; Achieved: {:.1}%",
+            product.name,
+            inventory.money(product.realized_profit),
+            inventory.money(target),
+            target_progress(product.realized_profit, target))?;
+    }
+    if !any {
+        writeln!(out, "No profit targets set")?;
+    }
+    Ok(())
+}
+
+/// Returns the number of units that must be sold at
+/// `sale_price` to cover `fixed_cost`, given the cost of
+/// each unit, rounded up to a whole unit. Returns `None` if
+/// the margin per unit is not positive, as the fixed cost is
+/// then never covered.
+fn break_even_units(fixed_cost: Cents, sale_price: Cents, unit_cost: Cents) -> Option<u64> {
+    let margin = sale_price - unit_cost;
+    if margin <= Cents::ZERO {
+        return None;
+    }
+    u64::try_from(fixed_cost.0.max(0)).ok().map(|cost| cost.div_ceil(margin.0 as u64))
+}
+
+/// Prompts the user for a fixed cost and displays the
+/// break-even report.
+fn break_even_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter fixed cost to cover, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let fixed_cost = match Cents::parse(&feature) {
+        Ok(x) => x,
+        Err(e) => {
+            ui::print_error(&format!("Invalid fixed cost: {} ({})", feature, e));
+            return Ok(());
+        }
+    };
+    report_break_even(inventory, fixed_cost, &mut io::stdout())
+}
+
+/// Displays the number of units of each product that must
+/// be sold to cover the fixed cost, with the units costed at
+/// the weighted-average purchase price.
+fn report_break_even(inventory: &Inventory, fixed_cost: Cents, out: &mut dyn Write) -> io::Result<()> {
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Break-even report for a fixed cost of {}", inventory.money(fixed_cost))?;
+    for product in inventory.products.iter() {
+        write!(out, "Product: {}; Sale price: {}; Average cost: ", product.name, product.sale_price)?;
+        let cost = match product.weighted_avg_cost() {
+            Some(cost) => cost,
+            None => {
+                writeln!(out, "Error (Unable to calculate)")?;
+                continue;
+            }
+        };
+        match break_even_units(fixed_cost, product.sale_price, cost) {
+            Some(units) => writeln!(out, "{}; Break-even units: {}", cost, units)?,
+            None => writeln!(out, "{}; Break-even units: None (no positive margin)", cost)?,
+        }
+    }
+    Ok(())
+}
+
+/// Displays the reservations still active at `now` and when
+/// they expire, from the soonest to expire.
+fn report_reservations(inventory: &Inventory, now: u64, out: &mut dyn Write) -> io::Result<()> {
+    let mut reservations: Vec<&Reservation> = inventory.reservations.iter()
+        .filter(|r| r.expires_at > now)
+        .collect();
+    reservations.sort_by_key(|r| r.expires_at);
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Active reservations")?;
+    for reservation in reservations {
+        writeln!(out, 
+            "Product: {}; Quantity: {}; Expires: {}",
+            reservation.product_name,
+            reservation.quantity,
+            inventory.timestamp(reservation.expires_at))?;
+    }
+    Ok(())
+}
+
+/// Prompts the user for a product name and displays the
+/// history of its sale price.
+fn price_history_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter product name to display price history for, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    report_price_history(inventory, &feature, &mut io::stdout())
+}
+
+/// Displays each sale price the product had and when it was
+/// set, with the change from the previous price and a bar
+/// chart of the prices.
+fn report_price_history(inventory: &Inventory, name: &str, out: &mut dyn Write) -> io::Result<()> {
+    let product = match inventory.get_product(name) {
+        Some(p) => p,
+        None => {
+            ui::print_error(&format!("Unavailable product: {}", name));
+            return Ok(());
+        }
+    };
+    let max_price = product.price_history.iter().map(|(_, p)| *p).max().unwrap_or(Cents::ZERO);
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Price history: {}", name)?;
+    let mut previous: Option<Cents> = None;
+    for (timestamp, price) in product.price_history.iter() {
+        let bar = if max_price > Cents::ZERO {
+            (price.0 as f64 / max_price.0 as f64 * HISTOGRAM_WIDTH as f64).round() as usize
+        } else {
+            0
+        };
+        let change = match previous {
+            Some(p) if *price >= p => format!("+{}", *price - p),
+            Some(p) => format!("-{}", p - *price),
+            None => "None".to_string(),
+        };
+        writeln!(out, 
+            "{} {:<width$} Sale price: {}; Change: {}",
+            inventory.timestamp(*timestamp),
+            "#".repeat(bar),
+            price,
+            change,
+            width = HISTOGRAM_WIDTH)?;
+        previous = Some(*price);
+    }
+    Ok(())
+}
+
+/// Width of the longest bar of the sales by hour histogram.
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Prompts the user for an offset from UTC in hours and
+/// displays the sales by hour of day in that time zone.
+fn sales_by_hour_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter offset from UTC in hours, press enter for UTC, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature == "x" {
+        return Ok(());
+    }
+    let offset_hours: i64 = if feature.is_empty() {
+        0
+    } else {
+        match feature.parse() {
+            Ok(x) if (-12..=14).contains(&x) => x,
+            _ => {
+                ui::print_error(&format!("Invalid offset from UTC: {}", feature));
+                return Ok(());
+            }
+        }
+    };
+    report_sales_by_hour(inventory, offset_hours, &mut io::stdout())
+}
+
+/// Returns the number of sales and the revenue for each hour
+/// of day, with the timestamps shifted by `offset_hours`.
+fn sales_by_hour(inventory: &Inventory, offset_hours: i64) -> [(u64, Cents); 24] {
+    let mut buckets = [(0, Cents::ZERO); 24];
+    for tx in inventory.sale_txs.iter() {
+        let local = tx.timestamp as i64 + offset_hours * 3_600;
+        let hour = (local.rem_euclid(datetime::SECS_PER_DAY as i64) / 3_600) as usize;
+        buckets[hour].0 += 1;
+        buckets[hour].1 += tx.revenue();
+    }
+    buckets
+}
+
+/// Displays the number of sales and the revenue for each
+/// hour of day, with a histogram of the revenue.
+fn report_sales_by_hour(inventory: &Inventory, offset_hours: i64, out: &mut dyn Write) -> io::Result<()> {
+    let buckets = sales_by_hour(inventory, offset_hours);
+    let max_revenue = buckets.iter().map(|(_, revenue)| *revenue).max().unwrap_or(Cents::ZERO);
+    writeln!(out, "{}", ui::separator())?;
+    writeln!(out, "Sales by hour of day (UTC{:+})", offset_hours)?;
+    for (hour, (count, revenue)) in buckets.iter().enumerate() {
+        let bar = if max_revenue > Cents::ZERO {
+            (revenue.0 as f64 / max_revenue.0 as f64 * HISTOGRAM_WIDTH as f64).round() as usize
+        } else {
+            0
+        };
+        writeln!(out, 
+            "{:02}:00 {:<width$} Sales: {}; Revenue: {}",
+            hour,
+            "#".repeat(bar),
+            count,
+            inventory.money(*revenue),
+            width = HISTOGRAM_WIDTH)?;
+    }
+    Ok(())
+}
+
+/// Writes the product, sales, purchase and stock valuation
+/// reports to a single text file named after the current
+/// time.
+fn export_reports(inventory: &Inventory) -> io::Result<()> {
+    let path = format!(
+        "reports_{}.txt",
+        inventory.timestamp(now()).replace(' ', "_").replace(':', ""));
+    let mut out = BufWriter::new(File::create(&path)?);
+    report_products(inventory, &mut out)?;
+    report_sales(inventory, Some(&inventory.settings.excluded_products), &mut out)?;
+    report_purchases(inventory, &mut out)?;
+    report_valuation(inventory, &mut out)?;
+    out.flush()?;
+    println!(">>> Reports exported: {}", path);
+    Ok(())
+}
+
+/// Prompts the user for a path and writes the product
+/// catalog to it as JSON.
+fn catalog_export_handler(inventory: &Inventory) -> io::Result<()> {
+    ui::banner();
+    ui::prompt("Enter path to export catalog as JSON, or x to escape:");
+    let feature = ui::read_trimmed_line();
+    if feature.is_empty() || feature == "x" {
+        return Ok(());
+    }
+    std::fs::write(&feature, inventory.catalog_json() + "\n")?;
+    println!(">>> Catalog exported: {}", feature);
+    Ok(())
+}
+
+/// Runs a single command of the JSON API and returns the
+/// response as a JSON line. A command is an object with the
+/// command name in `cmd`:
+/// - `add_product`: Purchases a new product given its
+///   `name`, `description`, `quantity`, `sale_price` and
+///   `purchase_price`
+/// - `sell`: Sells `quantity` units of the product `name`
+/// - `get_product`: Returns the product `name`
+/// - `report_products`: Returns all products
+///
+/// A successful response is `{"ok":true,"data":...}`, and a
+/// failed one is `{"ok":false,"error":"..."}`.
+pub fn handle_json_command(inventory: &mut Inventory, line: &str) -> String {
+    let response = match json::parse(line).and_then(|command| run_json_command(inventory, &command)) {
+        Ok(data) => Value::Object(vec![
+            ("ok".to_string(), Value::Bool(true)),
+            ("data".to_string(), data),
+        ]),
+        Err(e) => Value::Object(vec![
+            ("ok".to_string(), Value::Bool(false)),
+            ("error".to_string(), Value::String(e)),
+        ]),
+    };
+    response.to_string()
+}
+
+/// Dispatches a parsed JSON API command and returns the data
+/// of the response.
+fn run_json_command(inventory: &mut Inventory, command: &Value) -> Result<Value, String> {
+    let cmd = str_field(command, "cmd")?;
+    match &cmd[..] {
+        "add_product" => {
+            let name = str_field(command, "name")?;
+            if inventory.get_product(&name).is_some() {
+                return Err(format!("Product already exists: {}", name));
+            }
+            let description = str_field(command, "description")?;
+            let quantity = u64_field(command, "quantity")?;
+            if quantity == 0 {
+                return Err(format!("Invalid quantity: {}", quantity));
+            }
+            let sale_price = cents_field(command, "sale_price")?;
+            if sale_price < Cents::ZERO {
+                return Err(format!("Invalid sale price: {}", sale_price));
+            }
+            let purchase_price = cents_field(command, "purchase_price")?;
+            if purchase_price < Cents::ZERO {
+                return Err(format!("Invalid purchase price: {}", purchase_price));
+            }
+            let supplier = match command.get("supplier") {
+                Some(_) => str_field(command, "supplier")?,
+                None => String::new(),
+            };
+            let tx = PurchaseTx::new(name.clone(), quantity, purchase_price, supplier.clone());
+            inventory.add_new_product(name, description, quantity, sale_price, purchase_price, supplier);
+            Ok(inventory.record_purchase(tx).to_json())
+        }
+        "sell" => {
+            let name = str_field(command, "name")?;
+            let quantity = u64_field(command, "quantity")?;
+            inventory.sell_product(&name, quantity, None, now()).map(|tx| tx.to_json())
+        }
+        "get_product" => {
+            let name = str_field(command, "name")?;
+            match inventory.get_product(&name) {
+                Some(product) => Ok(product.to_json()),
+                None => Err(format!("Unavailable product: {}", name)),
+            }
+        }
+        "report_products" => Ok(Value::Array(inventory.products.iter().map(|p| p.to_json()).collect())),
+        _ => Err(format!("Unknown command: {}", cmd)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an inventory using the cost method with the
+    /// product purchased at the given quantity, sale price
+    /// and purchase price in cents.
+    fn inventory_with(method: CostMethod, name: &str, quantity: u64, sale_price: i64, purchase_price: i64) -> Inventory {
+        let mut inventory = Inventory::new();
+        inventory.settings.cost_method = method;
+        inventory.add_new_product(name.to_string(), String::new(), quantity, Cents(sale_price), Cents(purchase_price), String::new());
+        inventory
+    }
+
+    /// Returns the cost of the goods sold of the product, i.e.
+    /// its revenue less its realized profit.
+    fn cost_of_goods_sold(inventory: &Inventory, name: &str) -> Cents {
+        let revenue: Cents = inventory.sale_txs.iter()
+            .filter(|tx| tx.product_name == name)
+            .map(|tx| tx.revenue())
+            .sum();
+        revenue - inventory.get_product(name).unwrap().realized_profit
+    }
+
+    #[test]
+    fn lifo_takes_restocked_units_only_for_later_sales() {
+        let mut inventory = inventory_with(CostMethod::Lifo, "Potato", 10, 300, 100);
+        inventory.sell_product("Potato", 5, None, 1).unwrap();
+        inventory.add_same_product("Potato".to_string(), 10, Cents(200), String::new()).unwrap();
+        inventory.sell_product("Potato", 5, None, 2).unwrap();
+        inventory.sell_product("Potato", 10, None, 3).unwrap();
+        // 5 x 1.00, then 5 x 2.00, then 5 x 2.00 and 5 x 1.00
+        assert_eq!(cost_of_goods_sold(&inventory, "Potato"), Cents(3000));
+    }
+
+    #[test]
+    fn lifo_and_fifo_profits_differ_by_lot_order() {
+        let mut profits = Vec::new();
+        for method in [CostMethod::Fifo, CostMethod::Lifo] {
+            let mut inventory = inventory_with(method, "Potato", 10, 300, 100);
+            inventory.add_same_product("Potato".to_string(), 10, Cents(200), String::new()).unwrap();
+            inventory.sell_product("Potato", 5, None, 1).unwrap();
+            profits.push(inventory.get_product("Potato").unwrap().realized_profit);
+        }
+        assert_eq!(profits, vec![Cents(1000), Cents(500)]);
+    }
+
+    #[test]
+    fn cached_profit_matches_recomputation() {
+        for method in [CostMethod::Fifo, CostMethod::Lifo] {
+            let mut inventory = inventory_with(method, "Potato", 10, 300, 100);
+            inventory.settings.allow_backorder = true;
+            inventory.sell_product("Potato", 4, None, 1).unwrap();
+            inventory.add_same_product("Potato".to_string(), 10, Cents(200), String::new()).unwrap();
+            inventory.sell_product("Potato", 7, Some(Cents(250)), 2).unwrap();
+            inventory.sell_product("Potato", 12, None, 3).unwrap();
+            inventory.add_same_product("Potato".to_string(), 5, Cents(150), String::new()).unwrap();
+            inventory.sell_product("Potato", 2, None, 4).unwrap();
+            let cached = inventory.get_product("Potato").unwrap().realized_profit;
+            inventory.recompute_profit();
+            assert_eq!(inventory.get_product("Potato").unwrap().realized_profit, cached, "{:?}", method);
+            let mut loaded = Inventory::from_json(&inventory.to_json()).unwrap();
+            loaded.settings.cost_method = method;
+            loaded.recompute_profit();
+            assert_eq!(loaded.get_product("Potato").unwrap().realized_profit, cached, "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn write_off_takes_unsold_units_in_cost_method_order() {
+        let mut inventory = inventory_with(CostMethod::Lifo, "Milk", 10, 300, 100);
+        inventory.add_same_product("Milk".to_string(), 10, Cents(200), String::new()).unwrap();
+        inventory.sell_product("Milk", 10, None, 1).unwrap();
+        let cost = inventory.write_off("Milk", 5, "Spoiled".to_string()).unwrap();
+        assert_eq!(cost, Cents(500));
+        let lots: Vec<(u64, Cents)> = inventory.get_product("Milk").unwrap().purchase_prices.iter()
+            .map(|(q, p, _, _)| (*q, *p))
+            .collect();
+        assert_eq!(lots, vec![(5, Cents(100)), (10, Cents(200))]);
+        assert_eq!(inventory.get_product("Milk").unwrap().quantity, 5);
+        assert_eq!(inventory.get_product("Milk").unwrap().realized_profit, Cents(1000));
+    }
+
+    #[test]
+    fn transfer_conserves_stock_value_and_is_in_the_ledger() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Flour 25 kg", 10, 3000, 2000);
+        inventory.add_same_product("Flour 25 kg".to_string(), 10, Cents(2500), String::new()).unwrap();
+        inventory.add_new_product("Flour 1 kg".to_string(), String::new(), 0, Cents(200), Cents(100), String::new());
+        let value = |inventory: &Inventory| -> Cents {
+            inventory.products.iter()
+                .flat_map(|p| p.purchase_prices.iter())
+                .map(|(q, p, _, _)| p.times(*q))
+                .sum()
+        };
+        let before = value(&inventory);
+        inventory.transfer_stock("Flour 25 kg", "Flour 1 kg", 5).unwrap();
+        assert_eq!(value(&inventory), before);
+        assert_eq!(inventory.get_product("Flour 25 kg").unwrap().quantity, 15);
+        assert_eq!(inventory.get_product("Flour 1 kg").unwrap().quantity, 5);
+
+        let mut out = Vec::new();
+        report_stock_ledger(&inventory, "Flour 1 kg", &mut out).unwrap();
+        let ledger = String::from_utf8(out).unwrap();
+        assert!(ledger.contains("Transfer in: +5; Balance: 5"), "{}", ledger);
+    }
+
+    #[test]
+    fn validate_backup_accepts_saved_inventory_and_rejects_corrupt_file() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        let content = inventory.to_json().pretty();
+        assert_eq!(validate_backup(&content).unwrap(), "OK: 1 products, 1 sales, 0 purchases");
+
+        assert!(validate_backup(&content[..content.len() / 2]).is_err());
+        assert!(validate_backup(&"[".repeat(200_000)).is_err());
+    }
+
+    /// Builds an inventory of `products` products with
+    /// `sales` sales spread over them, plus sales of a
+    /// deleted product, without replaying the lots.
+    fn sales_dataset(products: u64, sales: u64) -> Inventory {
+        let mut inventory = Inventory::new();
+        for i in 0..products {
+            inventory.add_new_product(format!("Product {}", i), String::new(), 0, Cents(200), Cents(100), String::new());
+            inventory.products.last_mut().unwrap().realized_profit = Cents(i as i64 * 7);
+        }
+        for i in 0..sales {
+            let product_name = if i % 50 == 0 {
+                "Deleted product".to_string()
+            } else {
+                format!("Product {}", (i * 31) % products)
+            };
+            inventory.sale_txs.push(SaleTx {
+                id: i,
+                product_name,
+                quantity: i % 5 + 1,
+                free: 0,
+                sale_price: Cents(100 + (i % 13) as i64),
+                timestamp: i,
+                note: None,
+            });
+        }
+        inventory
+    }
+
+    /// The per-product lookups the sales summary was built
+    /// with before it was made a single pass.
+    fn naive_sales_summary(inventory: &Inventory, excluded: Option<&HashSet<String>>) -> SalesSummary {
+        let mut products: Vec<ProductSales> = Vec::new();
+        for tx in inventory.sale_txs.iter() {
+            if excluded.is_some_and(|e| e.contains(&tx.product_name)) {
+                continue;
+            }
+            let i = match products.iter().position(|p| p.product_name == tx.product_name) {
+                Some(i) => i,
+                None => {
+                    products.push(ProductSales {
+                        product_name: tx.product_name.clone(),
+                        quantity: 0,
+                        total_sale_price: Cents::ZERO,
+                        profit: None,
+                    });
+                    products.len() - 1
+                }
+            };
+            products[i].quantity += tx.quantity;
+            products[i].total_sale_price += tx.revenue();
+        }
+        let mut revenue = Cents::ZERO;
+        let mut profit = Cents::ZERO;
+        for sales in products.iter_mut() {
+            revenue += sales.total_sale_price;
+            if let Some(product) = inventory.products.iter().find(|p| p.name == sales.product_name) {
+                sales.profit = Some(product.realized_profit);
+                profit += product.realized_profit;
+            }
+        }
+        SalesSummary { products, revenue, profit }
+    }
+
+    #[test]
+    fn sales_summary_matches_naive_aggregation() {
+        let inventory = sales_dataset(40, 2_000);
+        let excluded = HashSet::from(["Product 3".to_string()]);
+        for excluded in [None, Some(&excluded)] {
+            let expected = naive_sales_summary(&inventory, excluded);
+            let summary = compute_sales_summary(&inventory, excluded);
+            assert_eq!(summary.revenue, expected.revenue);
+            assert_eq!(summary.profit, expected.profit);
+            assert_eq!(summary.products.len(), expected.products.len());
+            for (a, b) in summary.products.iter().zip(expected.products.iter()) {
+                assert_eq!(a.product_name, b.product_name);
+                assert_eq!(a.quantity, b.quantity);
+                assert_eq!(a.total_sale_price, b.total_sale_price);
+                assert_eq!(a.profit, b.profit);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --ignored --nocapture"]
+    fn sales_summary_benchmark() {
+        let inventory = sales_dataset(5_000, 100_000);
+        let start = std::time::Instant::now();
+        let summary = compute_sales_summary(&inventory, None);
+        let elapsed = start.elapsed();
+        let start = std::time::Instant::now();
+        let expected = naive_sales_summary(&inventory, None);
+        let naive_elapsed = start.elapsed();
+        assert_eq!(summary.revenue, expected.revenue);
+        println!("Sales summary of 100000 sales: {:?} (naive: {:?})", elapsed, naive_elapsed);
+    }
+
+    #[test]
+    fn underpriced_uses_the_cost_of_remaining_stock() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Tea", 10, 250, 100);
+        inventory.add_new_product("Coffee".to_string(), String::new(), 10, Cents(500), Cents(300), String::new());
+        inventory.add_new_product("Sugar".to_string(), String::new(), 0, Cents(100), Cents::ZERO, String::new());
+        // The cheap lot is sold out, the remaining Tea cost 3.00
+        inventory.sell_product("Tea", 10, None, 1).unwrap();
+        inventory.add_same_product("Tea".to_string(), 10, Cents(300), String::new()).unwrap();
+        let tea = inventory.get_product("Tea").unwrap();
+        assert_eq!(tea.weighted_avg_cost(), Some(Cents(200)));
+        assert_eq!(underpriced_gap(&inventory, tea), Some(Cents(50)));
+        assert_eq!(underpriced_gap(&inventory, inventory.get_product("Coffee").unwrap()), None);
+        assert_eq!(underpriced_gap(&inventory, inventory.get_product("Sugar").unwrap()), None);
+
+        let mut out = Vec::new();
+        report_underpriced(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Product: Tea; Sale price: 2.50; Average cost: 3.00; Gap: 0.50"), "{}", report);
+        assert!(!report.contains("Coffee"));
+
+        // Under LIFO the restocked lot was not sold either
+        inventory.settings.cost_method = CostMethod::Lifo;
+        assert_eq!(underpriced_gap(&inventory, inventory.get_product("Tea").unwrap()), Some(Cents(50)));
+    }
+
+    #[test]
+    fn stock_cost_by_category_adds_up_to_the_valuation() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 4, Cents(200), Cents(125), String::new());
+        inventory.add_new_product("Soap".to_string(), String::new(), 3, Cents(300), Cents(200), String::new());
+        for (name, category) in [("Apple", "Fruit"), ("Pear", "Fruit")] {
+            inventory.products.iter_mut().find(|p| p.name == name).unwrap().category = category.to_string();
+        }
+        // 5 Apple left at the average of 10 x 1.00 and 5 x 1.20
+        inventory.sell_product("Apple", 10, None, 1).unwrap();
+        inventory.add_same_product("Apple".to_string(), 5, Cents(120), String::new()).unwrap();
+
+        let categories = stock_cost_by_category(&inventory);
+        assert_eq!(categories.get("Fruit"), Some(&Cents(1035)));
+        assert_eq!(categories.get("Uncategorized"), Some(&Cents(600)));
+
+        let mut out = Vec::new();
+        report_stock_cost_by_category(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Category: Fruit; Stock cost: 10.35"), "{}", report);
+        assert!(report.contains("Category: Uncategorized; Stock cost: 6.00"), "{}", report);
+        assert!(report.contains("Total stock cost: 16.35"), "{}", report);
+        let mut out = Vec::new();
+        report_valuation(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Total value: 16.35"), "{}", report);
+    }
+
+    #[test]
+    fn seed_demo_adds_the_sample_data() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        assert_eq!(inventory.products.len(), DEMO_PRODUCTS.len());
+        assert_eq!(inventory.sale_txs.len(), DEMO_SALES.len());
+        assert_eq!(inventory.purchase_txs.len(), DEMO_PRODUCTS.len() + DEMO_RESTOCKS.len());
+        assert_eq!(inventory.get_product("Potato").unwrap().quantity, 95);
+        assert_eq!(inventory.unsaved_changes, 0);
+        assert_eq!(inventory.session_revenue, Cents::ZERO);
+    }
+
+    #[test]
+    fn import_sales_csv_skips_header_and_overselling_rows() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let path = std::env::temp_dir().join(format!("rusty_store_import_{}.csv", std::process::id()));
+        std::fs::write(&path, "\n  \nproduct,quantity,timestamp\nApple,4,100\nApple,7,200\nApple,6,300\n").unwrap();
+        let rows = parse_sales_csv(&std::fs::read_to_string(&path).unwrap());
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].0, 4);
+
+        let imported = inventory.import_sales_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(imported, Ok(2));
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 0);
+        assert_eq!(inventory.sale_txs.len(), 2);
+    }
+
+    #[test]
+    fn parse_restock_line_accepts_any_whitespace() {
+        assert_eq!(parse_restock_line("Apples  10 1.50"), Ok(("Apples".to_string(), 10, Cents(150))));
+        assert_eq!(parse_restock_line("\tGreen  apples 10\t 1.50 "), Ok(("Green apples".to_string(), 10, Cents(150))));
+        assert!(parse_restock_line("Apples 10").is_err());
+        assert!(parse_restock_line("Apples ten 1.50").unwrap_err().starts_with("Invalid quantity"));
+        assert!(parse_restock_line("Apples 10 1.505").unwrap_err().starts_with("Invalid purchase price"));
+    }
+
+    #[test]
+    fn changing_the_separator_changes_the_report_output() {
+        let inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let render = || {
+            let mut out = Vec::new();
+            report_valuation(&inventory, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let default = render();
+        assert!(default.starts_with(&format!("{}\n", ui::separator())));
+        ui::set_separator("=====");
+        let themed = render();
+        ui::set_separator("");
+        assert!(themed.starts_with("=====\nStock valuation report"), "{}", themed);
+        assert_eq!(themed.replace("=====", &ui::separator()), default);
+    }
+
+    #[test]
+    fn overselling_depends_on_the_backorder_setting() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 5, 150, 100);
+        assert_eq!(
+            inventory.sell_product("Apple", 8, None, 1).unwrap_err(),
+            "Insufficient stock: Apple (requested 8, available 5)");
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 5);
+
+        inventory.settings.allow_backorder = true;
+        inventory.sell_product("Apple", 8, None, 2).unwrap();
+        let apple = inventory.get_product("Apple").unwrap();
+        assert_eq!((apple.quantity, apple.backordered), (0, 3));
+
+        assert_eq!(inventory.sell_product("Apple", 0, None, 3).unwrap_err(), "Invalid quantity: 0");
+        assert_eq!(inventory.sale_txs.len(), 1);
+    }
+
+    #[test]
+    fn stock_ledger_keeps_a_running_balance() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let mut out = Vec::new();
+        report_stock_ledger(&inventory, "Potato", &mut out).unwrap();
+        let ledger = String::from_utf8(out).unwrap();
+        let events: Vec<&str> = ledger.lines().skip(2).map(|l| l.split_once("; ").unwrap().1).collect();
+        assert_eq!(events, [
+            "Purchase: +100; Balance: 100",
+            "Sale: -20; Balance: 80",
+            "Purchase: +50; Balance: 130",
+            "Sale: -35; Balance: 95",
+        ]);
+        assert_eq!(inventory.get_product("Potato").unwrap().quantity, 95);
+    }
+
+    #[test]
+    fn excluded_products_are_left_out_of_the_sales_totals() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let all = compute_sales_summary(&inventory, None);
+        let excluded = HashSet::from(["Honey".to_string()]);
+        let summary = compute_sales_summary(&inventory, Some(&excluded));
+        assert_eq!(all.revenue, Cents(24165));
+        assert_eq!(summary.revenue, Cents(24165 - 4495));
+        assert_eq!(summary.profit, all.profit - Cents(1245));
+        assert!(summary.products.iter().all(|p| p.product_name != "Honey"));
+        assert_eq!(summary.products.len(), all.products.len() - 1);
+    }
+
+    #[test]
+    fn sales_over_lists_sales_meeting_the_threshold() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        inventory.sell_product("Apple", 4, None, 2).unwrap();
+        inventory.sell_product("Apple", 3, Some(Cents(200)), 3).unwrap();
+        let mut out = Vec::new();
+        report_sales_over(&inventory, Cents(600), &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Sales of 6.00 or more"));
+        let totals: Vec<&str> = report.lines().skip(2).map(|l| l.rsplit("; ").next().unwrap()).collect();
+        assert_eq!(totals, ["Total: 6.00", "Total: 6.00"]);
+        assert!(!report.contains("Quantity: 2;"));
+    }
+
+    #[test]
+    fn avg_sale_price_is_weighted_by_quantity() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 10, Cents(200), Cents(100), String::new());
+        inventory.sell_product("Apple", 3, Some(Cents(100)), 1).unwrap();
+        inventory.sell_product("Apple", 1, Some(Cents(200)), 2).unwrap();
+        let mut out = Vec::new();
+        report_avg_sale_price(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Product: Apple; Listed sale price: 1.50; Average sale price: 1.25"), "{}", report);
+        assert!(report.contains("Product: Pear; Listed sale price: 2.00; Average sale price: No sales"), "{}", report);
+    }
+
+    #[test]
+    fn diff_backups_lists_added_removed_and_changed_products() {
+        let mut old = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        old.add_new_product("Pear".to_string(), String::new(), 5, Cents(200), Cents(100), String::new());
+        let mut new = inventory_with(CostMethod::Fifo, "Apple", 7, 150, 100);
+        new.add_new_product("Plum".to_string(), String::new(), 5, Cents(300), Cents(100), String::new());
+        assert_eq!(diff_backups(&old, &new), [
+            "Quantity changed: Apple (10 -> 7)",
+            "Removed: Pear",
+            "Added: Plum",
+        ]);
+        assert!(diff_backups(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn restock_must_be_a_multiple_of_the_moq() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Eggs", 12, 30, 20);
+        inventory.products[0].moq = 2;
+        assert_eq!(
+            inventory.add_same_product("Eggs".to_string(), 5, Cents(20), String::new()).unwrap_err(),
+            "Invalid quantity: 5 (must be a multiple of the minimum order quantity 2)");
+        assert_eq!(inventory.get_product("Eggs").unwrap().quantity, 12);
+        inventory.add_same_product("Eggs".to_string(), 6, Cents(20), String::new()).unwrap();
+        assert_eq!(inventory.get_product("Eggs").unwrap().quantity, 18);
+    }
+
+    #[test]
+    fn lot_profit_attributes_sales_to_each_lot() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 4, None, 1).unwrap();
+        inventory.add_same_product("Apple".to_string(), 10, Cents(120), String::new()).unwrap();
+        inventory.sell_product("Apple", 8, None, 2).unwrap();
+        let mut out = Vec::new();
+        report_lot_profit(&inventory, "Apple", &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        let lots: Vec<&str> = report.lines().skip(2).collect();
+        assert_eq!(lots, [
+            "Lot: 1; Quantity: 10; Purchase price: 1.00; Sold: 10; Average sale price: 1.50; Profit: 5.00",
+            "Lot: 2; Quantity: 10; Purchase price: 1.20; Sold: 2; Average sale price: 1.50; Profit: 0.60",
+        ]);
+        assert_eq!(inventory.get_product("Apple").unwrap().realized_profit, Cents(560));
+    }
+
+    #[test]
+    fn markup_sets_the_price_over_cost() {
+        assert_eq!(parse_markup("markup 25"), Some(Ok(25.0)));
+        assert_eq!(parse_markup("12.50"), None);
+        assert!(parse_markup("markup").unwrap().is_err());
+        assert!(parse_markup("markup -5").unwrap().is_err());
+        assert!(parse_markup("markup 5 10").unwrap().is_err());
+        assert_eq!(price_from_markup(Cents(200), 25.0), Cents(250));
+        assert_eq!(price_from_markup(Cents(333), 50.0), Cents(500));
+        assert_eq!(price_from_markup(Cents(200), 0.0), Cents(200));
+
+        let mut product = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 120).products.remove(0);
+        set_sale_price(&mut product, "markup 50").unwrap();
+        assert_eq!(product.sale_price, Cents(180));
+    }
+
+    #[test]
+    fn reports_can_be_captured_into_a_buffer() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 4, None, 1).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        report_valuation(&inventory, &mut out).unwrap();
+        report_sales(&inventory, None, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.contains("Stock valuation report"));
+        assert!(text.contains("Product: Apple; Quantity: 6; Average cost: 1.00; Value: 6.00"), "{}", text);
+        assert!(text.contains("Sales report"));
+        assert!(text.contains("Product: Apple; Quantity: 4; Total sale price: 6.00; Profit: 2.00"), "{}", text);
+    }
+
+    #[test]
+    fn zero_quantity_lot_is_skipped() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.products[0].purchase_prices.insert(0, (0, Cents(500), String::new(), 0));
+        inventory.sell_product("Apple", 4, None, 1).unwrap();
+        assert_eq!(inventory.get_product("Apple").unwrap().realized_profit, Cents(200));
+        inventory.recompute_profit();
+        assert_eq!(inventory.get_product("Apple").unwrap().realized_profit, Cents(200));
+        assert_eq!(
+            inventory.add_same_product("Apple".to_string(), 0, Cents(100), String::new()).unwrap_err(),
+            "Invalid quantity: 0");
+    }
+
+    #[test]
+    fn sell_all_sells_the_entire_stock_in_one_sale() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 7, 150, 100);
+        sell_all_handler(&mut inventory, &mut "Apple\n".as_bytes());
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 0);
+        assert_eq!(inventory.sale_txs.len(), 1);
+        assert_eq!((inventory.sale_txs[0].quantity, inventory.sale_txs[0].sale_price), (7, Cents(150)));
+
+        sell_all_handler(&mut inventory, &mut "Apple\n".as_bytes());
+        assert_eq!(inventory.sale_txs.len(), 1);
+    }
+
+    #[test]
+    fn recent_activity_is_chronological_and_capped() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let mut out = Vec::new();
+        report_recent_activity(&inventory, 4, &mut out).unwrap();
+        let feed = String::from_utf8(out).unwrap();
+        let events: Vec<(&str, &str)> = feed.lines().skip(2)
+            .map(|l| (l.split_whitespace().next().unwrap(), l.split("Product: ").nth(1).unwrap().split(';').next().unwrap()))
+            .collect();
+        assert_eq!(events, [("SALE", "Onion"), ("BUY", "Apple"), ("SALE", "Apple"), ("SALE", "Honey")]);
+    }
+
+    #[test]
+    fn reorder_by_lead_time_lists_fast_sellers_only() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 30, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 30, Cents(200), Cents(100), String::new());
+        for product in inventory.products.iter_mut() {
+            product.lead_time_days = 10;
+        }
+        inventory.sell_product("Apple", 20, None, 0).unwrap();
+        inventory.sell_product("Pear", 2, None, 0).unwrap();
+        let mut out = Vec::new();
+        report_reorder_by_lead_time(&inventory, 10 * datetime::SECS_PER_DAY, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Product: Apple; Quantity: 10; Daily sales: 2.00; Lead time: 10 days; Sales during lead time: 20.00"), "{}", report);
+        assert!(!report.contains("Pear"));
+    }
+
+    #[test]
+    fn override_price_or_keep_the_listed_one() {
+        assert_eq!(parse_override_price("", Cents(150)), Ok(Cents(150)));
+        assert_eq!(parse_override_price("1.25", Cents(150)), Ok(Cents(125)));
+        assert!(parse_override_price("cheap", Cents(150)).unwrap_err().starts_with("Invalid sale price: cheap"));
+    }
+
+    #[test]
+    fn apply_edit_keeps_the_value_on_empty_input_or_c() {
+        assert_eq!(apply_edit("Fresh", ""), "Fresh");
+        assert_eq!(apply_edit("Fresh", "c"), "Fresh");
+        assert_eq!(apply_edit("Fresh", "Crunchy"), "Crunchy");
+    }
+
+    #[test]
+    fn json_commands_answer_with_ok_or_error() {
+        let mut inventory = Inventory::new();
+        let mut run = |line: &str| json::parse(&handle_json_command(&mut inventory, line)).unwrap();
+        let added = run(r#"{"cmd":"add_product","name":"Apple","description":"Red","quantity":10,"sale_price":1.5,"purchase_price":1}"#);
+        assert_eq!(added.get("ok").and_then(|v| v.as_bool()), Some(true));
+        let sold = run(r#"{"cmd":"sell","name":"Apple","quantity":4}"#);
+        assert_eq!(sold.get("data").and_then(|d| d.get("quantity")).and_then(|v| v.as_u64()), Some(4));
+        let product = run(r#"{"cmd":"get_product","name":"Apple"}"#);
+        assert_eq!(product.get("data").and_then(|d| d.get("quantity")).and_then(|v| v.as_u64()), Some(6));
+
+        let zero = run(r#"{"cmd":"sell","name":"Apple","quantity":0}"#);
+        assert_eq!(zero.get("error").and_then(|v| v.as_str()), Some("Invalid quantity: 0"));
+        let unknown = run(r#"{"cmd":"restock"}"#);
+        assert_eq!(unknown.get("ok").and_then(|v| v.as_bool()), Some(false));
+        assert_eq!(unknown.get("error").and_then(|v| v.as_str()), Some("Unknown command: restock"));
+    }
+
+    #[test]
+    fn one_time_purchases_lists_old_single_purchases() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let report = |min_age_days: u64| {
+            let mut out = Vec::new();
+            report_one_time_purchases(&inventory, min_age_days, DEMO_START + 30 * datetime::SECS_PER_DAY, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let old = report(20);
+        for name in ["Carrot", "Onion", "Honey"] {
+            assert!(old.contains(&format!("Product: {};", name)), "{}", old);
+        }
+        assert!(!old.contains("Potato") && !old.contains("Apple"));
+        assert_eq!(report(40).lines().count(), 2);
+    }
+
+    #[test]
+    fn safe_total_rejects_overflow() {
+        assert_eq!(safe_total(3, Cents(150)), Ok(Cents(450)));
+        assert_eq!(safe_total(u64::MAX, Cents(150)).unwrap_err(), format!("Total overflow: {} units at 1.50", u64::MAX));
+        assert!(safe_total(2, Cents(i64::MAX)).is_err());
+    }
+
+    /// Returns a product to apply the field setters to.
+    fn product_to_edit() -> Product {
+        inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100).products.remove(0)
+    }
+
+    #[test]
+    fn set_description_updates_the_description() {
+        let mut product = product_to_edit();
+        set_description(&mut product, "Golden Delicious").unwrap();
+        assert_eq!(product.description, "Golden Delicious");
+    }
+
+    #[test]
+    fn set_sale_price_updates_the_sale_price() {
+        let mut product = product_to_edit();
+        set_sale_price(&mut product, "1.75").unwrap();
+        assert_eq!(product.sale_price, Cents(175));
+        assert!(set_sale_price(&mut product, "free").is_err());
+        assert_eq!(product.sale_price, Cents(175));
+    }
+
+    #[test]
+    fn set_category_updates_the_category() {
+        let mut product = product_to_edit();
+        set_category(&mut product, "Fruits").unwrap();
+        assert_eq!(product.category, "Fruits");
+        set_category(&mut product, "c").unwrap();
+        assert_eq!(product.category, "Fruits");
+    }
+
+    #[test]
+    fn set_reorder_level_updates_the_reorder_level() {
+        let mut product = product_to_edit();
+        set_reorder_level(&mut product, "25").unwrap();
+        assert_eq!(product.reorder_level, 25);
+        assert!(set_reorder_level(&mut product, "-1").is_err());
+    }
+
+    #[test]
+    fn set_moq_and_lead_time_update_their_fields() {
+        let mut product = product_to_edit();
+        set_moq(&mut product, "6").unwrap();
+        set_lead_time(&mut product, "3").unwrap();
+        assert_eq!((product.moq, product.lead_time_days), (6, 3));
+        assert_eq!(set_moq(&mut product, "0").unwrap_err(), "Invalid minimum order quantity: 0");
+    }
+
+    #[test]
+    fn latest_cost_differs_from_weighted_cost() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_same_product("Apple".to_string(), 10, Cents(120), String::new()).unwrap();
+        assert_eq!(inventory.get_product("Apple").unwrap().weighted_avg_cost(), Some(Cents(110)));
+        let mut out = Vec::new();
+        report_value_at_latest_cost(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Product: Apple; Quantity: 20; Latest cost: 1.20; Value: 24.00"), "{}", report);
+        assert!(report.contains("Total value: 24.00"), "{}", report);
+    }
+
+    #[test]
+    fn sale_ids_are_distinct_and_increasing() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let first = inventory.sell_product("Apple", 1, None, 1).unwrap().id;
+        let second = inventory.sell_product("Apple", 1, None, 1).unwrap().id;
+        let mut reloaded = Inventory::from_json(&inventory.to_json()).unwrap();
+        let third = reloaded.sell_product("Apple", 1, None, 1).unwrap().id;
+        assert!(first < second && second < third, "{} {} {}", first, second, third);
+    }
+
+    #[test]
+    fn top_margin_orders_by_margin_and_omits_excluded_products() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let names = |inventory: &Inventory| {
+            let mut out = Vec::new();
+            report_top_margin(inventory, 3, &mut out).unwrap();
+            String::from_utf8(out).unwrap().lines().skip(2)
+                .map(|l| l.split(';').next().unwrap().trim_start_matches("Product: ").to_string())
+                .collect::<Vec<String>>()
+        };
+        assert_eq!(names(&inventory), ["Onion", "Carrot", "Apple"]);
+        inventory.settings.excluded_products.insert("Carrot".to_string());
+        assert_eq!(names(&inventory), ["Onion", "Apple", "Honey"]);
+    }
+
+    #[test]
+    fn tags_are_not_duplicated_and_filter_products() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 5, Cents(200), Cents(100), String::new());
+        add_tag(&mut inventory.products[0].tags, "Organic");
+        add_tag(&mut inventory.products[0].tags, " organic ");
+        add_tag(&mut inventory.products[0].tags, "");
+        assert_eq!(inventory.products[0].tags, ["organic"]);
+        set_tags(&mut inventory.products[1], "Local").unwrap();
+
+        let names: Vec<&str> = search_by_tag(&inventory, "ORGANIC").iter().map(|p| &p.name[..]).collect();
+        assert_eq!(names, ["Apple"]);
+        let mut out = Vec::new();
+        report_products_by_tag(&inventory, "local", &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Product: Pear;") && !report.contains("Apple"), "{}", report);
+
+        set_tags(&mut inventory.products[0], "-Organic").unwrap();
+        assert!(search_by_tag(&inventory, "organic").is_empty());
+    }
+
+    #[test]
+    fn forecast_is_the_moving_average_of_daily_sales() {
+        let day = datetime::SECS_PER_DAY;
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 100, 150, 100);
+        for (d, quantity) in [(1, 2), (2, 4), (3, 6), (4, 8), (5, 50)] {
+            inventory.sell_product("Apple", quantity, None, d * day + 3600).unwrap();
+        }
+        let now = 5 * day + 7200;
+        assert_eq!(forecast_next_period(&inventory, "Apple", 3, now), Some(6.0));
+        assert_eq!(forecast_next_period(&inventory, "Apple", 4, now), Some(5.0));
+        assert_eq!(forecast_next_period(&inventory, "Apple", 5, now), None);
+        assert_eq!(forecast_next_period(&inventory, "Pear", 3, now), None);
+    }
+
+    #[test]
+    fn purchase_is_committed_only_when_confirmed() {
+        let mut inventory = Inventory::new();
+        for (answer, committed) in [("n\n", false), ("y\n", true)] {
+            if confirm_purchase_from(&mut answer.as_bytes(), "Apple", 10, Cents(100), Cents(1000)) {
+                commit_purchase(&mut inventory, "Apple".to_string(), Some((String::new(), Cents(150))), 10, Cents(100), String::new()).unwrap();
+            }
+            assert_eq!(inventory.get_product("Apple").is_some(), committed);
+            assert_eq!(inventory.purchase_txs.len(), committed as usize);
+        }
+        commit_purchase(&mut inventory, "Apple".to_string(), None, 5, Cents(120), String::new()).unwrap();
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 15);
+        assert_eq!(inventory.purchase_txs.len(), 2);
+    }
+
+    #[test]
+    fn category_profits_add_up_to_the_total() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let mut out = Vec::new();
+        report_profit_by_category(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = report.lines().skip(2).collect();
+        assert_eq!(lines, [
+            "Category: Fruits; Revenue: 87.50; Cost of goods sold: 63.00; Profit: 24.50",
+            "Category: Pantry; Revenue: 44.95; Cost of goods sold: 32.50; Profit: 12.45",
+            "Category: Vegetables; Revenue: 109.20; Cost of goods sold: 83.40; Profit: 25.80",
+            "Total Revenue: 241.65",
+            "Total Profit: 62.75",
+        ]);
+        let summary = compute_sales_summary(&inventory, None);
+        assert_eq!(summary.profit, Cents(2450 + 1245 + 2580));
+    }
+
+    #[test]
+    fn price_outlier_boundaries() {
+        assert!(!is_price_outlier(Cents(100), Cents(1000), 10.0));
+        assert!(is_price_outlier(Cents(100), Cents(1001), 10.0));
+        assert!(!is_price_outlier(Cents(100), Cents(10), 10.0));
+        assert!(is_price_outlier(Cents(100), Cents(9), 10.0));
+        assert!(!is_price_outlier(Cents::ZERO, Cents(100_000), 10.0));
+    }
+
+    #[test]
+    fn cash_flow_reports_spent_received_and_net() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        let mut out = Vec::new();
+        report_cash_flow(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = report.lines().skip(2).collect();
+        assert_eq!(lines, [
+            "Spent on purchases: 494.00",
+            "Received from sales: 241.65",
+            "Net cash flow: -252.35",
+        ]);
+    }
+
+    #[test]
+    fn stale_prices_lists_old_prices_only() {
+        let day = datetime::SECS_PER_DAY;
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 5, Cents(200), Cents(100), String::new());
+        inventory.products[0].record_price_change(10 * day);
+        inventory.products[1].record_price_change(55 * day);
+        let mut out = Vec::new();
+        report_stale_prices(&inventory, 30, 60 * day, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        assert!(report.contains("Product: Apple; Sale price: 1.50;"), "{}", report);
+        assert!(!report.contains("Pear"));
+    }
+
+    #[test]
+    fn product_round_trips_through_json() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_same_product("Apple".to_string(), 5, Cents(120), "Orchard".to_string()).unwrap();
+        let product = &mut inventory.products[0];
+        product.category = "Fruits".to_string();
+        add_tag(&mut product.tags, "organic");
+        product.image_url = Some("https://example.com/apple.png".to_string());
+        product.record_price_change(1_000);
+        let json = product.to_json();
+        let loaded = Product::from_json(&json::parse(&json.pretty()).unwrap()).unwrap();
+        assert_eq!(loaded.to_json().pretty(), json.pretty());
+        assert_eq!(loaded.purchase_prices, product.purchase_prices);
+        assert_eq!((loaded.quantity, loaded.sale_price), (15, Cents(150)));
+    }
+
+    #[test]
+    fn health_score_of_healthy_and_depleted_inventory() {
+        let mut healthy = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        healthy.sell_product("Apple", 5, None, 1).unwrap();
+        assert_eq!(health_components(&healthy), (100.0, 100.0, 50.0));
+        assert_eq!(inventory_health(&healthy), 85.0);
+
+        let mut depleted = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        depleted.sell_product("Apple", 10, Some(Cents(50)), 1).unwrap();
+        assert_eq!(health_components(&depleted), (0.0, 0.0, 100.0));
+        assert_eq!(inventory_health(&depleted), 30.0);
+    }
+
+    #[test]
+    fn sales_by_hour_fills_two_buckets() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 1, None, 9 * 3_600 + 59).unwrap();
+        inventory.sell_product("Apple", 2, None, 9 * 3_600 + 1_800).unwrap();
+        inventory.sell_product("Apple", 3, None, 23 * 3_600).unwrap();
+        let buckets = sales_by_hour(&inventory, 0);
+        assert_eq!(buckets[9], (2, Cents(450)));
+        assert_eq!(buckets[23], (1, Cents(450)));
+        assert_eq!(buckets.iter().map(|(count, _)| count).sum::<u64>(), 3);
+        let shifted = sales_by_hour(&inventory, 2);
+        assert_eq!((shifted[11].0, shifted[1].0), (2, 1));
+    }
+
+    #[test]
+    fn sell_bundle_sells_all_components_or_nothing() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 2, Cents(200), Cents(100), String::new());
+        let bundle = Bundle {
+            name: "Fruit box".to_string(),
+            components: vec![("Apple".to_string(), 2), ("Pear".to_string(), 1)],
+            sale_price: Cents(400),
+        };
+        inventory.add_bundle(bundle.clone()).unwrap();
+        assert_eq!(
+            inventory.sell_bundle(&bundle, 3).unwrap_err(),
+            "Insufficient stock: Pear (requested 3, available 2)");
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 10);
+        assert!(inventory.sale_txs.is_empty());
+
+        inventory.sell_bundle(&bundle, 2).unwrap();
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 6);
+        assert_eq!(inventory.get_product("Pear").unwrap().quantity, 0);
+        let revenue: Cents = inventory.sale_txs.iter().map(|tx| tx.revenue()).sum();
+        assert_eq!(revenue, Cents(800));
+    }
+
+    #[test]
+    fn bundle_split_gives_the_remainder_to_the_last_component() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        for name in ["Pear", "Plum"] {
+            inventory.add_new_product(name.to_string(), String::new(), 10, Cents(150), Cents(100), String::new());
+        }
+        let bundle = Bundle {
+            name: "Fruit trio".to_string(),
+            components: vec![("Apple".to_string(), 1), ("Pear".to_string(), 1), ("Plum".to_string(), 1)],
+            sale_price: Cents(100),
+        };
+        inventory.add_bundle(bundle.clone()).unwrap();
+        inventory.sell_bundle(&bundle, 1).unwrap();
+        let revenues: Vec<Cents> = inventory.sale_txs.iter().map(|tx| tx.revenue()).collect();
+        assert_eq!(revenues, [Cents(33), Cents(33), Cents(34)]);
+
+        inventory.sell_bundle(&bundle, 2).unwrap();
+        let sales: Vec<(&str, u64, Cents)> = inventory.sale_txs[3..].iter()
+            .map(|tx| (&tx.product_name[..], tx.quantity, tx.sale_price))
+            .collect();
+        assert_eq!(sales, [("Apple", 2, Cents(33)), ("Pear", 2, Cents(33)), ("Plum", 2, Cents(34))]);
+        let revenue: Cents = inventory.sale_txs[3..].iter().map(|tx| tx.revenue()).sum();
+        assert_eq!(revenue, Cents(200));
+    }
+
+    #[test]
+    fn loss_sales_flags_only_sales_below_cost() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let loss = inventory.sell_product("Apple", 2, Some(Cents(80)), 1).unwrap();
+        inventory.sell_product("Apple", 3, None, 2).unwrap();
+        let mut out = Vec::new();
+        report_loss_sales(&inventory, &mut out).unwrap();
+        let report = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = report.lines().skip(2).collect();
+        assert_eq!(lines, [format!("ID: {}; Product: Apple; Quantity: 2; Sale price: 0.80; Loss: 0.40", loss.id)]);
+    }
+
+    #[test]
+    fn autosave_fires_when_the_changes_reach_the_interval() {
+        let mut inventory = Inventory::new();
+        inventory.settings.autosave_interval = 3;
+        let mut saves = 0;
+        for i in 0..7 {
+            inventory.add_new_product(format!("Product {}", i), String::new(), 1, Cents(100), Cents(50), String::new());
+            if inventory.autosave(false, |_| { saves += 1; Ok(()) }).unwrap() {
+                assert!(i == 2 || i == 5, "saved after change {}", i + 1);
+            }
+        }
+        assert_eq!(saves, 2);
+        assert_eq!(inventory.unsaved_changes, 1);
+        assert!(inventory.autosave(true, |_| Ok(())).unwrap());
+        assert!(!inventory.autosave(true, |_| Ok(())).unwrap());
+
+        inventory.mark_changed();
+        assert!(inventory.autosave(true, |_| Err("Disk full".to_string())).is_err());
+        assert_eq!(inventory.unsaved_changes, 1);
+
+        // a sale or a purchase counts as a single change
+        inventory.sell_product("Product 0", 1, None, 1).unwrap();
+        assert_eq!(inventory.unsaved_changes, 2);
+        inventory.add_new_product("Product 7".to_string(), String::new(), 5, Cents(100), Cents(50), String::new());
+        inventory.record_purchase(PurchaseTx::new("Product 7".to_string(), 5, Cents(50), String::new()));
+        assert_eq!(inventory.unsaved_changes, 3);
+        inventory.add_same_product("Product 7".to_string(), 5, Cents(50), String::new()).unwrap();
+        inventory.record_purchase(PurchaseTx::new("Product 7".to_string(), 5, Cents(50), String::new()));
+        assert_eq!(inventory.unsaved_changes, 4);
+        inventory.settings.autosave_interval = 0;
+        assert!(!inventory.autosave(true, |_| Ok(())).unwrap());
+    }
+
+    #[test]
+    fn split_lot_keeps_price_supplier_and_order() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_same_product("Apple".to_string(), 5, Cents(120), "Orchard".to_string()).unwrap();
+        inventory.split_lot("Apple", 0, 4).unwrap();
+        let lots: Vec<(u64, Cents)> = inventory.get_product("Apple").unwrap().purchase_prices.iter().map(|(q, p, _, _)| (*q, *p)).collect();
+        assert_eq!(lots, [(4, Cents(100)), (6, Cents(100)), (5, Cents(120))]);
+        assert_eq!(
+            inventory.split_lot("Apple", 2, 5).unwrap_err(),
+            "Invalid quantity: 5 (must be between 1 and 4)");
+        assert!(inventory.split_lot("Apple", 0, 0).is_err());
+        assert_eq!(inventory.split_lot("Apple", 3, 1).unwrap_err(), "Unavailable lot: 4 of Apple");
+
+        inventory.sell_product("Apple", 12, None, 1).unwrap();
+        assert_eq!(inventory.get_product("Apple").unwrap().realized_profit, Cents(10 * 50 + 2 * 30));
+    }
+
+    #[test]
+    fn tsv_has_a_header_and_one_column_per_field() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.products[0].description = "Red\tand\nsweet".to_string();
+        let tsv = report_products_tsv(&inventory);
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], PRODUCTS_TSV_HEADER.join("\t"));
+        let columns: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(columns.len(), PRODUCTS_TSV_HEADER.len());
+        assert_eq!(columns[..2], ["Apple", "Redandsweet"]);
+    }
+
+    #[test]
+    fn restocks_at_the_same_price_merge_unless_disabled() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_same_product("Apple".to_string(), 5, Cents(100), String::new()).unwrap();
+        assert_eq!(inventory.get_product("Apple").unwrap().purchase_prices.len(), 1);
+
+        inventory.settings.merge_lots = false;
+        inventory.add_same_product("Apple".to_string(), 5, Cents(100), String::new()).unwrap();
+        let lots: Vec<u64> = inventory.get_product("Apple").unwrap().purchase_prices.iter().map(|(q, _, _, _)| *q).collect();
+        assert_eq!(lots, [15, 5]);
+        inventory.sell_product("Apple", 16, None, 1).unwrap();
+        assert_eq!(inventory.lots_sold(inventory.get_product("Apple").unwrap()), [15, 1]);
+    }
+
+    #[test]
+    fn notes_are_appended_with_timestamps() {
+        let mut notes = String::new();
+        append_note(&mut notes, " Supplier late ", 1_767_571_200, 0);
+        append_note(&mut notes, "Delivered", 1_767_571_200 + 90 * 60, 0);
+        assert_eq!(notes, "2026-01-05 00:00:00 Supplier late\n2026-01-05 01:30:00 Delivered");
+        append_note(&mut notes, "Shelved", 1_767_571_200, -120);
+        assert!(notes.ends_with("\n2026-01-04 22:00:00 Shelved"), "{}", notes);
+    }
+
+    #[test]
+    fn days_until_stockout_at_the_daily_sales() {
+        assert_eq!(days_until_stockout(30, 2.0), Some(15.0));
+        assert_eq!(days_until_stockout(0, 2.0), Some(0.0));
+        assert_eq!(days_until_stockout(30, 0.0), None);
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 30, 150, 100);
+        inventory.sell_product("Apple", 10, None, 0).unwrap();
+        let daily_sales = avg_daily_sales(&inventory, "Apple", 5 * datetime::SECS_PER_DAY).unwrap();
+        assert_eq!(days_until_stockout(20, daily_sales), Some(10.0));
+    }
+
+    #[test]
+    fn clamp_prices_raises_low_and_lowers_high_prices() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Low", 1, 50, 10);
+        inventory.add_new_product("High".to_string(), String::new(), 1, Cents(900), Cents(10), String::new());
+        inventory.add_new_product("Fine".to_string(), String::new(), 1, Cents(300), Cents(10), String::new());
+        assert_eq!(inventory.clamp_prices(Some(Cents(100)), Some(Cents(500))), 2);
+        let prices: Vec<Cents> = inventory.products.iter().map(|p| p.sale_price).collect();
+        assert_eq!(prices, [Cents(100), Cents(500), Cents(300)]);
+        assert_eq!(inventory.clamp_prices(None, Some(Cents(200))), 2);
+        assert_eq!(inventory.clamp_prices(None, None), 0);
+    }
+
+    #[test]
+    fn products_sort_by_quantity_descending() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 5, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 20, Cents(200), Cents(100), String::new());
+        inventory.add_new_product("Plum".to_string(), String::new(), 5, Cents(300), Cents(100), String::new());
+        inventory.add_new_product("Kiwi".to_string(), String::new(), 9, Cents(300), Cents(100), String::new());
+        let names: Vec<&str> = sorted_products(&inventory, SortKey::Quantity, false).iter().map(|p| &p.name[..]).collect();
+        assert_eq!(names, ["Pear", "Kiwi", "Apple", "Plum"]);
+    }
+
+    #[test]
+    fn repair_quantities_restores_the_quantity_from_the_ledger() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 5, 150, 100);
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        assert!(inventory.repair_quantities().is_empty());
+        inventory.products[0].quantity = 10;
+        assert_eq!(inventory.repair_quantities(), ["Quantity repaired: Apple (10 -> 3)"]);
+        assert_eq!(inventory.products[0].quantity, 3);
+        inventory.products[0].purchase_prices.clear();
+        assert_eq!(inventory.repair_quantities(), ["Manual review needed: Apple (sold 2 from stock, 0 in purchase lots)"]);
+        assert_eq!(inventory.products[0].quantity, 3);
+    }
+
+    #[test]
+    fn sales_are_grouped_by_iso_week() {
+        let day = datetime::SECS_PER_DAY;
+        // 2026-01-04, the Sunday ending the first ISO week of 2026
+        let sunday = 1_767_484_800;
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 1, None, sunday - 6 * day + 10 * 3_600).unwrap();
+        inventory.sell_product("Apple", 2, None, sunday + day).unwrap();
+        inventory.sell_product("Apple", 3, None, sunday + 7 * day + 3_599).unwrap();
+        let weeks: Vec<_> = sales_by_week(&inventory).into_iter().collect();
+        assert_eq!(weeks, [
+            ((2026, 1), (1, Cents(150), Cents(50))),
+            ((2026, 2), (5, Cents(750), Cents(250))),
+        ]);
+    }
+
+    #[test]
+    fn each_renderer_produces_its_format() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 5, 150, 100);
+        inventory.products[0].description = "Red, \"crisp\" | sweet".to_string();
+        let products = &inventory.products;
+        let text = TextRenderer.render_products(products);
+        assert!(text.contains("Product report") && text.contains("Apple"));
+        let csv = CsvRenderer.render_products(products);
+        assert_eq!(csv, "name,description,quantity,sale_price\nApple,\"Red, \"\"crisp\"\" | sweet\",5,1.50\n");
+        let markdown = MarkdownRenderer.render_products(products);
+        assert!(markdown.starts_with("## Product report\n\n| Name |"));
+        assert!(markdown.ends_with("| Apple | Red, \"crisp\" \\| sweet | 5 | 1.50 |\n"));
+    }
+
+    #[test]
+    fn price_range_keeps_products_within_the_bounds() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 1, 250, 100);
+        for (name, price) in [("Carrot", 90), ("Potato", 150), ("Onion", 110), ("Honey", 899)] {
+            inventory.add_new_product(name.to_string(), String::new(), 1, Cents(price), Cents(50), String::new());
+        }
+        let names = |min, max| -> Vec<String> {
+            products_in_price_range(&inventory, Cents(min), Cents(max)).iter().map(|p| p.name.clone()).collect()
+        };
+        assert_eq!(names(90, 250), ["Carrot", "Onion", "Potato", "Apple"]);
+        assert_eq!(names(100, 200), ["Onion", "Potato"]);
+        assert!(names(900, 1000).is_empty());
+    }
+
+    #[test]
+    fn set_quantity_changes_only_the_quantity() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 5, 150, 100);
+        let before = inventory.products[0].clone();
+        inventory.set_quantity("Apple", 42).unwrap();
+        let after = &inventory.products[0];
+        assert_eq!(after.quantity, 42);
+        assert_eq!(format!("{:?}", Product { quantity: before.quantity, ..after.clone() }), format!("{:?}", before));
+        assert!(inventory.purchase_txs.is_empty() && inventory.sale_txs.is_empty());
+        assert_eq!(inventory.set_quantity("Pear", 1), Err("Unavailable product: Pear".to_string()));
+    }
+
+    #[test]
+    fn pareto_shares_accumulate_from_the_largest_profit() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 10, Cents(200), Cents(100), String::new());
+        inventory.add_new_product("Kiwi".to_string(), String::new(), 10, Cents(300), Cents(250), String::new());
+        inventory.sell_product("Apple", 3, None, 1).unwrap();
+        inventory.sell_product("Pear", 1, None, 2).unwrap();
+        inventory.sell_product("Kiwi", 5, None, 3).unwrap();
+        let shares: Vec<(&str, f64, f64)> = pareto_shares(&inventory).into_iter().map(|(p, s, c)| (&p.name[..], s, c)).collect();
+        assert_eq!(shares, [("Kiwi", 50.0, 50.0), ("Apple", 30.0, 80.0), ("Pear", 20.0, 100.0)]);
+    }
+
+    #[test]
+    fn deleted_product_is_restored_with_its_profit() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        inventory.delete_product("Apple");
+        assert!(inventory.get_product("Apple").is_none());
+        assert_eq!(inventory.trash.len(), 1);
+        inventory.restore_product("Apple").unwrap();
+        let product = inventory.get_product("Apple").unwrap();
+        assert_eq!((product.quantity, product.realized_profit), (8, Cents(100)));
+        assert!(inventory.trash.is_empty());
+        assert_eq!(inventory.restore_product("Apple"), Err("Product already exists: Apple".to_string()));
+        inventory.delete_product("Apple");
+        inventory.add_new_product("Apple".to_string(), String::new(), 1, Cents(150), Cents(100), String::new());
+        assert!(inventory.restore_product("Apple").is_err());
+        assert_eq!(inventory.restore_product("Pear"), Err("Unavailable deleted product: Pear".to_string()));
+        let changes = inventory.unsaved_changes;
+        inventory.delete_product("Pear");
+        assert_eq!(inventory.unsaved_changes, changes);
+    }
+
+    #[test]
+    fn metrics_line_counts_products_and_transactions() {
+        let mut inventory = Inventory::new();
+        assert_eq!(metrics_line(&inventory), "Products: 0; Sales: 0; Purchases: 0");
+        inventory.add_new_product("Apple".to_string(), String::new(), 10, Cents(150), Cents(100), String::new());
+        inventory.add_new_product("Pear".to_string(), String::new(), 10, Cents(200), Cents(100), String::new());
+        inventory.record_purchase(PurchaseTx::new("Apple".to_string(), 10, Cents(100), String::new()));
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        inventory.sell_product("Pear", 1, None, 2).unwrap();
+        inventory.sell_product("Apple", 3, None, 3).unwrap();
+        assert_eq!(metrics_line(&inventory), "Products: 2; Sales: 3; Purchases: 1");
+    }
+
+    #[test]
+    fn break_even_needs_a_positive_margin() {
+        assert_eq!(break_even_units(Cents(10000), Cents(250), Cents(180)), Some(143));
+        assert_eq!(break_even_units(Cents(7000), Cents(250), Cents(180)), Some(100));
+        assert_eq!(break_even_units(Cents(10000), Cents(180), Cents(180)), None);
+        assert_eq!(break_even_units(Cents(10000), Cents(150), Cents(180)), None);
+    }
+
+    #[test]
+    fn only_expired_reservations_are_released() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let later = now() + 3_600;
+        inventory.reserve("Apple", 3, later).unwrap();
+        inventory.reserve("Apple", 4, later + 3_600).unwrap();
+        assert_eq!(inventory.unreserved_quantity("Apple", later - 1), Some(3));
+        assert_eq!(inventory.release_expired(later), 1);
+        assert_eq!(inventory.reservations.len(), 1);
+        assert_eq!(inventory.reservations[0].quantity, 4);
+        assert_eq!(inventory.unreserved_quantity("Apple", later), Some(6));
+        assert_eq!(inventory.release_expired(later), 0);
+    }
+
+    #[test]
+    fn two_price_edits_record_two_history_entries() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        for (timestamp, price) in [(10, "1.75"), (20, "1.60")] {
+            let mut product = inventory.get_product("Apple").unwrap().clone();
+            set_sale_price(&mut product, price).unwrap();
+            product.record_price_change(timestamp);
+            inventory.edit_product(product).unwrap();
+        }
+        let history = &inventory.get_product("Apple").unwrap().price_history;
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[1..], [(10, Cents(175)), (20, Cents(160))]);
+    }
+
+    #[test]
+    fn confirmation_is_required_only_above_the_threshold() {
+        assert!(!requires_confirmation(Cents(9999), Cents(10000)));
+        assert!(!requires_confirmation(Cents(10000), Cents(10000)));
+        assert!(requires_confirmation(Cents(10001), Cents(10000)));
+        assert!(!requires_confirmation(Cents(i64::MAX), Cents::ZERO));
+    }
+
+    #[test]
+    fn stock_status_is_ok_low_or_out() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 6, 150, 100);
+        let product = &mut inventory.products[0];
+        product.reorder_level = 5;
+        assert_eq!(stock_status(product), "OK");
+        product.quantity = 5;
+        assert_eq!(stock_status(product), "LOW");
+        product.quantity = 0;
+        assert_eq!(stock_status(product), "OUT");
+    }
+
+    #[test]
+    fn same_price_lots_of_two_suppliers_stay_distinct() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_same_product("Apple".to_string(), 5, Cents(100), "Acme".to_string()).unwrap();
+        inventory.add_same_product("Apple".to_string(), 5, Cents(100), "Orchard".to_string()).unwrap();
+        inventory.add_same_product("Apple".to_string(), 5, Cents(100), "Acme".to_string()).unwrap();
+        let lots: Vec<(u64, &str)> = inventory.products[0].purchase_prices.iter().map(|(q, _, s, _)| (*q, &s[..])).collect();
+        assert_eq!(lots, [(10, ""), (10, "Acme"), (5, "Orchard")]);
+    }
+
+    #[test]
+    fn target_progress_goes_above_100_once_exceeded() {
+        assert_eq!(target_progress(Cents::ZERO, Cents(5000)), 0.0);
+        assert_eq!(target_progress(Cents(1250), Cents(5000)), 25.0);
+        assert_eq!(target_progress(Cents(5000), Cents(5000)), 100.0);
+        assert_eq!(target_progress(Cents(7500), Cents(5000)), 150.0);
+        assert_eq!(target_progress(Cents(-500), Cents(5000)), -10.0);
+    }
+
+    #[test]
+    fn products_below_the_threshold_are_deleted() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Potato", 95, 150, 120);
+        for (name, quantity) in [("Carrot", 65), ("Onion", 48), ("Apple", 50), ("Honey", 7)] {
+            inventory.add_new_product(name.to_string(), String::new(), quantity, Cents(200), Cents(100), String::new());
+        }
+        inventory.sell_product("Apple", 5, None, 1).unwrap();
+        assert_eq!(inventory.delete_below_quantity(50), ["Onion", "Apple", "Honey"]);
+        let names: Vec<&str> = inventory.products.iter().map(|p| &p.name[..]).collect();
+        assert_eq!(names, ["Potato", "Carrot"]);
+        assert_eq!(inventory.trash.len(), 3);
+        assert_eq!(inventory.sale_txs.len(), 1);
+        assert!(inventory.delete_below_quantity(50).is_empty());
+    }
+
+    #[test]
+    fn sales_handler_sells_from_scripted_input() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.settings.large_sale_threshold = Cents(1000);
+        let mut input = "1\nApple\n3\n\n\ngift\n1\nApple\n7\n\n\n\nno\nx\n".as_bytes();
+        sales_handler(&mut inventory, &mut input);
+        assert_eq!(inventory.sale_txs.len(), 1);
+        let tx = &inventory.sale_txs[0];
+        assert_eq!((tx.quantity, tx.sale_price, tx.note.as_deref()), (3, Cents(150), Some("gift")));
+        assert_eq!(inventory.products[0].quantity, 7);
+    }
+
+    #[test]
+    fn url_validation_accepts_web_addresses_only() {
+        for url in ["https://example.com/apple.png", "http://localhost:8080/a?b=c"] {
+            assert!(is_valid_url(url), "{}", url);
+        }
+        for url in ["", "example.com", "ftp://example.com", "https://", "https:///apple.png", "https://example.com/red apple.png"] {
+            assert!(!is_valid_url(url), "{}", url);
+        }
+    }
+
+    #[test]
+    fn unique_match_finds_one_product_or_lists_candidates() {
+        let mut inventory = Inventory::new();
+        for name in ["Potato", "Carrot", "Onion", "Apple", "Honey", "Pineapple"] {
+            inventory.add_new_product(name.to_string(), String::new(), 1, Cents(300), Cents(200), String::new());
+        }
+        assert_eq!(unique_match(&inventory, "Apple").map(|p| &p.name[..]), Ok("Apple"));
+        assert_eq!(unique_match(&inventory, "HON").map(|p| &p.name[..]), Ok("Honey"));
+        assert_eq!(unique_match(&inventory, "o").map(|p| &p.name[..]), Err(vec![
+            "Carrot".to_string(), "Honey".to_string(), "Onion".to_string(), "Potato".to_string(),
+        ]));
+        assert_eq!(unique_match(&inventory, "pple").map(|p| &p.name[..]), Err(vec!["Apple".to_string(), "Pineapple".to_string()]));
+        assert_eq!(unique_match(&inventory, "kiwi").map(|p| &p.name[..]), Err(Vec::new()));
+    }
+
+    #[test]
+    fn profit_per_unit_is_rounded_to_the_cent() {
+        assert_eq!(profit_per_unit(Cents(1000), 4), Some(Cents(250)));
+        assert_eq!(profit_per_unit(Cents(1000), 3), Some(Cents(333)));
+        assert_eq!(profit_per_unit(Cents(-500), 3), Some(Cents(-167)));
+        assert_eq!(profit_per_unit(Cents(1000), 0), None);
+    }
+
+    #[test]
+    fn save_is_retried_while_the_file_is_locked() {
+        let mut calls = 0;
+        let result = save_with_retry(|| {
+            calls += 1;
+            if calls < 3 {
+                Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            } else {
+                Ok(())
+            }
+        }, 5, Duration::ZERO);
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result = save_with_retry(|| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::WouldBlock))
+        }, 3, Duration::ZERO);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(calls, 3);
+
+        let mut calls = 0;
+        let result = save_with_retry(|| {
+            calls += 1;
+            Err(io::Error::from(io::ErrorKind::NotFound))
+        }, 3, Duration::ZERO);
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn session_revenue_accumulates_over_sales() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        assert_eq!(inventory.session_revenue, Cents(300));
+        inventory.sell_product("Apple", 3, Some(Cents(120)), 2).unwrap();
+        assert_eq!(inventory.session_revenue, Cents(660));
+        assert!(inventory.sell_product("Apple", 0, None, 3).is_err());
+        assert_eq!(inventory.session_revenue, Cents(660));
+    }
+
+    #[test]
+    fn spend_is_grouped_by_month() {
+        let day = datetime::SECS_PER_DAY;
+        let start = 1_767_571_200;
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        for (quantity, price, timestamp) in [(10, 100, start + 26 * day + 23 * 3_600), (5, 120, start), (4, 110, start + 27 * day)] {
+            let mut tx = PurchaseTx::new("Apple".to_string(), quantity, Cents(price), String::new());
+            tx.timestamp = timestamp;
+            inventory.record_purchase(tx);
+        }
+        let months: Vec<_> = spend_by_month(&inventory).into_iter().collect();
+        assert_eq!(months, [((2026, 1), Cents(1600)), ((2026, 2), Cents(440))]);
+    }
+
+    #[test]
+    fn sale_note_is_recorded_and_displayed() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let tx = inventory.sell_product("Apple", 2, None, 1).unwrap();
+        inventory.set_sale_note(tx.id, "gift".to_string()).unwrap();
+        assert_eq!(inventory.sale_txs[0].note.as_deref(), Some("gift"));
+        let mut out = Vec::new();
+        display_sales(&inventory, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("Sale price: 1.50; Profit: 1.00; Note: gift\n"), "{}", out);
+    }
+
+    #[test]
+    fn sale_without_note_displays_nothing_extra() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.sell_product("Apple", 2, None, 1).unwrap();
+        assert_eq!(inventory.sale_txs[0].note, None);
+        let mut out = Vec::new();
+        display_sales(&inventory, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.ends_with("Sale price: 1.50; Profit: 1.00\n"), "{}", out);
+    }
+
+    #[test]
+    fn product_line_is_parsed_or_names_the_invalid_field() {
+        assert_eq!(
+            parse_product_line(" Apple | Red, crisp | 10 | 1.50 | 1.00 "),
+            Ok(("Apple".to_string(), "Red, crisp".to_string(), 10, Cents(150), Cents(100))));
+        let error = |line| parse_product_line(line).unwrap_err();
+        assert_eq!(error("Apple | 10 | 1.50 | 1.00"), "Expected 5 fields separated by '|', found 4");
+        assert_eq!(error("Apple | | 10 | 1.50 | 1.00 | x"), "Expected 5 fields separated by '|', found 6");
+        assert_eq!(error(" | | 10 | 1.50 | 1.00"), "Empty name in field 1");
+        assert_eq!(error("Apple | | 0 | 1.50 | 1.00"), "Invalid quantity in field 3: 0");
+        assert!(error("Apple | | ten | 1.50 | 1.00").starts_with("Invalid quantity in field 3: ten ("));
+        assert!(error("Apple | | 10 | 1.505 | 1.00").starts_with("Invalid sale price in field 4: 1.505 ("));
+        assert!(error("Apple | | 10 | 1.50 | -1").starts_with("Invalid purchase price in field 5: -1 ("));
+    }
+
+    #[test]
+    fn price_for_margin_rejects_margins_of_100_or_more() {
+        assert_eq!(price_for_margin(Cents(100), 0.0), Some(Cents(100)));
+        assert_eq!(price_for_margin(Cents(100), 20.0), Some(Cents(125)));
+        assert_eq!(price_for_margin(Cents(100), 33.0), Some(Cents(149)));
+        assert_eq!(price_for_margin(Cents(100), 99.0), Some(Cents(10000)));
+        assert_eq!(price_for_margin(Cents(100), 100.0), None);
+        assert_eq!(price_for_margin(Cents(100), -5.0), None);
+        assert_eq!(price_for_margin(Cents(100), f64::NAN), None);
+    }
+
+    #[test]
+    fn price_spread_flags_prices_far_apart() {
+        assert_eq!(price_spread(&[]), None);
+        assert_eq!(price_spread(&[Cents(150)]), Some((Cents(150), Cents(150), 0.0)));
+        assert_eq!(price_spread(&[Cents(100), Cents(300), Cents(200), Cents(200)]), Some((Cents(100), Cents(300), 5000f64.sqrt())));
+        assert!(!is_inconsistent_price(Cents(100), Cents(200), PRICE_VARIANCE_RATIO));
+        assert!(is_inconsistent_price(Cents(100), Cents(201), PRICE_VARIANCE_RATIO));
+        assert!(is_inconsistent_price(Cents::ZERO, Cents(1), PRICE_VARIANCE_RATIO));
+        assert!(!is_inconsistent_price(Cents::ZERO, Cents::ZERO, PRICE_VARIANCE_RATIO));
+    }
+
+    #[test]
+    fn edit_cannot_drop_the_quantity_below_reserved_units() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.reserve("Apple", 4, now() + 3_600).unwrap();
+        let mut product = inventory.get_product("Apple").unwrap().clone();
+        product.quantity = 3;
+        assert_eq!(inventory.edit_product(product.clone()), Err("Invalid quantity: 3 (below the 4 reserved units)".to_string()));
+        assert_eq!(inventory.products[0].quantity, 10);
+        product.quantity = 4;
+        inventory.edit_product(product).unwrap();
+        assert_eq!(inventory.products[0].quantity, 4);
+    }
+
+    #[test]
+    fn write_off_and_transfer_leave_reserved_units() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        inventory.add_new_product("Pear".to_string(), String::new(), 0, Cents(200), Cents(100), String::new());
+        inventory.reserve("Apple", 4, now() + 3_600).unwrap();
+        assert_eq!(
+            inventory.write_off("Apple", 7, "Spoiled".to_string()),
+            Err("Insufficient stock: Apple (requested 7, available 6)".to_string()));
+        assert_eq!(
+            inventory.transfer_stock("Apple", "Pear", 7),
+            Err("Insufficient stock: Apple (requested 7, available 6)".to_string()));
+        assert_eq!(inventory.products[0].quantity, 10);
+        assert!(inventory.write_off_txs.is_empty() && inventory.transfer_txs.is_empty());
+        inventory.write_off("Apple", 6, "Spoiled".to_string()).unwrap();
+        assert_eq!(inventory.products[0].quantity, 4);
+    }
+
+    #[test]
+    fn purchase_totals_sum_units_and_cost() {
+        assert_eq!(purchase_totals(&[]), (0, Cents::ZERO));
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        assert_eq!(purchase_totals(&inventory.purchase_txs), (382, Cents(49400)));
+    }
+
+    #[test]
+    fn catalog_has_the_public_fields_only() {
+        let mut inventory = Inventory::new();
+        inventory.seed_demo();
+        inventory.set_quantity("Honey", 0).unwrap();
+        let catalog = json::parse(&inventory.catalog_json()).unwrap();
+        let products = catalog.as_array().unwrap();
+        assert_eq!(products.len(), 5);
+        let keys: Vec<&str> = match &products[0] {
+            Value::Object(members) => members.iter().map(|(k, _)| &k[..]).collect(),
+            _ => panic!("not an object"),
+        };
+        assert_eq!(keys, ["name", "description", "category", "tags", "sale_price", "image_url", "in_stock"]);
+        assert_eq!(products[0].get("sale_price").and_then(|v| v.as_f64()), Some(1.5));
+        assert_eq!(products[0].get("in_stock").and_then(|v| v.as_bool()), Some(true));
+        assert_eq!(products[4].get("in_stock").and_then(|v| v.as_bool()), Some(false));
+        let text = inventory.catalog_json();
+        for cost_field in ["purchase_prices", "purchase_price", "supplier", "realized_profit", "price_history"] {
+            assert!(!text.contains(cost_field), "{}", cost_field);
+        }
+    }
+
+    #[test]
+    fn promo_bills_only_the_paid_units_of_complete_groups() {
+        assert_eq!(apply_promo(6, 2, 1), (4, 2));
+        assert_eq!(apply_promo(7, 2, 1), (5, 2));
+        assert_eq!(apply_promo(8, 2, 1), (6, 2));
+        assert_eq!(apply_promo(2, 1, 1), (1, 1));
+        assert_eq!(apply_promo(3, 1, 1), (2, 1));
+        assert_eq!(apply_promo(4, 3, 1), (3, 1));
+        assert_eq!(apply_promo(3, 3, 1), (3, 0));
+        assert_eq!(apply_promo(5, 2, 0), (5, 0));
+    }
+
+    #[test]
+    fn promo_sale_is_one_sale_billed_for_the_paid_units() {
+        let mut inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let tx = inventory.sell_promo("Apple", 7, Cents(150), 2, 1, 1).unwrap();
+        assert_eq!((tx.quantity, tx.free, tx.revenue()), (7, 2, Cents(750)));
+        assert_eq!(inventory.sale_txs.len(), 1);
+        assert_eq!(inventory.products[0].quantity, 3);
+        assert_eq!(inventory.session_revenue, Cents(750));
+        // 7 units cost 7.00 against 7.50 billed
+        assert_eq!(inventory.products[0].realized_profit, Cents(50));
+        let mut out = Vec::new();
+        report_loss_sales(&inventory, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+        let reloaded = Inventory::from_json(&inventory.to_json()).unwrap();
+        assert_eq!(reloaded.sale_txs[0].free, 2);
+    }
+
+    #[test]
+    fn validate_sales_csv_reports_overselling_rows_without_selling() {
+        let inventory = inventory_with(CostMethod::Fifo, "Apple", 10, 150, 100);
+        let path = std::env::temp_dir().join(format!("rusty_store_validate_{}.csv", std::process::id()));
+        std::fs::write(&path, "product,quantity\nApple,4\nApple,7\nPear,1\nApple,6\n").unwrap();
+        let problems = inventory.validate_sales_csv(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(problems, Ok(vec![
+            "Line 3: Insufficient stock: Apple (requested 7, available 6)".to_string(),
+            "Line 4: Unavailable product: Pear".to_string(),
+        ]));
+        assert_eq!(inventory.get_product("Apple").unwrap().quantity, 10);
+        assert!(inventory.sale_txs.is_empty());
     }
 }
@@ -0,0 +1,409 @@
+//! This module implements a minimal JSON parser and
+//! serializer for storing the system data in files.
+use std::fmt;
+
+/// JSON value. Object members keep their insertion order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Returns the member of an object with the given key.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a boolean if it is one.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a number if it is one.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an unsigned integer if it is a
+    /// non-negative whole number.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= u64::MAX as f64 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a string slice if it is a
+    /// string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of the value if it is an array.
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    /// Serializes the value with each array element and
+    /// object member on its own line, indented by two spaces
+    /// per nesting level.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, level: usize) {
+        let indent = "  ".repeat(level + 1);
+        match self {
+            Value::Array(a) if !a.is_empty() => {
+                out.push_str("[\n");
+                for (i, v) in a.iter().enumerate() {
+                    out.push_str(&indent);
+                    v.write_pretty(out, level + 1);
+                    out.push_str(if i + 1 < a.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(level));
+                out.push(']');
+            }
+            Value::Object(members) if !members.is_empty() => {
+                out.push_str("{\n");
+                for (i, (k, v)) in members.iter().enumerate() {
+                    out.push_str(&indent);
+                    write_string(out, k);
+                    out.push_str(": ");
+                    v.write_pretty(out, level + 1);
+                    out.push_str(if i + 1 < members.len() { ",\n" } else { "\n" });
+                }
+                out.push_str(&"  ".repeat(level));
+                out.push('}');
+            }
+            _ => out.push_str(&self.to_string()),
+        }
+    }
+}
+
+/// Appends the string to the output as a quoted JSON
+/// string with special characters escaped.
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Serializes the value compactly. Non-finite numbers,
+/// which JSON cannot represent, are written as `null`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(s) => {
+                let mut out = String::new();
+                write_string(&mut out, s);
+                write!(f, "{}", out)
+            }
+            Value::Array(a) => {
+                write!(f, "[")?;
+                for (i, v) in a.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    let mut key = String::new();
+                    write_string(&mut key, k);
+                    write!(f, "{}:{}", key, v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Maximum nesting depth of arrays and objects, so that a
+/// corrupt document can't overflow the stack of the parser.
+const MAX_DEPTH: usize = 128;
+
+/// Parses a JSON document. Returns an error describing the
+/// position of the first invalid character.
+pub fn parse(s: &str) -> Result<Value, String> {
+    let mut parser = Parser {
+        chars: s.chars().collect(),
+        pos: 0,
+        depth: 0,
+    };
+    let value = parser.parse_value()?;
+    parser.skip_whitespace();
+    if parser.pos < parser.chars.len() {
+        return Err(parser.error("Unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+/// Recursive descent JSON parser over the characters of a
+/// document.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn error(&self, msg: &str) -> String {
+        format!("Invalid JSON: {} at position {}", msg, self.pos)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn expect_literal(&mut self, literal: &str, value: Value) -> Result<Value, String> {
+        for expected in literal.chars() {
+            if self.next() != Some(expected) {
+                self.pos -= 1;
+                return Err(self.error("Unexpected character"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('n') => self.expect_literal("null", Value::Null),
+            Some('t') => self.expect_literal("true", Value::Bool(true)),
+            Some('f') => self.expect_literal("false", Value::Bool(false)),
+            Some('"') => Ok(Value::String(self.parse_string()?)),
+            Some('[') | Some('{') => {
+                if self.depth == MAX_DEPTH {
+                    return Err(self.error("Nesting too deep"));
+                }
+                self.depth += 1;
+                let value = if self.peek() == Some('[') {
+                    self.parse_array()
+                } else {
+                    self.parse_object()
+                };
+                self.depth -= 1;
+                value
+            }
+            Some(c) if c == '-' || c.is_ascii_digit() => self.parse_number(),
+            Some(_) => Err(self.error("Unexpected character")),
+            None => Err(self.error("Unexpected end of input")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_digit() || "+-.eE".contains(c)) {
+                break;
+            }
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        match text.parse() {
+            Ok(n) => Ok(Value::Number(n)),
+            Err(_) => {
+                self.pos = start;
+                Err(self.error("Invalid number"))
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let mut code: u32 = 0;
+        for _ in 0..4 {
+            match self.next().and_then(|c| c.to_digit(16)) {
+                Some(d) => code = code * 16 + d,
+                None => {
+                    self.pos -= 1;
+                    return Err(self.error("Invalid unicode escape"));
+                }
+            }
+        }
+        Ok(code)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.next();
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => {
+                        let mut code = self.parse_hex4()?;
+                        if (0xD800..0xDC00).contains(&code) {
+                            if self.next() != Some('\\') || self.next() != Some('u') {
+                                return Err(self.error("Unpaired surrogate"));
+                            }
+                            let low = self.parse_hex4()?;
+                            if !(0xDC00..0xE000).contains(&low) {
+                                self.pos -= 6;
+                                return Err(self.error("Unpaired surrogate"));
+                            }
+                            code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                        }
+                        match char::from_u32(code) {
+                            Some(c) => s.push(c),
+                            None => return Err(self.error("Invalid unicode escape")),
+                        }
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("Invalid escape"));
+                    }
+                },
+                Some(c) => s.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, String> {
+        self.next();
+        let mut elements = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.next();
+            return Ok(Value::Array(elements));
+        }
+        loop {
+            elements.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(elements)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("Expected ',' or ']'"));
+                }
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<Value, String> {
+        self.next();
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.next();
+            return Ok(Value::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some('"') {
+                return Err(self.error("Expected string key"));
+            }
+            let key = self.parse_string()?;
+            self.skip_whitespace();
+            if self.next() != Some(':') {
+                self.pos -= 1;
+                return Err(self.error("Expected ':'"));
+            }
+            let value = self.parse_value()?;
+            members.push((key, value));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Object(members)),
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error("Expected ',' or '}'"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_nesting_up_to_the_limit() {
+        let doc = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(parse(&doc).is_ok());
+    }
+
+    #[test]
+    fn rejects_deep_nesting_without_overflowing() {
+        let doc = "[".repeat(200_000);
+        assert!(parse(&doc).unwrap_err().contains("Nesting too deep"));
+        let doc = format!("{}{}", "{\"a\":".repeat(MAX_DEPTH + 1), "}".repeat(MAX_DEPTH + 1));
+        assert!(parse(&doc).unwrap_err().contains("Nesting too deep"));
+    }
+
+    #[test]
+    fn decodes_surrogate_pairs_and_rejects_unpaired_ones() {
+        assert_eq!(parse("\"\\ud83c\\udf4e\"").unwrap().as_str(), Some("\u{1F34E}"));
+        assert!(parse("\"\\ud83c\"").unwrap_err().contains("Unpaired surrogate"));
+        assert!(parse("\"\\ud83c\\u0041\"").unwrap_err().contains("Unpaired surrogate"));
+        assert!(parse("\"\\ud83c\\ud83c\"").unwrap_err().contains("Unpaired surrogate"));
+    }
+}
@@ -1,40 +1,289 @@
 //! This program is made for demonstration purposes.
 //! It is a simple Inventory Management System.
 mod auth;
+mod datetime;
 mod inventory;
+mod json;
+mod money;
+mod settings;
+mod ui;
 
-use inventory::Inventory;
+use std::io::BufRead;
+use std::path::Path;
+
+use inventory::{Inventory, AUTOSAVE_FILE};
+use settings::{Settings, SETTINGS_FILE};
+
+/// Submenus with numbered features that can be jumped to
+/// directly from the main menu.
+#[derive(Debug, Clone)]
+enum Submenu {
+    Inventory,
+    Sales,
+    Reporting,
+    Backups,
+}
+
+/// Navigation choice entered in the main menu:
+/// - `Exit`: Exit the program (`x`)
+/// - `Dashboard`: Display the dashboard (`d`)
+/// - `Overview`: Display the inventory overview (`o`)
+/// - `Menu`: Go to a main menu option by its number, e.g.
+///   `2` for Sales Management
+/// - `Feature`: Run a submenu feature directly by the
+///   submenu letter and feature number, e.g. `r2` for the
+///   sales report
+/// - `Repeat`: Repeat the last menu option or feature
+///   (`.`)
+/// - `Invalid`: Anything else
+#[derive(Debug, Clone)]
+enum Nav {
+    Exit,
+    Dashboard,
+    Overview,
+    Menu(String),
+    Feature(Submenu, String),
+    Repeat,
+    Invalid,
+}
+
+/// Parses the main menu input into a navigation choice. The
+/// submenu letters are `i` for Inventory Management, `s` for
+/// Sales Management, `r` for Reporting and `b` for Backups.
+fn parse_nav(input: &str) -> Nav {
+    let input = input.trim();
+    if input == "x" {
+        return Nav::Exit;
+    }
+    if input == "d" {
+        return Nav::Dashboard;
+    }
+    if input == "o" {
+        return Nav::Overview;
+    }
+    if input == "." {
+        return Nav::Repeat;
+    }
+    let is_number = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if is_number(input) {
+        return Nav::Menu(input.to_string());
+    }
+    let mut chars = input.chars();
+    let submenu = match chars.next() {
+        Some('i') => Submenu::Inventory,
+        Some('s') => Submenu::Sales,
+        Some('r') => Submenu::Reporting,
+        Some('b') => Submenu::Backups,
+        _ => return Nav::Invalid,
+    };
+    let feature = chars.as_str();
+    if !is_number(feature) {
+        return Nav::Invalid;
+    }
+    Nav::Feature(submenu, feature.to_string())
+}
+
+/// Returns the navigation choice to run, replacing `Repeat`
+/// with the last one run, and remembers it for the next
+/// `Repeat`. Repeating before anything was run is invalid,
+/// and exiting or invalid input is not remembered.
+fn resolve_repeat(nav: Nav, last: &mut Option<Nav>) -> Nav {
+    let nav = match nav {
+        Nav::Repeat => last.clone().unwrap_or(Nav::Invalid),
+        nav => nav,
+    };
+    if matches!(nav, Nav::Dashboard | Nav::Overview | Nav::Menu(_) | Nav::Feature(_, _)) {
+        *last = Some(nav.clone());
+    }
+    nav
+}
+
+/// Runs the JSON API mode for automation. The first line of
+/// the input must be the password; each following line is
+/// a JSON command, answered by a JSON response line.
+fn run_json_mode(inventory: &mut Inventory) {
+    let mut lines = std::io::stdin().lock().lines();
+    let password = lines.next().and_then(|l| l.ok()).unwrap_or_default();
+    if !auth::verify(password.trim()) {
+        println!("{{\"ok\":false,\"error\":\"Unauthorized\"}}");
+        return;
+    }
+    for line in lines {
+        let line = line.unwrap();
+        if line.trim().is_empty() {
+            continue;
+        }
+        println!("{}", inventory::handle_json_command(inventory, &line));
+        autosave(inventory, false);
+    }
+    autosave(inventory, true);
+}
+
+/// Checks that the backup file at `path` can be loaded and
+/// prints a summary of it, or the error. Returns the exit
+/// code of the program: 0 if the file is valid, or 1
+/// otherwise.
+fn run_validate_mode(path: Option<&str>) -> i32 {
+    let path = match path {
+        Some(p) => p,
+        None => {
+            ui::print_error("Missing file: --validate <file>");
+            return 1;
+        }
+    };
+    let result = std::fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read file: {} ({})", path, e))
+        .and_then(|content| inventory::validate_backup(&content));
+    match result {
+        Ok(summary) => {
+            println!("{}", summary);
+            0
+        }
+        Err(e) => {
+            ui::print_error(&e);
+            1
+        }
+    }
+}
+
+/// Saves the inventory to the autosave file when enough
+/// changes were made since the last autosave, or on `exit`.
+fn autosave(inventory: &mut Inventory, exit: bool) {
+    if let Err(e) = inventory.autosave(exit, |inv| inv.save_to_file(Path::new(AUTOSAVE_FILE))) {
+        ui::print_error(&e);
+    }
+}
+
+/// Returns the exit code for the authentication outcome.
+fn exit_code(authed: bool) -> i32 {
+    if authed {
+        0
+    } else {
+        2
+    }
+}
 
 /// Entry point to the program. Displays the main menu and
-/// prompts the user for an option number to continue. To 
-/// exit the program, enter `x`.
+/// prompts the user for an option number to continue, or a
+/// shortcut to a submenu feature. To exit the program,
+/// enter `x`. With the `--json` argument, runs the JSON API
+/// mode instead. With the `--validate <file>` arguments,
+/// only checks that the backup file can be loaded. With the
+/// `--quiet` argument, the separators and prompts are not
+/// printed. With the `--separator <text>` arguments, the
+/// separators are printed as the given text. With the
+/// `--demo` argument, the inventory starts with sample
+/// products, sales and purchases. If the authentication is
+/// abandoned, exits with code 2.
 fn main() {
-    if !auth::authorize() {
+    if std::env::args().any(|a| a == "--quiet") {
+        ui::set_quiet(true);
+    }
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|a| a == "--separator") {
+        ui::set_separator(args.get(i + 1).map_or("", |a| &a[..]));
+    }
+    if let Some(i) = args.iter().position(|a| a == "--validate") {
+        std::process::exit(run_validate_mode(args.get(i + 1).map(|a| &a[..])));
+    }
+    let demo = std::env::args().any(|a| a == "--demo");
+    if std::env::args().any(|a| a == "--json") {
+        let mut inventory = Inventory::new();
+        *inventory.settings_mut() = Settings::load(Path::new(SETTINGS_FILE));
+        if demo {
+            inventory.seed_demo();
+        }
+        run_json_mode(&mut inventory);
         return;
     }
 
+    let authed = auth::authorize();
+    if !authed {
+        std::process::exit(exit_code(authed));
+    }
+
     let mut inventory = Inventory::new();
+    *inventory.settings_mut() = Settings::load(Path::new(SETTINGS_FILE));
+    if demo {
+        inventory.seed_demo();
+    }
 
-    let mut feature = String::new();
+    let mut feature;
+    let mut last_nav = None;
     loop {
-        println!("<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::");
-        println!("Enter feature number to go to, or x to escape:");
-        println!("Inventory Management  1");
-        println!("Sales Management      2");
-        println!("Purchase Management   3");
-        println!("Reporting             4");
-        feature.clear();
-        std::io::stdin().read_line(&mut feature).unwrap();
-        feature = feature.trim().to_string();
-        if feature == "x" {
-            return;
+        ui::banner();
+        ui::prompt("Enter feature number to go to, shortcut (e.g. r2), or x to escape:");
+        ui::prompt("Inventory Management  1  (i)");
+        ui::prompt("Sales Management      2  (s)");
+        ui::prompt("Purchase Management   3");
+        ui::prompt("Reporting             4  (r)");
+        ui::prompt("Settings              5");
+        ui::prompt("Backups               6  (b)");
+        ui::prompt("Dashboard                (d)");
+        ui::prompt("Overview                 (o)");
+        ui::prompt("Repeat last option       (.)");
+        feature = ui::read_trimmed_line();
+        match resolve_repeat(parse_nav(&feature), &mut last_nav) {
+            Nav::Exit => {
+                autosave(&mut inventory, true);
+                return;
+            }
+            Nav::Dashboard => inventory::dashboard_handler(&inventory),
+            Nav::Overview => inventory::overview_handler(&inventory),
+            Nav::Menu(option) => match &option[..] {
+                "1" => inventory::inventory_handler(&mut inventory),
+                "2" => inventory::sales_handler(&mut inventory, &mut std::io::stdin().lock()),
+                "3" => inventory::purchase_handler(&mut inventory),
+                "4" => inventory::report_handler(&mut inventory),
+                "5" => {
+                    settings::settings_handler(inventory.settings_mut());
+                    inventory.recompute_profit();
+                }
+                "6" => inventory::backup_handler(&mut inventory),
+                _ => (),
+            },
+            Nav::Feature(submenu, feature) => match submenu {
+                Submenu::Inventory => inventory::inventory_feature(&mut inventory, &feature),
+                Submenu::Sales => inventory::sales_feature(&mut inventory, &feature, &mut std::io::stdin().lock()),
+                Submenu::Reporting => inventory::report_feature(&mut inventory, &feature),
+                Submenu::Backups => inventory::backup_feature(&mut inventory, &feature),
+            },
+            Nav::Repeat | Nav::Invalid => (),
         }
-        match &feature[..] {
-            "1" => inventory::inventory_handler(&mut inventory),
-            "2" => inventory::sales_handler(&mut inventory),
-            "3" => inventory::purchase_handler(&mut inventory),
-            "4" => inventory::report_handler(&mut inventory),
-            _ => (),
+        autosave(&mut inventory, false);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_code_tells_abandoned_authentication_apart() {
+        assert_eq!(exit_code(true), 0);
+        assert_eq!(exit_code(false), 2);
+    }
+
+    #[test]
+    fn parse_nav_reads_menu_numbers_and_shortcuts() {
+        assert!(matches!(parse_nav("2"), Nav::Menu(n) if n == "2"));
+        assert!(matches!(parse_nav(" r2 "), Nav::Feature(Submenu::Reporting, f) if f == "2"));
+        assert!(matches!(parse_nav("b10"), Nav::Feature(Submenu::Backups, f) if f == "10"));
+        assert!(matches!(parse_nav("x"), Nav::Exit));
+        for input in ["", "r", "q2", "r2a", "2.5"] {
+            assert!(matches!(parse_nav(input), Nav::Invalid), "{}", input);
         }
     }
+
+    #[test]
+    fn resolve_repeat_runs_the_last_action_again() {
+        let mut last = None;
+        assert!(matches!(resolve_repeat(Nav::Repeat, &mut last), Nav::Invalid));
+        resolve_repeat(parse_nav("r2"), &mut last);
+        resolve_repeat(parse_nav("x"), &mut last);
+        resolve_repeat(parse_nav("q"), &mut last);
+        assert!(matches!(resolve_repeat(Nav::Repeat, &mut last), Nav::Feature(Submenu::Reporting, f) if f == "2"));
+        resolve_repeat(parse_nav("d"), &mut last);
+        assert!(matches!(resolve_repeat(parse_nav("."), &mut last), Nav::Dashboard));
+    }
 }
@@ -0,0 +1,199 @@
+//! This module implements amounts of money stored as a
+//! whole number of cents, so that sums and differences of
+//! prices are exact.
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub, SubAssign};
+
+/// Amount of money in cents. The arithmetic saturates at the
+/// limits of `i64` instead of overflowing.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Cents(pub i64);
+
+impl Cents {
+    /// Zero amount.
+    pub const ZERO: Cents = Cents(0);
+
+    /// Parses a non-negative amount with at most two decimal
+    /// places, e.g. `12.34`, `12.3` or `12`, without going
+    /// through floating point.
+    pub fn parse(input: &str) -> Result<Cents, String> {
+        let invalid = || "not a non-negative amount with at most two decimal places".to_string();
+        let (whole, fraction) = input.split_once('.').unwrap_or((input, ""));
+        let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+        if whole.len() + fraction.len() == 0 || fraction.len() > 2 || !is_digits(whole) || !is_digits(fraction) {
+            return Err(invalid());
+        }
+        let whole: i64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| invalid())? };
+        let fraction: i64 = format!("{:0<2}", fraction).parse().map_err(|_| invalid())?;
+        whole.checked_mul(100)
+            .and_then(|c| c.checked_add(fraction))
+            .map(Cents)
+            .ok_or_else(invalid)
+    }
+
+    /// Returns the amount closest to the number of dollars, or
+    /// `None` if it is not finite or out of range. JSON files
+    /// store amounts as numbers of dollars.
+    pub fn from_dollars(dollars: f64) -> Option<Cents> {
+        let cents = (dollars * 100.0).round();
+        if cents.is_finite() && cents.abs() < i64::MAX as f64 {
+            Some(Cents(cents as i64))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the amount in dollars, for ratios and JSON.
+    pub fn dollars(self) -> f64 {
+        self.0 as f64 / 100.0
+    }
+
+    /// Returns the total price of `qty` units at this price
+    /// per unit, or `None` if it is too large to be
+    /// represented.
+    pub fn checked_times(self, qty: u64) -> Option<Cents> {
+        i64::try_from(qty).ok().and_then(|q| self.0.checked_mul(q)).map(Cents)
+    }
+
+    /// Returns the sum of the amounts, or `None` if it is too
+    /// large to be represented.
+    pub fn checked_add(self, other: Cents) -> Option<Cents> {
+        self.0.checked_add(other.0).map(Cents)
+    }
+
+    /// Returns the total price of `qty` units at this price
+    /// per unit, saturating at the limits of `i64`.
+    pub fn times(self, qty: u64) -> Cents {
+        self.checked_times(qty).unwrap_or(if self.0 < 0 { Cents(i64::MIN) } else { Cents(i64::MAX) })
+    }
+
+    /// Returns the price per unit of `qty` units at this
+    /// total price, rounded to the nearest cent. The quantity
+    /// must not be zero.
+    pub fn per_unit(self, qty: u64) -> Cents {
+        let (total, qty) = (self.0 as i128, qty as i128);
+        let rounded = (2 * total + total.signum() * qty) / (2 * qty);
+        Cents(rounded as i64)
+    }
+
+    /// Formats the amount with two decimal places. If
+    /// `group_thousands` is `true`, the digits of the whole
+    /// part are grouped in thousands with commas (e.g.
+    /// `1,234,567.89`).
+    pub fn format(self, group_thousands: bool) -> String {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        let whole = (abs / 100).to_string();
+        let mut grouped = String::new();
+        for (i, c) in whole.chars().enumerate() {
+            if group_thousands && i > 0 && (whole.len() - i).is_multiple_of(3) {
+                grouped.push(',');
+            }
+            grouped.push(c);
+        }
+        format!("{}{}.{:02}", sign, grouped, abs % 100)
+    }
+}
+
+impl fmt::Display for Cents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(false))
+    }
+}
+
+/// Shows the amount the same way as `Display`, so that
+/// debug output of the structures holding prices stays
+/// readable.
+impl fmt::Debug for Cents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(false))
+    }
+}
+
+impl Add for Cents {
+    type Output = Cents;
+    fn add(self, other: Cents) -> Cents {
+        Cents(self.0.saturating_add(other.0))
+    }
+}
+
+impl Sub for Cents {
+    type Output = Cents;
+    fn sub(self, other: Cents) -> Cents {
+        Cents(self.0.saturating_sub(other.0))
+    }
+}
+
+impl Neg for Cents {
+    type Output = Cents;
+    fn neg(self) -> Cents {
+        Cents(self.0.saturating_neg())
+    }
+}
+
+impl AddAssign for Cents {
+    fn add_assign(&mut self, other: Cents) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Cents {
+    fn sub_assign(&mut self, other: Cents) {
+        *self = *self - other;
+    }
+}
+
+impl Sum for Cents {
+    fn sum<I: Iterator<Item = Cents>>(iter: I) -> Cents {
+        iter.fold(Cents::ZERO, |a, b| a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_amounts_without_floating_point() {
+        assert_eq!(Cents::parse("12.34"), Ok(Cents(1234)));
+        assert_eq!(Cents::parse("12.3"), Ok(Cents(1230)));
+        assert_eq!(Cents::parse("12"), Ok(Cents(1200)));
+        assert_eq!(Cents::parse(".5"), Ok(Cents(50)));
+        for input in ["", ".", "12.345", "-1", "1,5", "abc", "99999999999999999999"] {
+            assert!(Cents::parse(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn sums_are_exact() {
+        let total: Cents = std::iter::repeat_n(Cents::parse("0.10").unwrap(), 1000).sum();
+        assert_eq!(total, Cents(10000));
+        assert_eq!(Cents(1999).times(3) - Cents(1234).times(3), Cents(2295));
+        assert_eq!(Cents::from_dollars(0.1 + 0.2), Some(Cents(30)));
+    }
+
+    #[test]
+    fn rounds_per_unit_to_the_nearest_cent() {
+        assert_eq!(Cents(1000).per_unit(3), Cents(333));
+        assert_eq!(Cents(500).per_unit(3), Cents(167));
+        assert_eq!(Cents(-500).per_unit(3), Cents(-167));
+        assert_eq!(Cents(5).per_unit(2), Cents(3));
+    }
+
+    #[test]
+    fn saturates_instead_of_overflowing() {
+        assert_eq!(Cents(i64::MAX).times(2), Cents(i64::MAX));
+        assert_eq!(Cents(i64::MAX).checked_times(2), None);
+        assert_eq!(Cents(i64::MAX) + Cents(1), Cents(i64::MAX));
+    }
+
+    #[test]
+    fn groups_thousands_with_commas() {
+        assert_eq!(Cents(99999).format(true), "999.99");
+        assert_eq!(Cents(100000).format(true), "1,000.00");
+        assert_eq!(Cents(123456789).format(true), "1,234,567.89");
+        assert_eq!(Cents(-123456789).format(true), "-1,234,567.89");
+        assert_eq!(Cents(123456789).format(false), "1234567.89");
+    }
+}
@@ -0,0 +1,457 @@
+//! This module contains the settings that change the
+//! behavior of the system. The settings are stored in a
+//! JSON file and loaded at startup.
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use crate::json::{self, Value};
+use crate::money::Cents;
+use crate::ui;
+
+/// Name of the file the settings are stored in.
+pub const SETTINGS_FILE: &str = "settings.json";
+
+/// Method of calculating the cost of sold products from
+/// their purchase lots:
+/// - `Fifo`: First in, first out; the oldest lots are sold
+///   first
+/// - `Lifo`: Last in, first out; the newest lots are sold
+///   first
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CostMethod {
+    #[default]
+    Fifo,
+    Lifo,
+}
+
+impl CostMethod {
+    /// Returns the name the cost method is stored under.
+    fn name(&self) -> &'static str {
+        match self {
+            CostMethod::Fifo => "FIFO",
+            CostMethod::Lifo => "LIFO",
+        }
+    }
+
+    /// Returns the cost method stored under the given name.
+    fn from_name(name: &str) -> Result<CostMethod, String> {
+        match name {
+            "FIFO" => Ok(CostMethod::Fifo),
+            "LIFO" => Ok(CostMethod::Lifo),
+            _ => Err(format!("Invalid cost method: {}", name)),
+        }
+    }
+}
+
+/// Format the product report is displayed in:
+/// - `Text`: Plain text with a block of lines per product
+/// - `Csv`: Comma-separated values with a header row
+/// - `Markdown`: Markdown table
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReportFormat {
+    #[default]
+    Text,
+    Csv,
+    Markdown,
+}
+
+impl ReportFormat {
+    /// Returns the name the report format is stored under.
+    fn name(&self) -> &'static str {
+        match self {
+            ReportFormat::Text => "text",
+            ReportFormat::Csv => "csv",
+            ReportFormat::Markdown => "markdown",
+        }
+    }
+
+    /// Returns the report format stored under the given name.
+    fn from_name(name: &str) -> Result<ReportFormat, String> {
+        match name {
+            "text" => Ok(ReportFormat::Text),
+            "csv" => Ok(ReportFormat::Csv),
+            "markdown" => Ok(ReportFormat::Markdown),
+            _ => Err(format!("Invalid report format: {}", name)),
+        }
+    }
+
+    /// Returns the report format following this one, for
+    /// cycling through the formats in the menu.
+    fn next(&self) -> ReportFormat {
+        match self {
+            ReportFormat::Text => ReportFormat::Csv,
+            ReportFormat::Csv => ReportFormat::Markdown,
+            ReportFormat::Markdown => ReportFormat::Text,
+        }
+    }
+}
+
+/// Structure for the system settings:
+/// - `allow_backorder`: Whether selling more than the
+///   quantity in stock is permitted, with the missing
+///   units tracked as backorders (boolean)
+/// - `excluded_products`: Names of products omitted from
+///   the sales report totals (set of strings)
+/// - `cost_method`: Method of calculating the profit from
+///   sales (cost method)
+/// - `group_thousands`: Whether money amounts in reports
+///   are grouped in thousands with commas (boolean)
+/// - `price_outlier_factor`: How many times a new price may
+///   be higher or lower than the existing one before a
+///   warning is shown (floating point number)
+/// - `autosave_interval`: Number of changes to the inventory
+///   after which it is saved to the autosave file, or 0 to
+///   disable autosaving (unsigned integer)
+/// - `merge_lots`: Whether a purchase is added to an existing
+///   lot of the product with the same purchase price instead
+///   of a new lot (boolean)
+/// - `report_format`: Format of the product report (report
+///   format)
+/// - `tz_offset_minutes`: Offset of the local time zone from
+///   UTC in minutes, used when displaying timestamps (signed
+///   integer)
+/// - `large_sale_threshold`: Total above which a sale must be
+///   confirmed by typing `CONFIRM`, or 0 to disable the
+///   confirmation (amount in cents)
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub allow_backorder: bool,
+    pub excluded_products: HashSet<String>,
+    pub cost_method: CostMethod,
+    pub group_thousands: bool,
+    pub price_outlier_factor: f64,
+    pub autosave_interval: u64,
+    pub merge_lots: bool,
+    pub report_format: ReportFormat,
+    pub tz_offset_minutes: i32,
+    pub large_sale_threshold: Cents,
+}
+
+/// Default factor of the price outlier check.
+const DEFAULT_PRICE_OUTLIER_FACTOR: f64 = 10.0;
+/// Default number of changes between autosaves.
+const DEFAULT_AUTOSAVE_INTERVAL: u64 = 1;
+/// Offsets from UTC in minutes of the time zones in use,
+/// from UTC-12 to UTC+14.
+const TZ_OFFSET_RANGE: RangeInclusive<i32> = -720..=840;
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            allow_backorder: false,
+            excluded_products: HashSet::new(),
+            cost_method: CostMethod::default(),
+            group_thousands: false,
+            price_outlier_factor: DEFAULT_PRICE_OUTLIER_FACTOR,
+            autosave_interval: DEFAULT_AUTOSAVE_INTERVAL,
+            merge_lots: true,
+            report_format: ReportFormat::default(),
+            tz_offset_minutes: 0,
+            large_sale_threshold: Cents::ZERO,
+        }
+    }
+}
+
+impl Settings {
+    /// Converts the settings into a JSON object.
+    pub fn to_json(&self) -> Value {
+        let mut excluded: Vec<&String> = self.excluded_products.iter().collect();
+        excluded.sort();
+        Value::Object(vec![
+            ("allow_backorder".to_string(), Value::Bool(self.allow_backorder)),
+            (
+                "excluded_products".to_string(),
+                Value::Array(excluded.into_iter().map(|n| Value::String(n.clone())).collect()),
+            ),
+            ("cost_method".to_string(), Value::String(self.cost_method.name().to_string())),
+            ("group_thousands".to_string(), Value::Bool(self.group_thousands)),
+            ("price_outlier_factor".to_string(), Value::Number(self.price_outlier_factor)),
+            ("autosave_interval".to_string(), Value::Number(self.autosave_interval as f64)),
+            ("merge_lots".to_string(), Value::Bool(self.merge_lots)),
+            ("report_format".to_string(), Value::String(self.report_format.name().to_string())),
+            ("tz_offset_minutes".to_string(), Value::Number(self.tz_offset_minutes as f64)),
+            ("large_sale_threshold".to_string(), Value::Number(self.large_sale_threshold.dollars())),
+        ])
+    }
+
+    /// Reads the settings from a JSON object. Missing fields
+    /// take their default values, while fields of a wrong
+    /// type or with an invalid value are rejected.
+    pub fn from_json(value: &Value) -> Result<Settings, String> {
+        if !matches!(value, Value::Object(_)) {
+            return Err("Invalid settings: expected an object".to_string());
+        }
+        let mut settings = Settings::default();
+        if let Some(v) = value.get("allow_backorder") {
+            settings.allow_backorder = v.as_bool()
+                .ok_or("Invalid setting: allow_backorder")?;
+        }
+        if let Some(v) = value.get("excluded_products") {
+            for name in v.as_array().ok_or("Invalid setting: excluded_products")? {
+                let name = name.as_str().ok_or("Invalid setting: excluded_products")?;
+                settings.excluded_products.insert(name.to_string());
+            }
+        }
+        if let Some(v) = value.get("cost_method") {
+            settings.cost_method = CostMethod::from_name(v.as_str().ok_or("Invalid setting: cost_method")?)?;
+        }
+        if let Some(v) = value.get("group_thousands") {
+            settings.group_thousands = v.as_bool()
+                .ok_or("Invalid setting: group_thousands")?;
+        }
+        if let Some(v) = value.get("price_outlier_factor") {
+            settings.price_outlier_factor = v.as_f64()
+                .and_then(parse_outlier_factor)
+                .ok_or("Invalid setting: price_outlier_factor")?;
+        }
+        if let Some(v) = value.get("autosave_interval") {
+            settings.autosave_interval = v.as_u64()
+                .ok_or("Invalid setting: autosave_interval")?;
+        }
+        if let Some(v) = value.get("merge_lots") {
+            settings.merge_lots = v.as_bool()
+                .ok_or("Invalid setting: merge_lots")?;
+        }
+        if let Some(v) = value.get("report_format") {
+            settings.report_format = ReportFormat::from_name(v.as_str().ok_or("Invalid setting: report_format")?)?;
+        }
+        if let Some(v) = value.get("tz_offset_minutes") {
+            settings.tz_offset_minutes = v.as_f64()
+                .filter(|x| x.fract() == 0.0)
+                .and_then(|x| parse_tz_offset(x as i64))
+                .ok_or("Invalid setting: tz_offset_minutes")?;
+        }
+        if let Some(v) = value.get("large_sale_threshold") {
+            settings.large_sale_threshold = v.as_f64()
+                .filter(|x| *x >= 0.0)
+                .and_then(Cents::from_dollars)
+                .ok_or("Invalid setting: large_sale_threshold")?;
+        }
+        Ok(settings)
+    }
+
+    /// Loads the settings from the file. Returns the default
+    /// settings if the file does not exist, or if it is
+    /// invalid, in which case a warning is printed.
+    pub fn load(path: &Path) -> Settings {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return Settings::default(),
+        };
+        match json::parse(&content).and_then(|v| Settings::from_json(&v)) {
+            Ok(settings) => settings,
+            Err(e) => {
+                ui::print_warning(&format!("Default settings used, unable to load {} ({})", path.display(), e));
+                Settings::default()
+            }
+        }
+    }
+
+    /// Saves the settings to the file.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        std::fs::write(path, self.to_json().pretty() + "\n")
+            .map_err(|e| format!("Unable to save settings: {} ({})", path.display(), e))
+    }
+}
+
+/// Displays the Settings submenu and prompts the user for a
+/// setting number to change. To return to the main menu,
+/// enter `x`.
+pub fn settings_handler(settings: &mut Settings) {
+    let mut feature;
+    loop {
+        ui::banner();
+        ui::prompt("Enter setting number to change, or x to escape:");
+        ui::prompt(&format!("{:<30}1", format!("Allow backorders ({})", settings.allow_backorder)));
+        ui::prompt(&format!("{:<30}2", format!("Excluded products ({})", settings.excluded_products.len())));
+        ui::prompt(&format!("{:<30}3", format!("Cost method ({})", settings.cost_method.name())));
+        ui::prompt(&format!("{:<30}4", format!("Group thousands ({})", settings.group_thousands)));
+        ui::prompt(&format!("{:<30}5", format!("Price outlier factor ({})", settings.price_outlier_factor)));
+        ui::prompt(&format!("{:<30}6", format!("Autosave interval ({})", settings.autosave_interval)));
+        ui::prompt(&format!("{:<30}7", format!("Merge purchase lots ({})", settings.merge_lots)));
+        ui::prompt(&format!("{:<30}8", format!("Report format ({})", settings.report_format.name())));
+        ui::prompt(&format!("{:<30}9", format!("Time zone offset ({})", settings.tz_offset_minutes)));
+        ui::prompt(&format!("{:<30}10", format!("Large sale threshold ({})", settings.large_sale_threshold)));
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        match &feature[..] {
+            "1" => {
+                settings.allow_backorder = !settings.allow_backorder;
+                println!(">>> Allow backorders: {}", settings.allow_backorder);
+            }
+            "2" => exclusion_handler(&mut settings.excluded_products),
+            "3" => {
+                settings.cost_method = match settings.cost_method {
+                    CostMethod::Fifo => CostMethod::Lifo,
+                    CostMethod::Lifo => CostMethod::Fifo,
+                };
+                println!(">>> Cost method: {}", settings.cost_method.name());
+            }
+            "4" => {
+                settings.group_thousands = !settings.group_thousands;
+                println!(">>> Group thousands: {}", settings.group_thousands);
+            }
+            "5" => {
+                ui::banner();
+                ui::prompt("Enter price outlier factor of at least 1, or x to escape:");
+                feature = ui::read_trimmed_line();
+                if feature == "x" {
+                    continue;
+                }
+                match feature.parse::<f64>().ok().and_then(parse_outlier_factor) {
+                    Some(x) => settings.price_outlier_factor = x,
+                    None => {
+                        ui::print_error(&format!("Invalid price outlier factor: {}", feature));
+                        continue;
+                    }
+                }
+                println!(">>> Price outlier factor: {}", settings.price_outlier_factor);
+            }
+            "6" => {
+                ui::banner();
+                ui::prompt("Enter number of changes between autosaves, 0 to disable, or x to escape:");
+                feature = ui::read_trimmed_line();
+                if feature == "x" {
+                    continue;
+                }
+                match feature.parse::<u64>() {
+                    Ok(x) => settings.autosave_interval = x,
+                    Err(e) => {
+                        ui::print_error(&format!("Invalid autosave interval: {} ({})", feature, e));
+                        continue;
+                    }
+                }
+                println!(">>> Autosave interval: {}", settings.autosave_interval);
+            }
+            "7" => {
+                settings.merge_lots = !settings.merge_lots;
+                println!(">>> Merge purchase lots: {}", settings.merge_lots);
+            }
+            "8" => {
+                settings.report_format = settings.report_format.next();
+                println!(">>> Report format: {}", settings.report_format.name());
+            }
+            "9" => {
+                ui::banner();
+                ui::prompt("Enter offset from UTC in minutes, e.g. 120 or -300, or x to escape:");
+                feature = ui::read_trimmed_line();
+                if feature == "x" {
+                    continue;
+                }
+                match feature.parse::<i64>().ok().and_then(parse_tz_offset) {
+                    Some(x) => settings.tz_offset_minutes = x,
+                    None => {
+                        ui::print_error(&format!("Invalid time zone offset: {}", feature));
+                        continue;
+                    }
+                }
+                println!(">>> Time zone offset: {}", settings.tz_offset_minutes);
+            }
+            "10" => {
+                ui::banner();
+                ui::prompt("Enter sale total above which to confirm, 0 to disable, or x to escape:");
+                feature = ui::read_trimmed_line();
+                if feature == "x" {
+                    continue;
+                }
+                match Cents::parse(&feature) {
+                    Ok(x) => settings.large_sale_threshold = x,
+                    Err(e) => {
+                        ui::print_error(&format!("Invalid large sale threshold: {} ({})", feature, e));
+                        continue;
+                    }
+                }
+                println!(">>> Large sale threshold: {}", settings.large_sale_threshold);
+            }
+            _ => continue,
+        }
+        if let Err(e) = settings.save(Path::new(SETTINGS_FILE)) {
+            ui::print_error(&e);
+        }
+    }
+}
+
+/// Returns the price outlier factor if it is a finite number
+/// of at least 1.
+fn parse_outlier_factor(factor: f64) -> Option<f64> {
+    if factor >= 1.0 && factor.is_finite() {
+        Some(factor)
+    } else {
+        None
+    }
+}
+
+/// Returns the time zone offset in minutes if it is within
+/// the offsets in use.
+fn parse_tz_offset(offset: i64) -> Option<i32> {
+    i32::try_from(offset).ok().filter(|x| TZ_OFFSET_RANGE.contains(x))
+}
+
+/// Displays the products excluded from the sales report and
+/// prompts the user for a product name to add to or remove
+/// from the list. To return to the Settings submenu, enter
+/// `x`.
+fn exclusion_handler(excluded: &mut HashSet<String>) {
+    let mut feature;
+    loop {
+        let mut names: Vec<&String> = excluded.iter().collect();
+        names.sort();
+        ui::banner();
+        ui::prompt(&format!("Excluded products: {:?}", names));
+        ui::prompt("Enter product name to exclude or include back, or x to escape:");
+        feature = ui::read_trimmed_line();
+        if feature == "x" {
+            return;
+        }
+        if feature.is_empty() {
+            continue;
+        }
+        if excluded.remove(&feature) {
+            println!(">>> Product included: {}", feature);
+        } else {
+            println!(">>> Product excluded: {}", feature);
+            excluded.insert(feature.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_json() {
+        let settings = Settings {
+            allow_backorder: true,
+            excluded_products: HashSet::from(["Gift card".to_string()]),
+            cost_method: CostMethod::Lifo,
+            report_format: ReportFormat::Markdown,
+            tz_offset_minutes: -300,
+            large_sale_threshold: Cents(50000),
+            ..Settings::default()
+        };
+        let text = settings.to_json().pretty();
+        let loaded = Settings::from_json(&json::parse(&text).unwrap()).unwrap();
+        assert_eq!(loaded.to_json().pretty(), text);
+        assert!(loaded.excluded_products.contains("Gift card"));
+        assert_eq!(loaded.cost_method, CostMethod::Lifo);
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for doc in [
+            "[]",
+            r#"{"allow_backorder": "yes"}"#,
+            r#"{"cost_method": "AVG"}"#,
+            r#"{"price_outlier_factor": 0.5}"#,
+            r#"{"tz_offset_minutes": 900}"#,
+            r#"{"large_sale_threshold": -1}"#,
+        ] {
+            assert!(Settings::from_json(&json::parse(doc).unwrap()).is_err(), "{}", doc);
+        }
+        assert!(Settings::from_json(&json::parse("{}").unwrap()).is_ok());
+    }
+}
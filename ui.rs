@@ -0,0 +1,265 @@
+//! This module contains helpers for printing messages to
+//! the terminal.
+use std::ffi::OsStr;
+use std::io::{BufRead, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::RwLock;
+
+/// ANSI escape code for red text.
+const RED: &str = "\x1b[31m";
+/// ANSI escape code for yellow text.
+const YELLOW: &str = "\x1b[33m";
+/// ANSI escape code to reset the text color.
+const RESET: &str = "\x1b[0m";
+/// Width of the output in characters assumed when the
+/// width of the terminal is unknown, e.g. when the output is
+/// piped to a file.
+pub const DEFAULT_WIDTH: usize = 80;
+/// Maximum number of characters accepted in a line of
+/// input.
+pub const MAX_INPUT_LEN: usize = 1024;
+/// Separator printed before each prompt and at the top of
+/// each report, unless another one is set.
+const DEFAULT_SEPARATOR: &str = "<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::<>::";
+
+/// Separator set for restyling the output, or empty for
+/// `DEFAULT_SEPARATOR`.
+static SEPARATOR: RwLock<String> = RwLock::new(String::new());
+
+/// Whether the separators and prompts are suppressed, so
+/// that only the `>>>` result lines are printed.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables the quiet mode for scripted use.
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Sets the separator printed before each prompt and at the
+/// top of each report. An empty separator restores the
+/// default one.
+pub fn set_separator(separator: &str) {
+    *SEPARATOR.write().unwrap() = separator.to_string();
+}
+
+/// Returns the separator printed before each prompt and at
+/// the top of each report.
+pub fn separator() -> String {
+    let separator = SEPARATOR.read().unwrap();
+    if separator.is_empty() {
+        DEFAULT_SEPARATOR.to_string()
+    } else {
+        separator.clone()
+    }
+}
+
+/// Prints the separator banner unless the quiet mode is
+/// enabled.
+pub fn banner() {
+    write_unless_quiet(&mut std::io::stdout(), &separator());
+}
+
+/// Prints a prompt or menu line unless the quiet mode is
+/// enabled.
+pub fn prompt(msg: &str) {
+    write_unless_quiet(&mut std::io::stdout(), msg);
+}
+
+/// Writes the line to `out` unless the quiet mode is
+/// enabled.
+fn write_unless_quiet(out: &mut dyn Write, line: &str) {
+    if !QUIET.load(Ordering::Relaxed) {
+        let _ = writeln!(out, "{}", line);
+    }
+}
+
+/// Returns `true` if messages should be colored. Color is
+/// disabled when the `NO_COLOR` environment variable is set
+/// to a non-empty value, or when the output is not a
+/// terminal (e.g. piped to a file).
+fn use_color() -> bool {
+    color_allowed(std::env::var_os("NO_COLOR").as_deref(), std::io::stdout().is_terminal())
+}
+
+/// Returns `true` if messages should be colored given the
+/// value of the `NO_COLOR` environment variable and whether
+/// the output is a terminal.
+fn color_allowed(no_color: Option<&OsStr>, is_terminal: bool) -> bool {
+    let no_color = no_color.is_some_and(|v| !v.is_empty());
+    !no_color && is_terminal
+}
+
+/// Returns the width of the terminal in characters, taken
+/// from the `COLUMNS` environment variable or else queried
+/// with `stty`. Returns `DEFAULT_WIDTH` when the output is
+/// not a terminal or the width cannot be detected.
+pub fn terminal_width() -> usize {
+    if !std::io::stdout().is_terminal() {
+        return DEFAULT_WIDTH;
+    }
+    let columns = std::env::var("COLUMNS").ok().or_else(|| {
+        let output = std::process::Command::new("stty")
+            .arg("size")
+            .stdin(std::process::Stdio::inherit())
+            .output()
+            .ok()?;
+        let size = String::from_utf8(output.stdout).ok()?;
+        size.split_whitespace().nth(1).map(|c| c.to_string())
+    });
+    width_or_default(columns.as_deref())
+}
+
+/// Returns the number of columns if it is a positive
+/// number, or `DEFAULT_WIDTH` if it is missing or invalid.
+fn width_or_default(columns: Option<&str>) -> usize {
+    match columns.and_then(|c| c.trim().parse::<usize>().ok()) {
+        Some(width) if width > 0 => width,
+        _ => DEFAULT_WIDTH,
+    }
+}
+
+/// Prefixes the message with `>>>` and wraps it in the
+/// given color if coloring is enabled.
+fn paint(msg: &str, color: &str) -> String {
+    if use_color() {
+        format!("{}>>> {}{}", color, msg, RESET)
+    } else {
+        format!(">>> {}", msg)
+    }
+}
+
+/// Asks the user a yes or no question and returns `true` if
+/// the answer is `y`.
+pub fn confirm(question: &str) -> bool {
+    confirm_from(&mut std::io::stdin().lock(), question)
+}
+
+/// Asks the user a yes or no question, reading the answer
+/// from `input`, and returns `true` if the answer is `y`.
+pub fn confirm_from(input: &mut impl BufRead, question: &str) -> bool {
+    prompt(&format!("{} y/n", question));
+    read_line_from(input) == "y"
+}
+
+/// Renders the items `page_size` at a time, waiting for the
+/// user to press enter after each page but the last. Returns
+/// `true` if all items were rendered, or `false` if the user
+/// entered `x` to stop early.
+pub fn paginate<T, F: Fn(&T)>(items: &[T], page_size: usize, render: F) -> bool {
+    paginate_from(&mut std::io::stdin().lock(), items, page_size, render)
+}
+
+/// Renders the items `page_size` at a time, at least one,
+/// the same way as `paginate`, reading the answer after
+/// each page from `input`.
+pub fn paginate_from<T, F: Fn(&T)>(input: &mut impl BufRead, items: &[T], page_size: usize, render: F) -> bool {
+    let page_size = page_size.max(1);
+    for (i, page) in items.chunks(page_size).enumerate() {
+        for item in page.iter() {
+            render(item);
+        }
+        let shown = i * page_size + page.len();
+        if shown < items.len() {
+            prompt(&format!("{} of {} shown; press enter for more, or x to escape:", shown, items.len()));
+            if read_line_from(input) == "x" {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Reads a line from the standard input with the leading
+/// and trailing whitespace removed. Lines longer than
+/// `MAX_INPUT_LEN` characters are truncated with a warning.
+pub fn read_trimmed_line() -> String {
+    read_line_from(&mut std::io::stdin().lock())
+}
+
+/// Reads a line from `input` the same way as
+/// `read_trimmed_line`, so that handlers can be driven by
+/// other input than the standard input, e.g. a script.
+pub fn read_line_from(input: &mut impl BufRead) -> String {
+    let mut line = String::new();
+    input.read_line(&mut line).unwrap();
+    let (input, truncated) = truncate_input(line.trim());
+    if truncated {
+        print_warning(&format!("Input truncated to {} characters", MAX_INPUT_LEN));
+    }
+    input.to_string()
+}
+
+/// Returns the first `MAX_INPUT_LEN` characters of the input
+/// and whether any characters were cut off.
+fn truncate_input(input: &str) -> (&str, bool) {
+    match input.char_indices().nth(MAX_INPUT_LEN) {
+        Some((end, _)) => (&input[..end], true),
+        None => (input, false),
+    }
+}
+
+/// Prints an error message in red.
+pub fn print_error(msg: &str) {
+    println!("{}", paint(msg, RED));
+}
+
+/// Prints a warning message in yellow.
+pub fn print_warning(msg: &str) {
+    println!("{}", paint(msg, YELLOW));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_color_suppresses_color() {
+        assert!(color_allowed(None, true));
+        assert!(color_allowed(Some(OsStr::new("")), true));
+        assert!(!color_allowed(Some(OsStr::new("1")), true));
+        assert!(!color_allowed(None, false));
+    }
+
+    #[test]
+    fn quiet_mode_writes_nothing() {
+        let mut out = Vec::new();
+        set_quiet(true);
+        write_unless_quiet(&mut out, "Enter product name, or x to escape:");
+        set_quiet(false);
+        assert!(out.is_empty());
+        write_unless_quiet(&mut out, "Enter product name, or x to escape:");
+        assert_eq!(out, b"Enter product name, or x to escape:\n");
+    }
+
+    #[test]
+    fn over_length_input_is_truncated() {
+        let long = "é".repeat(MAX_INPUT_LEN + 10);
+        let line = read_line_from(&mut format!("  {}\nnext\n", long).as_bytes());
+        assert_eq!(line.chars().count(), MAX_INPUT_LEN);
+        assert_eq!(read_line_from(&mut "  Apple  \n".as_bytes()), "Apple");
+        assert_eq!(truncate_input("Apple"), ("Apple", false));
+    }
+
+    #[test]
+    fn width_falls_back_when_detection_fails() {
+        assert_eq!(width_or_default(Some("120")), 120);
+        assert_eq!(width_or_default(Some(" 100\n")), 100);
+        for columns in [None, Some(""), Some("0"), Some("-80"), Some("wide")] {
+            assert_eq!(width_or_default(columns), DEFAULT_WIDTH, "{:?}", columns);
+        }
+    }
+
+    #[test]
+    fn paginate_waits_between_pages_and_stops_on_x() {
+        let rendered = std::cell::RefCell::new(Vec::new());
+        let render = |item: &u32| rendered.borrow_mut().push(*item);
+        assert!(paginate_from(&mut "\n\n".as_bytes(), &[1, 2, 3, 4, 5], 2, render));
+        assert_eq!(rendered.take(), [1, 2, 3, 4, 5]);
+        assert!(!paginate_from(&mut "\nx\n".as_bytes(), &[1, 2, 3, 4, 5], 2, render));
+        assert_eq!(rendered.take(), [1, 2, 3, 4]);
+        assert!(!paginate_from(&mut "x\n".as_bytes(), &[1, 2], 0, render));
+        assert_eq!(rendered.take(), [1]);
+        assert!(paginate_from(&mut "".as_bytes(), &[1, 2], 2, render));
+        assert_eq!(rendered.take(), [1, 2]);
+    }
+}